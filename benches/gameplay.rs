@@ -0,0 +1,129 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use std::{collections::HashMap, hint::black_box};
+use untimed_explosion::{
+    gameplay::{Cable, Game, Player, PlayingPlayer, Room, Rules, Team, WaitingPlayer},
+    roles::Role,
+};
+
+struct Waiting(u32);
+
+impl Player for Waiting {
+    type ID = u32;
+
+    fn id(&self) -> Self::ID {
+        self.0
+    }
+
+    fn name(&self) -> &str {
+        "player"
+    }
+}
+
+impl WaitingPlayer for Waiting {
+    fn ready(&self) -> bool {
+        true
+    }
+}
+
+struct Playing {
+    id: u32,
+    team: Team,
+    cables: Vec<Cable>,
+}
+
+impl Player for Playing {
+    type ID = u32;
+
+    fn id(&self) -> Self::ID {
+        self.id
+    }
+
+    fn name(&self) -> &str {
+        "player"
+    }
+}
+
+impl PlayingPlayer for Playing {
+    fn new<T: WaitingPlayer<ID = Self::ID>>(player: &T, team: Team) -> Self {
+        Self {
+            id: player.id(),
+            team,
+            cables: Vec::new(),
+        }
+    }
+
+    fn connected(&self) -> bool {
+        true
+    }
+
+    fn team(&self) -> Team {
+        self.team
+    }
+
+    fn cables(&self) -> &[Cable] {
+        &self.cables
+    }
+
+    fn set_cables(&mut self, cables: Vec<Cable>) {
+        self.cables = cables;
+    }
+
+    fn cut_cable(&mut self, index: usize) -> Cable {
+        self.cables.remove(index)
+    }
+
+    fn restore_cable(&mut self, index: usize, cable: Cable) {
+        self.cables.insert(index, cable);
+    }
+
+    fn role(&self) -> Option<Role> {
+        None
+    }
+
+    fn set_role(&mut self, _role: Option<Role>) {}
+}
+
+fn new_game(player_count: u32) -> Game<Playing> {
+    let players: HashMap<_, _> = (0..player_count).map(|id| (id, Waiting(id))).collect();
+    let rules = Rules {
+        seed: Some(42),
+        ..Rules::default()
+    };
+    Game::new("BENCH".to_owned(), &players, 0, rules, &HashMap::new())
+}
+
+fn benchmarks(c: &mut Criterion) {
+    c.bench_function("Game::new (8 players)", |b| {
+        b.iter(|| new_game(black_box(8)));
+    });
+
+    c.bench_function("next_round (8 players)", |b| {
+        b.iter_batched(
+            || new_game(8),
+            |mut game| game.next_round(),
+            BatchSize::SmallInput,
+        );
+    });
+
+    c.bench_function("cut (8 players)", |b| {
+        b.iter_batched(
+            || {
+                let game = new_game(8);
+                let cutted = *game
+                    .players()
+                    .keys()
+                    .find(|id| **id != game.wire_cutters)
+                    .unwrap();
+                (game, cutted)
+            },
+            |(mut game, cutted)| {
+                let cutting = game.wire_cutters;
+                game.cut(cutting, cutted, 0).ok()
+            },
+            BatchSize::SmallInput,
+        );
+    });
+}
+
+criterion_group!(benches, benchmarks);
+criterion_main!(benches);
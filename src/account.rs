@@ -0,0 +1,72 @@
+use crate::config::Config;
+use rocket::{
+    get,
+    http::{CookieJar, RawStr},
+    response::Redirect,
+    routes, State,
+};
+
+const SUGGESTIONS: usize = 3;
+
+/// The name belongs to an account the client isn't logged in to
+#[derive(Debug)]
+pub struct Reserved {
+    pub suggestions: Vec<String>,
+}
+
+impl Reserved {
+    /// Back to the menu, which shows the error and the suggestions
+    pub fn redirect(&self) -> Redirect {
+        let suggestions = self.suggestions.join(",");
+        Redirect::to(format!(
+            "/gameMenu.html?error=This%20name%20is%20reserved&suggestions={}",
+            RawStr::new(&suggestions).percent_encode()
+        ))
+    }
+}
+
+fn is_reserved(name: &str, config: &Config) -> bool {
+    config.accounts.iter().any(|x| x.name == name)
+}
+
+/// Whether the client can display this name
+pub fn check_name(name: &str, config: &Config, jar: &CookieJar<'_>) -> Result<(), Reserved> {
+    if !is_reserved(name, config)
+        || jar
+            .get_private("account")
+            .is_some_and(|x| x.value() == name)
+    {
+        return Ok(());
+    }
+
+    let suggestions = (2..)
+        .map(|i| format!("{name}{i}"))
+        .filter(|x| !is_reserved(x, config))
+        .take(SUGGESTIONS)
+        .collect();
+    Err(Reserved { suggestions })
+}
+
+#[get("/account/login?<name>&<key>")]
+fn login(name: &str, key: &str, config: &State<Config>, jar: &CookieJar<'_>) -> Redirect {
+    if !config
+        .accounts
+        .iter()
+        .any(|x| x.name == name && x.key == key)
+    {
+        return Redirect::to("/gameMenu.html?error=Wrong%20name%20or%20key");
+    }
+
+    jar.add_private(("account", name.to_owned()));
+    Redirect::to("/gameMenu.html")
+}
+
+#[get("/account/logout")]
+fn logout(jar: &CookieJar<'_>) -> Redirect {
+    jar.remove_private("account");
+    Redirect::to("/gameMenu.html")
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![login, logout]
+}
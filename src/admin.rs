@@ -0,0 +1,254 @@
+use crate::{
+    channel::Depth,
+    common::{make_event, GlobalState},
+    config::Config,
+    game::{self, PlayerView, Tapped},
+};
+use rocket::{
+    get,
+    http::{ContentType, Status},
+    request::{FromRequest, Outcome, Request},
+    response::stream::{Event, EventStream},
+    routes,
+    serde::{json::Json, Serialize},
+    tokio::select,
+    Shutdown, State,
+};
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Requests authenticated with `Authorization: Bearer <admin_token>`
+pub struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let config = request.rocket().state::<Config>().unwrap();
+        // hide the admin endpoints entirely when they are disabled
+        let Some(token) = &config.admin_token else {
+            return Outcome::Error((Status::NotFound, ()));
+        };
+
+        match request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|x| x.strip_prefix("Bearer "))
+        {
+            Some(x) if x == token => Outcome::Success(Self),
+            _ => Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Lobby,
+    Game,
+    Party,
+}
+
+#[derive(Debug)]
+struct Connection {
+    kind: Kind,
+    room: String,
+    player: u32,
+    connected_at: u64,
+    queue: Depth,
+}
+
+/// Every open event stream
+#[derive(Debug, Default)]
+pub struct Connections {
+    next_id: AtomicU64,
+    open: Arc<Mutex<HashMap<u64, Connection>>>,
+}
+
+/// Removes the connection from the list when dropped
+pub struct Tracked {
+    open: Arc<Mutex<HashMap<u64, Connection>>>,
+    id: u64,
+}
+
+impl Connections {
+    pub fn track(&self, kind: Kind, room: &str, player: u32, queue: Depth) -> Tracked {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.open.lock().unwrap().insert(
+            id,
+            Connection {
+                kind,
+                room: room.to_owned(),
+                player,
+                connected_at: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |x| x.as_secs()),
+                queue,
+            },
+        );
+
+        Tracked {
+            open: Arc::clone(&self.open),
+            id,
+        }
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.open.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct ConnectionInfo {
+    kind: Kind,
+    room: String,
+    player: u32,
+    connected_at: u64,
+    /// Messages sent to the client that the stream didn't forward yet
+    queued: usize,
+    /// Milliseconds the oldest of them has been waiting
+    lag: u128,
+}
+
+#[get("/admin/connections")]
+#[allow(clippy::needless_pass_by_value)]
+fn connections(_admin: Admin, state: &State<GlobalState>) -> Json<Vec<ConnectionInfo>> {
+    let mut list: Vec<_> = state
+        .connections
+        .open
+        .lock()
+        .unwrap()
+        .values()
+        .map(|x| ConnectionInfo {
+            kind: x.kind,
+            room: x.room.clone(),
+            player: x.player,
+            connected_at: x.connected_at,
+            queued: x.queue.get(),
+            lag: x.queue.lag().as_millis(),
+        })
+        .collect();
+    list.sort_by_key(|x| x.connected_at);
+
+    Json(list)
+}
+
+/// Every message of the game, including the ones only sent to one player. The stream ends with the
+/// game.
+#[get("/admin/games/<code>/events")]
+#[allow(clippy::needless_pass_by_value)]
+fn game_events(
+    _admin: Admin,
+    code: &str,
+    state: &State<GlobalState>,
+    mut end: Shutdown,
+) -> Option<EventStream![]> {
+    let game = state.games.get(&code.to_uppercase())?;
+    let mut receiver = game::watch(&game);
+    // the watchers must not keep the game alive
+    drop(game);
+
+    Some(
+        EventStream! {
+            while let Some(msg) = select! {
+                msg = receiver.recv(Tapped::resync) => msg,
+                () = &mut end => None,
+            } {
+                yield make_event!(msg);
+            }
+        }
+        .heartbeat(Duration::from_secs(5)),
+    )
+}
+
+/// Exactly what a player receives, to check that nothing hidden leaks to them
+#[get("/admin/games/<code>/players/<player>/events")]
+#[allow(clippy::needless_pass_by_value)]
+fn player_events(
+    _admin: Admin,
+    code: &str,
+    player: u32,
+    state: &State<GlobalState>,
+    config: &State<Config>,
+    mut end: Shutdown,
+) -> Option<EventStream![]> {
+    let game = state.games.get(&code.to_uppercase())?;
+    let mut view = PlayerView::new(&game, player, config)?;
+    drop(game);
+
+    Some(
+        EventStream! {
+            while let Some(event) = select! {
+                event = view.next() => event,
+                () = &mut end => None,
+            } {
+                yield event;
+            }
+        }
+        .heartbeat(Duration::from_secs(5)),
+    )
+}
+
+/// Start times of the logged games played with this code
+fn logs(config: &Config, code: &str) -> Vec<u64> {
+    let prefix = format!("{}-", code.to_uppercase());
+    let Some(dir) = &config.game_log_dir else {
+        return Vec::new();
+    };
+    let mut logs: Vec<u64> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name();
+            name.to_str()?
+                .strip_prefix(&prefix)?
+                .strip_suffix(".jsonl")?
+                .parse()
+                .ok()
+        })
+        .collect();
+    logs.sort_unstable();
+
+    logs
+}
+
+#[get("/admin/games/<code>/logs")]
+#[allow(clippy::needless_pass_by_value)]
+fn game_logs(_admin: Admin, code: &str, config: &State<Config>) -> Json<Vec<u64>> {
+    Json(logs(config, code))
+}
+
+/// The log of the game started at `started`, or of the last one played with this code
+#[get("/admin/games/<code>/log?<started>")]
+#[allow(clippy::needless_pass_by_value)]
+fn game_log(
+    _admin: Admin,
+    code: &str,
+    started: Option<u64>,
+    config: &State<Config>,
+) -> Option<(ContentType, String)> {
+    let started = started.or_else(|| logs(config, code).pop())?;
+    let path = config
+        .game_log_dir
+        .as_ref()?
+        .join(format!("{}-{started}.jsonl", code.to_uppercase()));
+
+    Some((ContentType::Plain, fs::read_to_string(path).ok()?))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![connections, game_events, player_events, game_logs, game_log]
+}
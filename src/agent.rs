@@ -0,0 +1,75 @@
+use crate::gameplay::{Cable, Game, HistoryEntry, PlayingPlayer, Room, Team};
+use rand::{seq::IteratorRandom, thread_rng, Rng};
+
+/// What a seat knows about a game
+pub struct View<'a, PLAYER: PlayingPlayer> {
+    game: &'a Game<PLAYER>,
+    seat: PLAYER::ID,
+}
+
+impl<'a, PLAYER: PlayingPlayer> View<'a, PLAYER> {
+    /// Returns `None` if `seat` isn't part of the game
+    pub fn new(game: &'a Game<PLAYER>, seat: PLAYER::ID) -> Option<Self> {
+        game.get_player(seat)?;
+        Some(Self { game, seat })
+    }
+
+    pub const fn seat(&self) -> PLAYER::ID {
+        self.seat
+    }
+
+    pub fn team(&self) -> Team {
+        self.game.get_player(self.seat).unwrap().team()
+    }
+
+    /// The cables of the seat, without telling where they are in the hand
+    pub fn hand(&self) -> Vec<Cable> {
+        let mut hand = self.game.get_player(self.seat).unwrap().cables().to_vec();
+        hand.sort_unstable();
+        hand
+    }
+
+    /// How many cables each player has left
+    pub fn hand_sizes(&self) -> impl Iterator<Item = (PLAYER::ID, usize)> + '_ {
+        self.game
+            .players()
+            .values()
+            .map(|p| (p.id(), p.cables().len()))
+    }
+
+    pub fn history(&self) -> &[HistoryEntry<PLAYER::ID>] {
+        self.game.history()
+    }
+
+    pub const fn defusing_remaining(&self) -> usize {
+        self.game.defusing_remaining()
+    }
+
+    /// Players the seat may cut right now, with how many cables they have
+    pub fn targets(&self) -> impl Iterator<Item = (PLAYER::ID, usize)> + '_ {
+        self.hand_sizes()
+            .filter(|(id, count)| *count > 0 && self.game.check_cut(self.seat, *id).is_ok())
+    }
+}
+
+/// A player controlled by a program, independent of how the game is served
+pub trait Agent<PLAYER: PlayingPlayer> {
+    /// Called with everything that happens in the game, in order
+    fn observe(&mut self, _event: &HistoryEntry<PLAYER::ID>) {}
+
+    /// Choose whose cable to cut and its position, only called when the seat holds the wire
+    /// cutters. Returns `None` if there is nothing to cut.
+    fn decide(&mut self, view: &View<'_, PLAYER>) -> Option<(PLAYER::ID, usize)>;
+}
+
+/// Cuts a random cable among the ones it's allowed to
+#[derive(Debug, Default)]
+pub struct RandomAgent;
+
+impl<PLAYER: PlayingPlayer> Agent<PLAYER> for RandomAgent {
+    fn decide(&mut self, view: &View<'_, PLAYER>) -> Option<(PLAYER::ID, usize)> {
+        let mut rng = thread_rng();
+        let (target, count) = view.targets().choose(&mut rng)?;
+        Some((target, rng.gen_range(0..count)))
+    }
+}
@@ -0,0 +1,169 @@
+use crate::config::CacheRule;
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    fs::{relative, NamedFile},
+    get,
+    http::ContentType,
+    request::{FromRequest, Outcome, Request},
+    response::{self, Responder},
+    routes, Response,
+};
+use std::{
+    convert::Infallible,
+    path::{Path, PathBuf},
+};
+
+/// Pre-compressed variants looked for next to each file, by order of preference
+const ENCODINGS: [(&str, &str); 2] = [("br", "br"), ("gzip", "gz")];
+
+/// Encodings accepted by the client, from the `Accept-Encoding` header
+struct AcceptEncoding(Vec<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AcceptEncoding {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let encodings = request
+            .headers()
+            .get("Accept-Encoding")
+            .flat_map(|header| header.split(','))
+            .filter_map(|encoding| {
+                let mut parts = encoding.split(';').map(str::trim);
+                let name = parts.next()?.to_lowercase();
+                // `q=0` means the encoding is explicitly refused
+                let refused = parts.any(|param| {
+                    param
+                        .strip_prefix("q=")
+                        .and_then(|q| q.parse::<f32>().ok())
+                        .is_some_and(|q| q == 0.)
+                });
+                (!refused).then_some(name)
+            })
+            .collect();
+
+        Outcome::Success(Self(encodings))
+    }
+}
+
+impl AcceptEncoding {
+    fn accepts(&self, encoding: &str) -> bool {
+        self.0.iter().any(|x| x == encoding || x == "*")
+    }
+}
+
+struct Asset {
+    file: NamedFile,
+    content_type: Option<ContentType>,
+    encoding: Option<&'static str>,
+}
+
+impl<'r> Responder<'r, 'static> for Asset {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = self.file.respond_to(request)?;
+        // the content type must be the one of the original file, not the one of the archive
+        if let Some(content_type) = self.content_type {
+            response.set_header(content_type);
+        }
+        if let Some(encoding) = self.encoding {
+            response.set_raw_header("Content-Encoding", encoding);
+        }
+        response.set_raw_header("Vary", "Accept-Encoding");
+        Ok(response)
+    }
+}
+
+#[get("/<path..>", rank = 10)]
+async fn asset(path: PathBuf, accept_encoding: AcceptEncoding) -> Option<Asset> {
+    let mut path = Path::new(relative!("static")).join(path);
+    if path.is_dir() {
+        path.push("index.html");
+    }
+    if !path.is_file() {
+        return None;
+    }
+
+    let content_type = path
+        .extension()
+        .and_then(|ext| ContentType::from_extension(&ext.to_string_lossy()));
+
+    for (encoding, extension) in ENCODINGS {
+        if !accept_encoding.accepts(encoding) {
+            continue;
+        }
+
+        let mut compressed = path.clone().into_os_string();
+        compressed.push(".");
+        compressed.push(extension);
+        if let Ok(file) = NamedFile::open(compressed).await {
+            return Some(Asset {
+                file,
+                content_type,
+                encoding: Some(encoding),
+            });
+        }
+    }
+
+    Some(Asset {
+        file: NamedFile::open(path).await.ok()?,
+        content_type,
+        encoding: None,
+    })
+}
+
+/// Sets the `Cache-Control` header of the responses that don't have one
+pub struct CacheControl(pub Vec<CacheRule>);
+
+#[rocket::async_trait]
+impl Fairing for CacheControl {
+    fn info(&self) -> Info {
+        Info {
+            name: "Cache-Control",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if response.headers().contains("Cache-Control")
+            || response.status().class().is_server_error()
+        {
+            return;
+        }
+
+        let path = request.uri().path();
+        if let Some(rule) = self
+            .0
+            .iter()
+            .find(|rule| glob_match(&rule.pattern, path.as_str()))
+        {
+            response.set_raw_header("Cache-Control", rule.value.clone());
+        }
+    }
+}
+
+/// Whether `text` matches `pattern`, where `*` matches any sequence of characters
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // there is always at least one part
+    let first = parts.next().unwrap();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+
+    let mut parts: Vec<_> = parts.collect();
+    let Some(last) = parts.pop() else {
+        // no wildcard
+        return rest.is_empty();
+    };
+    for part in parts {
+        let Some(index) = rest.find(part) else {
+            return false;
+        };
+        rest = &rest[index + part.len()..];
+    }
+    rest.ends_with(last)
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![asset]
+}
@@ -0,0 +1,142 @@
+use crate::{
+    common::{GlobalState, PlayerId, Protected, Registry},
+    config::Config,
+    game,
+    gameplay::{self, Game, Lobby},
+    lobby,
+    stats::Stats,
+    webhook,
+};
+use rocket::{
+    get,
+    http::{CookieJar, Status},
+    request::{FromRequest, Outcome, Request},
+    response::status::BadRequest,
+    routes,
+    serde::{json::Json, Serialize},
+    tokio, State,
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// A program authenticated with `Authorization: Bearer <key>`, one of the configured bots
+pub struct Bot {
+    name: String,
+    webhook: Option<String>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Bot {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let config = request.rocket().state::<Config>().unwrap();
+        // hide the bot endpoints entirely when they are disabled
+        if config.bots.is_empty() {
+            return Outcome::Error((Status::NotFound, ()));
+        }
+
+        let key = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|x| x.strip_prefix("Bearer "));
+        match config.bots.iter().find(|bot| Some(bot.key.as_str()) == key) {
+            Some(bot) => Outcome::Success(Self {
+                name: bot.name.clone(),
+                webhook: bot.webhook.clone(),
+            }),
+            None => Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Joined {
+    lobby: String,
+    player: u32,
+}
+
+/// Take a seat in a lobby. Bots with a webhook are played by the server, the others then use the
+/// same endpoints as the web client with the cookies they received.
+#[get("/bot/join?<lobby>&<password>")]
+#[allow(clippy::needless_pass_by_value)]
+fn join(
+    lobby: &str,
+    password: Option<&str>,
+    bot: Bot,
+    state: &State<GlobalState>,
+    config: &State<Config>,
+    jar: &CookieJar<'_>,
+) -> Result<Json<Joined>, BadRequest<&'static str>> {
+    let player = match bot.webhook {
+        Some(url) => {
+            let games = Arc::clone(&state.games);
+            let stats = Arc::clone(&state.stats);
+            let remote_config = config.inner().clone();
+            lobby::seat_remote(state, lobby, bot.name, password, config, move |game, id| {
+                let timeout = Duration::from_secs(remote_config.bot_webhook_timeout);
+                let decide = webhook::decider(url, timeout);
+                game::play_remotely(game, id, games, stats, remote_config, decide);
+            })
+            .map_err(BadRequest)?
+        }
+        None => {
+            let player =
+                lobby::enter(lobby, bot.name, password, state, config, jar).map_err(BadRequest)?;
+            jar.add_private(("bot", "true"));
+            player
+        }
+    };
+
+    Ok(Json(Joined {
+        lobby: lobby.to_uppercase(),
+        player,
+    }))
+}
+
+/// Play the seat `id` from the server, it cuts a random cable after `bot_delay` seconds whenever it
+/// holds the wire cutters
+pub fn play(
+    game: Protected<Game<game::Player>>,
+    id: <game::Player as gameplay::Player>::ID,
+    games: Arc<Registry<Game<game::Player>>>,
+    stats: Arc<Mutex<Stats>>,
+    config: Config,
+) {
+    let delay = Duration::from_secs(config.bot_delay);
+    // without an answer, a random cable is cut
+    let decide = move |_| async move {
+        tokio::time::sleep(delay).await;
+        None
+    };
+    game::play_remotely(game, id, games, stats, config, decide);
+}
+
+/// Fill a seat of the lobby with a bot played by the server, see [`play`]
+#[get("/lobby/addbot")]
+#[allow(clippy::needless_pass_by_value)]
+fn add(
+    lobby: Protected<Lobby<lobby::Player>>,
+    state: &State<GlobalState>,
+    config: &State<Config>,
+    id: PlayerId,
+) -> Result<Json<u32>, BadRequest<&'static str>> {
+    let id = id.0;
+
+    let games = Arc::clone(&state.games);
+    let stats = Arc::clone(&state.stats);
+    let bot_config = config.inner().clone();
+    let player = lobby::add_bot(state, lobby, id, move |game, id| {
+        play(game, id, games, stats, bot_config);
+    })
+    .map_err(BadRequest)?;
+
+    Ok(Json(player))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![join, add]
+}
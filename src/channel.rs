@@ -0,0 +1,325 @@
+use rocket::{
+    serde::Deserialize,
+    tokio::sync::{mpsc::error::SendError, Notify},
+};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+static LIMITS: OnceLock<Limits> = OnceLock::new();
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// What happens when a message is sent to a full channel
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum Overflow {
+    /// Forget the oldest message, the receiver is told it has to resync
+    #[default]
+    DropOldest,
+    /// Forget every message, the receiver is told to resync and its stream closed
+    Disconnect,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    capacity: usize,
+    overflow: Overflow,
+    max_lag: Option<Duration>,
+}
+
+/// Set the capacity and overflow policy of the channels created afterward. A receiver whose oldest
+/// waiting message is older than `max_lag` is disconnected whatever the policy.
+pub fn set_limits(capacity: usize, overflow: Overflow, max_lag: Option<Duration>) {
+    // only the first call is taken into account
+    let _ = LIMITS.set(Limits {
+        capacity: capacity.max(1),
+        overflow,
+        max_lag,
+    });
+}
+
+/// Number of messages waiting in a channel, and since when
+#[derive(Debug, Clone, Default)]
+pub struct Depth(Arc<(AtomicUsize, Mutex<Option<Instant>>)>);
+
+impl Depth {
+    pub fn get(&self) -> usize {
+        self.0 .0.load(Ordering::Relaxed)
+    }
+
+    /// How long the oldest waiting message has been waiting
+    pub fn lag(&self) -> Duration {
+        self.0
+             .1
+            .lock()
+            .unwrap()
+            .map_or(Duration::ZERO, |x| x.elapsed())
+    }
+
+    fn update<T>(&self, messages: &VecDeque<(Instant, T)>) {
+        self.0 .0.store(messages.len(), Ordering::Relaxed);
+        *self.0 .1.lock().unwrap() = messages.front().map(|x| x.0);
+    }
+}
+
+#[derive(Debug)]
+struct Queue<T> {
+    messages: VecDeque<(Instant, T)>,
+    /// The receiver has to be told to resync
+    overflowed: bool,
+    /// The receiver was too slow, the channel only closes its stream now
+    cut_off: bool,
+    senders: usize,
+    receiver_alive: bool,
+}
+
+#[derive(Debug)]
+struct Shared<T> {
+    id: u64,
+    queue: Mutex<Queue<T>>,
+    notify: Notify,
+    depth: Depth,
+    limits: Limits,
+}
+
+#[derive(Debug)]
+pub struct Sender<T>(Arc<Shared<T>>);
+
+#[derive(Debug)]
+pub struct Receiver<T>(Arc<Shared<T>>);
+
+/// Bounded channel that keeps track of how many messages are waiting to be received
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    with_limits(LIMITS.get().copied().unwrap_or(Limits {
+        capacity: 256,
+        overflow: Overflow::default(),
+        max_lag: None,
+    }))
+}
+
+fn with_limits<T>(limits: Limits) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        queue: Mutex::new(Queue {
+            messages: VecDeque::new(),
+            overflowed: false,
+            cut_off: false,
+            senders: 1,
+            receiver_alive: true,
+        }),
+        notify: Notify::new(),
+        depth: Depth::default(),
+        limits,
+    });
+
+    (Sender(Arc::clone(&shared)), Receiver(shared))
+}
+
+impl<T> Sender<T> {
+    /// Fails when the receiver is gone or was cut off for being too slow
+    pub fn send(&self, message: T) -> Result<(), SendError<T>> {
+        let mut queue = self.0.queue.lock().unwrap();
+        if !queue.receiver_alive || queue.cut_off {
+            return Err(SendError(message));
+        }
+
+        let limits = self.0.limits;
+        let lagging = limits
+            .max_lag
+            .zip(queue.messages.front())
+            .is_some_and(|(max_lag, (sent, _))| sent.elapsed() > max_lag);
+        let full = queue.messages.len() >= limits.capacity;
+        if lagging || (full && matches!(limits.overflow, Overflow::Disconnect)) {
+            queue.messages.clear();
+            queue.overflowed = true;
+            queue.cut_off = true;
+            self.0.depth.update(&queue.messages);
+            drop(queue);
+            self.0.notify.notify_one();
+            return Err(SendError(message));
+        }
+
+        if full {
+            queue.overflowed = true;
+            queue.messages.pop_front();
+        }
+        queue.messages.push_back((Instant::now(), message));
+        self.0.depth.update(&queue.messages);
+        drop(queue);
+
+        self.0.notify.notify_one();
+        Ok(())
+    }
+
+    pub fn depth(&self) -> Depth {
+        self.0.depth.clone()
+    }
+
+    /// Identifies the channel, unlike the sender it doesn't keep the channel open
+    pub fn id(&self) -> u64 {
+        self.0.id
+    }
+}
+
+// derive would require `T: Clone`
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.0.queue.lock().unwrap().senders += 1;
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.0.queue.lock().unwrap().senders -= 1;
+        self.0.notify.notify_one();
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Returns `resync()` in place of the messages dropped because of [`Overflow::DropOldest`].
+    /// A receiver cut off for being too slow gets `resync()` too, then `None` as if the channel
+    /// was closed.
+    pub async fn recv(&mut self, resync: impl FnOnce() -> T) -> Option<T> {
+        loop {
+            {
+                let mut queue = self.0.queue.lock().unwrap();
+                if queue.overflowed {
+                    queue.overflowed = false;
+                    return Some(resync());
+                }
+                if queue.cut_off {
+                    return None;
+                }
+                if let Some((_, message)) = queue.messages.pop_front() {
+                    self.0.depth.update(&queue.messages);
+                    return Some(message);
+                }
+                if queue.senders == 0 {
+                    return None;
+                }
+            }
+
+            self.0.notify.notified().await;
+        }
+    }
+
+    /// Discard all the waiting messages
+    pub fn clear(&mut self) {
+        let mut queue = self.0.queue.lock().unwrap();
+        queue.messages.clear();
+        queue.overflowed = false;
+        self.0.depth.update(&queue.messages);
+    }
+
+    /// Same as [`Sender::id`]
+    pub fn id(&self) -> u64 {
+        self.0.id
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.0.queue.lock().unwrap().receiver_alive = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn limited(
+        capacity: usize,
+        overflow: Overflow,
+        max_lag: Option<Duration>,
+    ) -> (Sender<u32>, Receiver<u32>) {
+        with_limits(Limits {
+            capacity,
+            overflow,
+            max_lag,
+        })
+    }
+
+    #[rocket::async_test]
+    async fn drop_oldest_resyncs() {
+        let (sender, mut receiver) = limited(2, Overflow::DropOldest, None);
+        for i in 0..3 {
+            sender.send(i).unwrap();
+        }
+        assert_eq!(sender.depth().get(), 2);
+
+        assert_eq!(receiver.recv(|| 100).await, Some(100));
+        assert_eq!(receiver.recv(|| 100).await, Some(1));
+        assert_eq!(receiver.recv(|| 100).await, Some(2));
+        drop(sender);
+        assert_eq!(receiver.recv(|| 100).await, None);
+    }
+
+    #[rocket::async_test]
+    async fn disconnect_cuts_off() {
+        let (sender, mut receiver) = limited(2, Overflow::Disconnect, None);
+        sender.send(0).unwrap();
+        sender.send(1).unwrap();
+        assert!(sender.send(2).is_err());
+        assert!(sender.send(3).is_err());
+        assert_eq!(sender.depth().get(), 0);
+
+        assert_eq!(receiver.recv(|| 100).await, Some(100));
+        assert_eq!(receiver.recv(|| 100).await, None);
+    }
+
+    #[rocket::async_test]
+    async fn max_lag_cuts_off() {
+        let (sender, mut receiver) =
+            limited(256, Overflow::DropOldest, Some(Duration::from_millis(10)));
+        sender.send(0).unwrap();
+        sender.send(1).unwrap();
+        thread::sleep(Duration::from_millis(20));
+        assert!(sender.depth().lag() >= Duration::from_millis(10));
+        assert!(sender.send(2).is_err());
+
+        assert_eq!(receiver.recv(|| 100).await, Some(100));
+        assert_eq!(receiver.recv(|| 100).await, None);
+    }
+
+    #[rocket::async_test]
+    async fn max_lag_spares_a_receiver_keeping_up() {
+        let (sender, mut receiver) =
+            limited(256, Overflow::DropOldest, Some(Duration::from_millis(10)));
+        sender.send(0).unwrap();
+        assert_eq!(receiver.recv(|| 100).await, Some(0));
+        thread::sleep(Duration::from_millis(20));
+        // the lag is the one of the oldest waiting message, there is none
+        sender.send(1).unwrap();
+        assert_eq!(receiver.recv(|| 100).await, Some(1));
+    }
+
+    #[rocket::async_test]
+    async fn clear() {
+        let (sender, mut receiver) = limited(2, Overflow::DropOldest, None);
+        for i in 0..3 {
+            sender.send(i).unwrap();
+        }
+        receiver.clear();
+        assert_eq!(sender.depth().get(), 0);
+        assert_eq!(sender.depth().lag(), Duration::ZERO);
+
+        // the overflow is forgotten along with the messages
+        sender.send(3).unwrap();
+        assert_eq!(receiver.recv(|| 100).await, Some(3));
+    }
+
+    #[test]
+    fn send_after_receiver_dropped() {
+        let (sender, receiver) = limited(2, Overflow::DropOldest, None);
+        drop(receiver);
+        assert!(sender.send(0).is_err());
+    }
+}
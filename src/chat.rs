@@ -0,0 +1,35 @@
+use rocket::serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MAX_MESSAGE_LENGTH: usize = 500;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ChatMessage {
+    pub player: u32,
+    pub name: String,
+    pub message: String,
+    pub timestamp: u64,
+}
+
+impl ChatMessage {
+    /// Fails when the message is empty or too long
+    pub fn new(player: u32, name: &str, message: &str) -> Result<Self, &'static str> {
+        let message = message.trim();
+        if message.is_empty() {
+            return Err("Empty message");
+        }
+        if message.chars().count() > MAX_MESSAGE_LENGTH {
+            return Err("Message too long");
+        }
+
+        Ok(Self {
+            player,
+            name: name.to_owned(),
+            message: message.to_owned(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |x| x.as_secs()),
+        })
+    }
+}
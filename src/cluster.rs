@@ -0,0 +1,205 @@
+//! Spreads the rooms over several instances, each room code belongs to one of them
+use crate::{common::GlobalState, config::Config};
+use rocket::{
+    get,
+    http::Status,
+    post,
+    request::{FromRequest, Outcome, Request},
+    response::Redirect,
+    routes,
+};
+
+/// Points of each node on the ring, more points spread the codes more evenly
+const POINTS_PER_NODE: u32 = 64;
+
+// FNV-1a, every node must compute the same hash whatever it was compiled with
+fn hash(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    let hash = bytes
+        .into_iter()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    // the high bits hardly depend on the last bytes, without the finalizer of MurmurHash3 the
+    // points of a node are all next to each other
+    let hash = (hash ^ (hash >> 33)).wrapping_mul(0xff51_afd7_ed55_8ccd);
+    let hash = (hash ^ (hash >> 33)).wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+/// Consistent hashing of the room codes, adding or removing a node only moves the codes next to
+/// its points
+#[derive(Debug, Default)]
+pub struct Ring {
+    nodes: Vec<String>,
+    /// Sorted by hash, with the index of the node
+    points: Vec<(u64, usize)>,
+    this: Option<usize>,
+}
+
+impl Ring {
+    pub fn new(config: &Config) -> Self {
+        let nodes: Vec<String> = config
+            .nodes
+            .iter()
+            .map(|x| x.trim_end_matches('/').to_owned())
+            .collect();
+        let this = config
+            .node
+            .as_deref()
+            .and_then(|this| nodes.iter().position(|x| x == this.trim_end_matches('/')));
+        // no room code would ever be free to create here
+        assert!(
+            nodes.is_empty() || this.is_some(),
+            "`node` must be one of the `nodes`"
+        );
+
+        let mut points: Vec<_> = nodes
+            .iter()
+            .enumerate()
+            .flat_map(|(i, node)| {
+                (0..POINTS_PER_NODE)
+                    .map(move |point| (hash(node.bytes().chain([0]).chain(point.to_le_bytes())), i))
+            })
+            .collect();
+        points.sort_unstable();
+
+        Self {
+            nodes,
+            points,
+            this,
+        }
+    }
+
+    /// Base URL of the node the room belongs to, `None` for this one
+    pub fn owner(&self, code: &str) -> Option<&str> {
+        let first = self.points.first()?;
+        let code = hash(code.to_uppercase().bytes());
+        let index = self.points.partition_point(|x| x.0 < code);
+        let (_, node) = self.points.get(index).unwrap_or(first);
+
+        (Some(*node) != self.this).then(|| self.nodes[*node].as_str())
+    }
+
+    pub fn owns(&self, code: &str) -> bool {
+        self.owner(code).is_none()
+    }
+}
+
+/// A request about a room of another node
+pub struct Foreign(String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Foreign {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let state = request.rocket().state::<GlobalState>().unwrap();
+        let query = |name| request.query_value::<String>(name).and_then(Result::ok);
+        let code = match request.uri().path().as_str() {
+            "/lobby/create" => query("id"),
+            _ => query("lobby"),
+        }
+        .or_else(|| {
+            request
+                .cookies()
+                .get_private("lobby")
+                .map(|x| x.value().to_owned())
+        });
+
+        match code.as_deref().and_then(|code| state.ring.owner(code)) {
+            Some(node) => Outcome::Success(Self(format!("{node}{}", request.uri()))),
+            None => Outcome::Forward(Status::NotFound),
+        }
+    }
+}
+
+#[get("/<_..>")]
+fn get_elsewhere(foreign: Foreign) -> Redirect {
+    Redirect::temporary(foreign.0)
+}
+
+#[post("/<_..>")]
+fn post_elsewhere(foreign: Foreign) -> Redirect {
+    Redirect::temporary(foreign.0)
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    let mut routes = routes![get_elsewhere, post_elsewhere];
+    // before every other route, the requests are forwarded to them when the room is here
+    for route in &mut routes {
+        route.rank = -20;
+    }
+
+    routes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{
+        distributions::{Alphanumeric, DistString},
+        rngs::StdRng,
+        SeedableRng,
+    };
+
+    fn ring(nodes: &[&str], node: Option<&str>) -> Ring {
+        Ring::new(&Config {
+            nodes: nodes.iter().map(|&x| x.to_owned()).collect(),
+            node: node.map(str::to_owned),
+            ..Config::default()
+        })
+    }
+
+    /// Codes like the ones given to the rooms, always the same ones
+    fn codes() -> impl Iterator<Item = String> {
+        let mut rng = StdRng::seed_from_u64(0);
+        (0..1000).map(move |_| Alphanumeric.sample_string(&mut rng, 6).to_uppercase())
+    }
+
+    #[test]
+    fn alone_owns_everything() {
+        let ring = ring(&[], None);
+        assert!(codes().all(|code| ring.owns(&code)));
+    }
+
+    #[test]
+    fn every_code_has_one_owner() {
+        let nodes = ["http://a", "http://b/", "http://c"];
+        let rings: Vec<_> = nodes.iter().map(|&x| ring(&nodes, Some(x))).collect();
+        for code in codes() {
+            assert_eq!(rings.iter().filter(|x| x.owns(&code)).count(), 1);
+            // the others all send the code to the same node, without its trailing slash
+            let owners: Vec<_> = rings.iter().filter_map(|x| x.owner(&code)).collect();
+            assert!(owners.windows(2).all(|x| x[0] == x[1]));
+            assert!(!owners[0].ends_with('/'));
+        }
+        // the codes are spread over all the nodes
+        for ring in &rings {
+            assert!(codes().filter(|x| ring.owns(x)).count() > 200);
+        }
+    }
+
+    #[test]
+    fn codes_are_case_insensitive() {
+        let ring = ring(&["http://a", "http://b"], Some("http://a"));
+        for code in codes() {
+            assert_eq!(ring.owner(&code), ring.owner(&code.to_lowercase()));
+        }
+    }
+
+    #[test]
+    fn adding_a_node_only_moves_codes_to_it() {
+        let before = ring(&["http://a", "http://b"], Some("http://a"));
+        let after = ring(&["http://a", "http://b", "http://c"], Some("http://a"));
+        for code in codes() {
+            let owner = after.owner(&code);
+            assert!(owner == before.owner(&code) || owner == Some("http://c"));
+        }
+    }
+
+    #[test]
+    #[should_panic = "`node` must be one of the `nodes`"]
+    fn unknown_node() {
+        ring(&["http://a", "http://b"], Some("http://c"));
+    }
+}
@@ -1,11 +1,31 @@
 use crate::{
+    admin::Connections,
+    cluster::Ring,
+    config::Config,
     game,
-    gameplay::{Game, Lobby},
+    gameplay::{Game, Lobby, Room},
+    janitor::Janitor,
+    limits::{Abuse, StreamCounts},
     lobby,
+    mirror::Mirrored,
+    party::Party,
+    scenario::Scenarios,
+    stats::Stats,
+};
+use rocket::{
+    catch, catchers,
+    http::{Cookie, CookieJar, Status},
+    request::{FromRequest, Outcome, Request},
+    response::{self, Responder, Response},
+    serde::{json::Json, Serialize},
+    tokio, Catcher, State,
 };
 use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex, MutexGuard},
+    collections::{hash_map::DefaultHasher, HashMap},
+    convert::Infallible,
+    future::Future,
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex, MutexGuard, Weak},
 };
 
 macro_rules! make_event {
@@ -17,20 +37,86 @@ macro_rules! make_event {
 
 pub(crate) use make_event;
 
+const SHARDS: usize = 16;
+
+/// Rooms by code, split in shards so that unrelated rooms don't wait on the same lock
+pub struct Registry<T>([Mutex<HashMap<String, Protected<T>>>; SHARDS]);
+
+impl<T> Registry<T> {
+    /// Lock the shard containing `key`
+    pub fn shard(&self, key: &str) -> MutexGuard<'_, HashMap<String, Protected<T>>> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        #[allow(clippy::cast_possible_truncation)]
+        let shard = &self.0[(hasher.finish() % SHARDS as u64) as usize];
+        shard.lock().unwrap_or_else(|poisoned| {
+            rocket::warn!("A handler panicked while holding a registry, recovering it");
+            shard.clear_poison();
+            poisoned.into_inner()
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<Protected<T>> {
+        self.shard(key).get(key).cloned()
+    }
+}
+
+impl<T> Default for Registry<T> {
+    fn default() -> Self {
+        Self(std::array::from_fn(|_| Mutex::default()))
+    }
+}
+
 pub struct GlobalState {
-    pub lobbys: Arc<Mutex<HashMap<String, Protected<Lobby<lobby::Player>>>>>,
-    pub games: Arc<Mutex<HashMap<String, Protected<Game<game::Player>>>>>,
+    pub lobbys: Arc<Registry<Lobby<lobby::Player>>>,
+    pub games: Arc<Registry<Game<game::Player>>>,
+    pub stats: Arc<Mutex<Stats>>,
+    pub parties: Arc<Registry<Party>>,
+    pub streams: StreamCounts,
+    pub abuse: Abuse,
+    pub connections: Connections,
+    pub janitor: Janitor,
+    pub scenarios: Scenarios,
+    pub ring: Ring,
+    pub mirrored: Mirrored,
+    /// Lobby opened for the players of a finished game, by id of its record in the stats
+    pub rematches: Mutex<HashMap<u64, String>>,
 }
 
 impl GlobalState {
-    pub fn new() -> Self {
+    pub fn new(config: &Config) -> Self {
         Self {
-            lobbys: Arc::new(Mutex::new(HashMap::new())),
-            games: Arc::new(Mutex::new(HashMap::new())),
+            lobbys: Arc::default(),
+            games: Arc::default(),
+            stats: Arc::new(Mutex::new(Stats::load(config))),
+            parties: Arc::default(),
+            streams: StreamCounts::default(),
+            abuse: Abuse::default(),
+            connections: Connections::default(),
+            janitor: Janitor::default(),
+            scenarios: Scenarios::load(&config.scenario_dir),
+            ring: Ring::new(config),
+            mirrored: Mirrored::default(),
+            rematches: Mutex::default(),
         }
     }
 }
 
+/// Run `task` in the background, calling `on_panic` if it panicked, e.g. to recover the room it
+/// was changing like [`catchers`] do for the requests
+pub fn spawn_supervised<F>(on_panic: impl FnOnce() + Send + 'static, task: F)
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let handle = tokio::spawn(task);
+    tokio::spawn(async move {
+        if handle.await.is_err_and(|e| e.is_panic()) {
+            on_panic();
+        }
+    });
+}
+
 pub struct Protected<T>(Arc<Mutex<T>>);
 
 impl<T> Protected<T> {
@@ -38,8 +124,20 @@ impl<T> Protected<T> {
         Self(Arc::new(Mutex::new(content)))
     }
 
-    pub fn lock(&self) -> MutexGuard<T> {
-        self.0.lock().unwrap()
+    /// A panic while the room was locked doesn't lose it, see [`catchers`] for the recovery
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.0.lock().unwrap_or_else(|poisoned| {
+            self.0.clear_poison();
+            poisoned.into_inner()
+        })
+    }
+
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    pub fn downgrade(&self) -> WeakProtected<T> {
+        WeakProtected(Arc::downgrade(&self.0))
     }
 }
 
@@ -48,3 +146,202 @@ impl<T> Clone for Protected<T> {
         Self(Arc::clone(&self.0))
     }
 }
+
+pub struct WeakProtected<T>(Weak<Mutex<T>>);
+
+impl<T> WeakProtected<T> {
+    pub fn upgrade(&self) -> Option<Protected<T>> {
+        self.0.upgrade().map(Protected)
+    }
+}
+
+/// Why the room of the client couldn't be found
+#[derive(Debug, Clone, Copy)]
+pub enum NoRoom {
+    /// The client didn't join any room
+    NotJoined,
+    /// The client is in a room of the other kind, e.g. a game instead of a lobby
+    Elsewhere,
+    /// The room the client was in doesn't exist anymore, its cookies have been cleared
+    Expired,
+}
+
+impl NoRoom {
+    /// Make the room guard for `name` fail, forgetting the session if it's gone for good
+    pub async fn check<T>(request: &Request<'_>, name: &str) -> Outcome<T, Self> {
+        let state = request.guard::<&State<GlobalState>>().await.unwrap();
+        if state.lobbys.get(name).is_some() || state.games.get(name).is_some() {
+            return Outcome::Error((Status::NotFound, Self::Elsewhere));
+        }
+
+        forget_session(request.cookies());
+        Outcome::Error((Status::Gone, Self::Expired))
+    }
+}
+
+fn forget_session(jar: &CookieJar<'_>) {
+    jar.remove_private("lobby");
+    jar.remove_private("id");
+    jar.remove_private("name");
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct SessionExpired {
+    error: &'static str,
+    reason: &'static str,
+}
+
+#[catch(410)]
+fn session_expired(request: &Request<'_>) -> Json<SessionExpired> {
+    // changes made to the cookies by the guards are lost when they fail
+    forget_session(request.cookies());
+
+    Json(SessionExpired {
+        error: "session_expired",
+        reason: "The room you were in doesn't exist anymore",
+    })
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct InternalError {
+    error: &'static str,
+    reason: &'static str,
+}
+
+/// The handler may have panicked in the middle of changing the room of the client, save it for
+/// debugging and have everyone in it fetch its state again
+#[catch(500)]
+fn internal_error(request: &Request<'_>) -> Json<InternalError> {
+    let state = request.rocket().state::<GlobalState>().unwrap();
+    let config = request.rocket().state::<Config>().unwrap();
+    if let Some(room) = request.cookies().get_private("lobby") {
+        if let Some(game) = state.games.get(room.value()) {
+            game::recover(&game, config);
+        } else if let Some(lobby) = state.lobbys.get(room.value()) {
+            lobby::recover(&lobby);
+        }
+    }
+
+    Json(InternalError {
+        error: "internal_error",
+        reason: "Something went wrong on the server",
+    })
+}
+
+/// Why a guard refused the request, for the 400 catcher
+struct Rejected(Option<&'static str>);
+
+#[catch(400)]
+fn bad_request(request: &Request<'_>) -> &'static str {
+    request
+        .local_cache(|| Rejected(None))
+        .0
+        .unwrap_or("Bad request")
+}
+
+pub fn catchers() -> Vec<Catcher> {
+    catchers![bad_request, session_expired, internal_error]
+}
+
+/// The player sending the request, from their private `id` cookie. Refused once their seat in the
+/// game was given to someone else.
+#[derive(Debug, Clone, Copy)]
+pub struct PlayerId(pub u32);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for PlayerId {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let reject = |reason| {
+            request.local_cache(|| Rejected(Some(reason)));
+            Outcome::Error((Status::BadRequest, reason))
+        };
+        let cookies = request.cookies();
+        let Some(id) = cookies
+            .get_private("id")
+            .and_then(|x| x.value().parse().ok())
+        else {
+            return reject("Invalid player id");
+        };
+
+        let state = request.rocket().state::<GlobalState>().unwrap();
+        let seat = cookies.get_private("seat");
+        let revoked = cookies
+            .get_private("lobby")
+            .and_then(|lobby| state.games.get(lobby.value()))
+            .is_some_and(|game| {
+                game.lock()
+                    .get_player(id)
+                    .is_some_and(|x| !x.holds_seat(seat.as_ref().map(Cookie::value)))
+            });
+        if revoked {
+            return reject("Someone else took your seat");
+        }
+
+        Outcome::Success(Self(id))
+    }
+}
+
+/// Value of the `If-None-Match` header
+pub struct IfNoneMatch(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfNoneMatch {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(Self(
+            request
+                .headers()
+                .get_one("If-None-Match")
+                .map(str::to_owned),
+        ))
+    }
+}
+
+impl IfNoneMatch {
+    fn matches(&self, etag: &str) -> bool {
+        self.0.as_deref().is_some_and(|header| {
+            header
+                .split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag)
+        })
+    }
+}
+
+/// A state that is only sent again if it changed since the client last polled it
+pub enum Polled<T> {
+    NotModified(String),
+    Modified(String, T),
+}
+
+impl<T> Polled<T> {
+    /// `tag` must change every time `content` would be different
+    pub fn new(tag: &str, if_none_match: &IfNoneMatch, content: impl FnOnce() -> T) -> Self {
+        let etag = format!("\"{tag}\"");
+        if if_none_match.matches(&etag) {
+            Self::NotModified(etag)
+        } else {
+            Self::Modified(etag, content())
+        }
+    }
+}
+
+impl<'r, T: Responder<'r, 'static>> Responder<'r, 'static> for Polled<T> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let (mut response, etag) = match self {
+            Self::NotModified(etag) => (
+                Response::build().status(Status::NotModified).finalize(),
+                etag,
+            ),
+            Self::Modified(etag, content) => (content.respond_to(request)?, etag),
+        };
+        response.set_raw_header("ETag", etag);
+        response.set_raw_header("Cache-Control", "no-cache");
+        Ok(response)
+    }
+}
@@ -0,0 +1,321 @@
+use crate::{
+    channel::Overflow,
+    gameplay::{Cable, Team},
+};
+use rocket::serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(default)]
+pub struct Config {
+    /// Seconds before the wire cutter holder is reminded that it's their turn, nobody is reminded
+    /// if unset
+    pub nudge_after: Option<u64>,
+    /// Also tell everyone who the game is waiting on when nudging
+    pub broadcast_waiting: bool,
+    /// Seconds after which a lobby member who isn't ready and did nothing is kicked, nobody is
+    /// kicked if unset
+    pub idle_kick_after: Option<u64>,
+    /// Seconds after which a lobby is closed no matter what, they stay open if unset
+    pub max_lobby_lifetime: Option<u64>,
+    /// Seconds after which a running game is stopped
+    pub max_game_duration: Option<u64>,
+    /// How a game stopped by `max_game_duration` ends
+    pub timeout_outcome: TimeoutOutcome,
+    /// Milliseconds clients should wait before revealing a cut cable
+    pub reveal_delay: u64,
+    /// Milliseconds to wait instead when the reveal is dramatic
+    pub dramatic_reveal_delay: u64,
+    /// A reveal is dramatic when at most this many defusing cables remain
+    pub dramatic_threshold: usize,
+    /// Seconds a cutter has to confirm their cut when the rules ask for it
+    pub cut_confirm_delay: u64,
+    /// Seconds between the end of a round and the new hands being dealt, 0 deals them right away
+    pub round_countdown: u64,
+    /// Where to save the stats of the players, they are only kept in memory if unset
+    pub stats_file: Option<PathBuf>,
+    /// Where the messages of each game are written once it is over
+    pub game_log_dir: Option<PathBuf>,
+    /// Where the state of a game is saved when a request made in it fails
+    pub crash_dir: Option<PathBuf>,
+    /// Length of a season in days, the leaderboard starts over at the beginning of each one
+    pub season_days: Option<u64>,
+    /// Mixed into the seed of the daily challenge, so that its deal can't be worked out from the
+    /// date. It must be the same on every node, a random one is picked at startup if empty.
+    pub daily_secret: String,
+    /// Mixed into the pseudonyms of anonymous games, so they can't be matched with a list of names.
+    /// A random one is kept with the stats if empty.
+    pub pseudonym_salt: String,
+    /// Directory containing the scenario files (`<name>.json`)
+    pub scenario_dir: PathBuf,
+    /// `Cache-Control` header of the responses, the first rule matching the path is used
+    pub cache_control: Vec<CacheRule>,
+    /// Port of a plain HTTP listener redirecting to HTTPS, only used when `tls` is configured
+    pub http_redirect_port: Option<u16>,
+    /// How many event streams a single IP address can keep open at the same time
+    pub max_streams_per_ip: Option<usize>,
+    /// How many event streams can be open at the same time, new ones are refused above
+    pub max_streams: Option<usize>,
+    /// Seconds clients are told to wait before reconnecting when the server is busy
+    pub busy_retry_after: u64,
+    /// How many lobby creations, joins and leaves an IP address can do in `abuse_window`
+    /// before being blocked
+    pub abuse_max_actions: Option<usize>,
+    /// Seconds
+    pub abuse_window: u64,
+    /// Seconds an IP address stays blocked
+    pub abuse_block: u64,
+    /// Token giving access to the `/admin` endpoints, they are disabled if unset
+    pub admin_token: Option<String>,
+    /// How many messages can wait to be sent to a single client
+    pub channel_capacity: usize,
+    /// What to do when a client is too slow and `channel_capacity` is reached
+    pub channel_overflow: Overflow,
+    /// Seconds a message can wait to be sent to a client, a client falling further behind is told
+    /// to resync and disconnected
+    pub max_client_lag: Option<u64>,
+    /// Programs allowed to play through the `/bot` endpoints, they are disabled if empty
+    pub bots: Vec<BotKey>,
+    /// Seconds a bot webhook has to answer before a random cable is cut instead
+    pub bot_webhook_timeout: u64,
+    /// Seconds the bots added by the host of a lobby wait before cutting
+    pub bot_delay: u64,
+    /// Registered players, nobody else can play under their name
+    pub accounts: Vec<Account>,
+    /// What to do when someone picks the name of another player of the room
+    pub name_collision: NameCollision,
+    /// Base URL of every instance sharing the room codes, empty when running alone. They must
+    /// have the same `secret_key`.
+    pub nodes: Vec<String>,
+    /// Base URL of this instance, one of `nodes`
+    pub node: Option<String>,
+    /// Sent by the mirrors as `Authorization: Bearer <key>` to follow the games of this instance,
+    /// nobody can mirror it if unset
+    pub mirror_key: Option<String>,
+    /// Names the lobbies can pick for the teams and cables, besides the default ones
+    pub themes: Vec<Theme>,
+    /// Base URL of the instance this one mirrors, it then only serves spectators and replays. Its
+    /// `mirror_key` must be given in `mirror_key`.
+    pub mirror_of: Option<String>,
+    /// Seconds the spectators are kept behind the game, so that they can't tell the players what
+    /// just happened. 0 shows them everything right away.
+    pub spectator_delay: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CacheRule {
+    /// Path of the request, where `*` matches anything
+    pub pattern: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct BotKey {
+    /// Name the bot plays under
+    pub name: String,
+    /// Sent by the bot as `Authorization: Bearer <key>`
+    pub key: String,
+    /// When set, the server plays the seats of the bot and asks this URL which cable to cut
+    pub webhook: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Account {
+    /// Reserved display name
+    pub name: String,
+    /// Given to `/account/login` to play under the name
+    pub key: String,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "lowercase")]
+pub enum TimeoutOutcome {
+    Draw,
+    Moriarty,
+}
+
+impl TimeoutOutcome {
+    pub const fn winner(self) -> Option<Team> {
+        match self {
+            Self::Draw => None,
+            Self::Moriarty => Some(Team::Moriarty),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "lowercase")]
+pub enum NameCollision {
+    Reject,
+    /// Add a number after the name
+    Suffix,
+    Allow,
+}
+
+impl NameCollision {
+    /// The name to use, `None` if it is refused
+    pub fn resolve(self, name: String, taken: impl Fn(&str) -> bool) -> Option<String> {
+        match self {
+            _ if !taken(&name) => Some(name),
+            Self::Reject => None,
+            Self::Suffix => (2..).map(|i| format!("{name} {i}")).find(|x| !taken(x)),
+            Self::Allow => Some(name),
+        }
+    }
+}
+
+/// Names shown for the teams and cables, the messages only use their ids
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Theme {
+    /// Id of the theme, given to `/lobby/settings`
+    pub name: String,
+    pub teams: TeamNames,
+    pub cables: CableNames,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TeamNames {
+    pub sherlock: String,
+    pub moriarty: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(default)]
+pub struct CableNames {
+    pub safe: String,
+    pub defusing: String,
+    pub bomb: String,
+    pub short_fuse: String,
+    pub decoy_bomb: String,
+}
+
+impl Default for CableNames {
+    fn default() -> Self {
+        Self {
+            safe: "Safe".to_owned(),
+            defusing: "Defusing".to_owned(),
+            bomb: "Bomb".to_owned(),
+            short_fuse: "Short fuse".to_owned(),
+            decoy_bomb: "Decoy bomb".to_owned(),
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            name: "classic".to_owned(),
+            teams: TeamNames {
+                sherlock: "Sherlock".to_owned(),
+                moriarty: "Moriarty".to_owned(),
+            },
+            cables: CableNames::default(),
+        }
+    }
+}
+
+impl Theme {
+    pub fn team(&self, team: Team) -> &str {
+        match team {
+            Team::Sherlock => &self.teams.sherlock,
+            Team::Moriarty => &self.teams.moriarty,
+        }
+    }
+
+    pub fn cable(&self, cable: Cable) -> &str {
+        match cable {
+            Cable::Safe => &self.cables.safe,
+            Cable::Defusing => &self.cables.defusing,
+            Cable::Bomb => &self.cables.bomb,
+            Cable::ShortFuse => &self.cables.short_fuse,
+            Cable::DecoyBomb => &self.cables.decoy_bomb,
+        }
+    }
+}
+
+impl Config {
+    /// The theme with this name, the default one if it isn't configured
+    pub fn theme(&self, name: Option<&str>) -> Theme {
+        name.and_then(|name| self.themes.iter().find(|x| x.name == name))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Every theme a lobby can pick, starting with the default one
+    pub fn all_themes(&self) -> Vec<Theme> {
+        let mut themes = vec![Theme::default()];
+        themes.extend(self.themes.iter().cloned());
+        themes
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            nudge_after: None,
+            broadcast_waiting: true,
+            idle_kick_after: None,
+            max_lobby_lifetime: None,
+            max_game_duration: None,
+            timeout_outcome: TimeoutOutcome::Draw,
+            reveal_delay: 1000,
+            dramatic_reveal_delay: 3000,
+            dramatic_threshold: 1,
+            cut_confirm_delay: 5,
+            round_countdown: 0,
+            stats_file: None,
+            game_log_dir: None,
+            crash_dir: None,
+            season_days: None,
+            daily_secret: String::new(),
+            pseudonym_salt: String::new(),
+            scenario_dir: PathBuf::from("scenarios"),
+            cache_control: vec![
+                CacheRule {
+                    pattern: "/".to_owned(),
+                    value: "no-cache".to_owned(),
+                },
+                CacheRule {
+                    pattern: "*.html".to_owned(),
+                    value: "no-cache".to_owned(),
+                },
+                // file names of these assets contain their hash, so they never change
+                CacheRule {
+                    pattern: "/assets/*".to_owned(),
+                    value: "public, max-age=31536000, immutable".to_owned(),
+                },
+            ],
+            http_redirect_port: None,
+            max_streams_per_ip: Some(16),
+            max_streams: None,
+            busy_retry_after: 30,
+            abuse_max_actions: Some(30),
+            abuse_window: 60,
+            abuse_block: 60 * 5,
+            admin_token: None,
+            channel_capacity: 256,
+            channel_overflow: Overflow::DropOldest,
+            max_client_lag: Some(30),
+            bots: Vec::new(),
+            bot_webhook_timeout: 10,
+            bot_delay: 2,
+            accounts: Vec::new(),
+            name_collision: NameCollision::Suffix,
+            nodes: Vec::new(),
+            node: None,
+            mirror_key: None,
+            mirror_of: None,
+            themes: Vec::new(),
+            spectator_delay: 0,
+        }
+    }
+}
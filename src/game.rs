@@ -1,39 +1,191 @@
 use crate::{
-    common::{make_event, GlobalState, Protected},
-    gameplay::{self, errors, Cable, CutOutcome, Game, PlayingPlayer, Room, Team, WaitingPlayer},
+    account,
+    admin::Kind,
+    agent::{Agent, RandomAgent, View},
+    bot,
+    channel::{channel, Receiver, Sender},
+    chat::ChatMessage,
+    common::{
+        self, make_event, GlobalState, IfNoneMatch, NoRoom, PlayerId, Polled, Protected, Registry,
+        WeakProtected,
+    },
+    config::{Config, Theme},
+    gameplay::{
+        self, errors, Cable, Claim, CutEvent, CutOutcome, Game, HistoryEntry, PlayingPlayer, Room,
+        RoundReveal, Series, Team, TeamSizes, WaitingPlayer,
+    },
+    janitor::JanitorHandle,
+    limits::{Refused, Shed, StreamSlot},
+    lobby,
+    roles::{Effect, Role},
+    stats::{self, Stats},
+};
+use rand::{
+    distributions::{Alphanumeric, DistString},
+    thread_rng, Rng,
 };
-use rand::{seq::SliceRandom, thread_rng};
 use rocket::{
     get,
     http::{CookieJar, Status},
+    post,
     request::{FromRequest, Outcome, Request},
     response::{
         status::BadRequest,
         stream::{Event, EventStream},
+        Redirect,
     },
     routes,
-    serde::Serialize,
-    tokio::{
-        self, select,
-        sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    serde::{
+        json::{self, Json},
+        Serialize,
     },
-    Shutdown, State,
+    tokio::{self, select},
+    FromFormField, Shutdown, State,
 };
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet, VecDeque},
+    fs,
+    future::Future,
     sync::{Arc, Mutex, Weak},
-    time::Duration,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+#[cfg(feature = "debug-endpoints")]
+mod debug;
+mod describe;
+
 #[derive(Debug)]
 pub struct Player {
     id: <Self as gameplay::Player>::ID,
     name: String,
     team: Team,
+    role: Option<Role>,
     cables: Vec<Cable>,
-    revealed_cables: Vec<Cable>,
-    sender: UnboundedSender<Message>,
-    receiver: Option<Mutex<UnboundedReceiver<Message>>>,
+    /// `cables` sorted, shared with the messages sending it
+    hand: Arc<[Cable]>,
+    revealed_cables: Arc<[Cable]>,
+    sender: Sender<Message>,
+    receiver: Option<Mutex<Receiver<Message>>>,
+    disconnected_at: Option<Instant>,
+    substitute: Option<Substitute>,
+    /// Given to whoever took the seat of someone else, the ones who had it before don't have it
+    seat_token: Option<String>,
+    pending_cut: Option<PendingCut>,
+    /// Players who want this one out of the game, forgotten when they come back
+    removal_votes: HashSet<<Self as gameplay::Player>::ID>,
+    /// Wants the game to be cancelled
+    abort_vote: bool,
+    /// Agreed to undo the cut made at this turn
+    undo_consent: Option<usize>,
+    /// What this player thinks of the others, only ever sent to them
+    suspicions: HashMap<<Self as gameplay::Player>::ID, Suspicion>,
+    /// Left the game, which counts as a loss whoever wins it
+    forfeited: bool,
+    /// The game is still counted in the stats, but not under the name of the player
+    no_stats: bool,
+    /// See [`stats::key`], the name of the player is used without one
+    stats_key: Option<String>,
+    tap: Tap,
+}
+
+/// Copies of the messages of a game for the admins watching it, shared by all the players once
+/// someone watches
+#[derive(Debug, Clone, Default)]
+struct Tap(Arc<Mutex<Vec<Sender<Tapped>>>>);
+
+impl Tap {
+    fn mirror(&self, to: Option<<Player as gameplay::Player>::ID>, msg: &Message) {
+        self.0.lock().unwrap().retain(|watcher| {
+            watcher
+                .send(Tapped {
+                    to,
+                    private: to.is_some(),
+                    message: msg.clone(),
+                    sent: Instant::now(),
+                })
+                .is_ok()
+        });
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Tapped {
+    /// `None` when the message was sent to everyone
+    to: Option<<Player as gameplay::Player>::ID>,
+    /// Only the recipient was supposed to see this message
+    private: bool,
+    message: Message,
+    #[serde(skip)]
+    sent: Instant,
+}
+
+impl Tapped {
+    pub const fn name(&self) -> &'static str {
+        self.message.name()
+    }
+
+    pub fn resync() -> Self {
+        Self {
+            to: None,
+            private: false,
+            message: Message::Resync,
+            sent: Instant::now(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(untagged)]
+enum Uncut {
+    Defusing(usize),
+    Hands(Vec<Cable>),
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Revealed {
+    team: Team,
+    role: Option<Role>,
+    /// The cables left uncut
+    cables: Arc<[Cable]>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct TurnTimer {
+    seconds: u64,
+    /// Seconds left for the current turn
+    remaining: u64,
+}
+
+/// A cut waiting for `/game/cut/confirm`
+#[derive(Debug, Clone, Copy)]
+struct PendingCut {
+    /// `None` for the neutral hand
+    player: Option<<Player as gameplay::Player>::ID>,
+    index: usize,
+    since: Instant,
+}
+
+/// Someone asking to take over the seat of a disconnected player
+#[derive(Debug)]
+struct Substitute {
+    token: String,
+    name: String,
+    approved: bool,
+}
+
+/// A private note about another player, it has no effect on the game
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, FromFormField)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "lowercase")]
+enum Suspicion {
+    Trusted,
+    Suspected,
+    /// Removes the marker
+    Unknown,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -41,19 +193,56 @@ pub struct Player {
 struct PlayerData {
     id: <Player as gameplay::Player>::ID,
     name: String,
-    revealed_cables: Vec<Cable>,
+    revealed_cables: Arc<[Cable]>,
+    hand_size: usize,
     connected: bool,
 }
 
 impl Player {
+    /// Send a message to this player only, a dead stream is noticed by the next broadcast
+    fn send(&self, msg: Message) {
+        self.tap.mirror(Some(self.id), &msg);
+        let _ = self.sender.send(msg);
+    }
+
+    /// Disconnected for longer than the grace, they don't count in the votes anymore
+    fn abandoned(&self, grace: Duration) -> bool {
+        self.disconnected_at.is_some_and(|x| x.elapsed() >= grace)
+    }
+
     fn clone_data(&self) -> PlayerData {
         PlayerData {
             id: self.id,
             name: self.name.clone(),
-            revealed_cables: self.revealed_cables.clone(),
+            revealed_cables: Arc::clone(&self.revealed_cables),
+            hand_size: self.cables.len(),
             connected: self.receiver.is_none(),
         }
     }
+
+    /// The cables of the player, without telling where they are in the hand
+    fn hand(&self) -> Arc<[Cable]> {
+        Arc::clone(&self.hand)
+    }
+
+    /// Whether the client with the `seat` cookie `token` is the one in this seat
+    pub fn holds_seat(&self, token: Option<&str>) -> bool {
+        self.seat_token.as_deref().is_none_or(|x| Some(x) == token)
+    }
+
+    pub fn hide_from_stats(&mut self) {
+        self.no_stats = true;
+    }
+
+    pub fn record_as(&mut self, key: String) {
+        self.stats_key = Some(key);
+    }
+
+    fn update_hand(&mut self) {
+        let mut cables = self.cables.clone();
+        cables.sort_unstable();
+        self.hand = cables.into();
+    }
 }
 
 impl gameplay::Player for Player {
@@ -70,15 +259,29 @@ impl gameplay::Player for Player {
 
 impl gameplay::PlayingPlayer for Player {
     fn new<T: WaitingPlayer<ID = Self::ID>>(player: &T, team: Team) -> Self {
-        let (sender, receiver) = unbounded_channel();
+        let (sender, receiver) = channel();
         Self {
             id: player.id(),
             name: player.name().to_owned(),
             team,
+            role: None,
             cables: Vec::new(),
-            revealed_cables: Vec::new(),
+            hand: Arc::new([]),
+            revealed_cables: Arc::new([]),
             sender,
             receiver: Some(Mutex::new(receiver)),
+            disconnected_at: None,
+            substitute: None,
+            seat_token: None,
+            pending_cut: None,
+            removal_votes: HashSet::new(),
+            abort_vote: false,
+            undo_consent: None,
+            suspicions: HashMap::new(),
+            forfeited: false,
+            no_stats: false,
+            stats_key: None,
+            tap: Tap::default(),
         }
     }
 
@@ -96,14 +299,36 @@ impl gameplay::PlayingPlayer for Player {
 
     fn set_cables(&mut self, cables: Vec<Cable>) {
         self.cables = cables;
+        self.update_hand();
     }
 
-    fn cut_cable(&mut self) -> Cable {
-        self.cables.shuffle(&mut thread_rng());
-        let cutted = self.cables.pop().unwrap();
-        self.revealed_cables.push(cutted);
+    fn cut_cable(&mut self, index: usize) -> Cable {
+        let cutted = self.cables.remove(index);
+        self.update_hand();
+        self.revealed_cables = self
+            .revealed_cables
+            .iter()
+            .copied()
+            .chain([cutted])
+            .collect();
         cutted
     }
+
+    fn restore_cable(&mut self, index: usize, cable: Cable) {
+        self.cables.insert(index, cable);
+        self.update_hand();
+        let mut revealed = self.revealed_cables.to_vec();
+        revealed.pop();
+        self.revealed_cables = revealed.into();
+    }
+
+    fn role(&self) -> Option<Role> {
+        self.role
+    }
+
+    fn set_role(&mut self, role: Option<Role>) {
+        self.role = role;
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -113,74 +338,551 @@ enum Message {
     Error {
         reason: &'static str,
     },
+    /// Some messages were dropped because the client is too slow, it must fetch `/game/state`
+    Resync,
+    /// The room the client was in doesn't exist anymore
+    Expired,
     Initialize {
         lobby: String,
+        player: <Player as gameplay::Player>::ID,
         players: Vec<PlayerData>,
         team: Team,
+        role: Option<Role>,
+        wire_cutters: <Player as gameplay::Player>::ID,
+        history: Vec<HistoryEntry<<Player as gameplay::Player>::ID>>,
+        theme: Theme,
+        spectators: usize,
+        turn_timer: Option<TurnTimer>,
+        round: usize,
+        /// How many of their own cables the player may still cut, `None` if there is no limit
+        self_cuts: Option<u8>,
+        /// Number of cables in the neutral hand, if there is one
+        neutral: Option<usize>,
+        suspicions: HashMap<<Player as gameplay::Player>::ID, Suspicion>,
+    },
+    /// `Initialize` for someone watching the game, without anything hidden
+    Spectate {
+        lobby: String,
+        players: Vec<PlayerData>,
         wire_cutters: <Player as gameplay::Player>::ID,
+        history: Vec<HistoryEntry<<Player as gameplay::Player>::ID>>,
+        theme: Theme,
+        spectators: usize,
+        round: usize,
+        neutral: Option<usize>,
+    },
+    /// Someone started or stopped watching the game
+    Spectators {
+        spectators: usize,
     },
     Connect {
         player: <Player as gameplay::Player>::ID,
     },
     Disconnect {
         player: <Player as gameplay::Player>::ID,
+        /// seconds left before the game gives up on the player
+        grace: u64,
+    },
+    RoundCountdown {
+        seconds: u64,
     },
     RoundStart {
-        cables: Vec<Cable>,
+        round: usize,
+        cables: Arc<[Cable]>,
+    },
+    /// Every cut of the round that just ended
+    RoundEnd {
+        round: usize,
+        cuts: Vec<HistoryEntry<<Player as gameplay::Player>::ID>>,
+        /// What the `round_reveal` rule tells about each hand, `None` when it reveals nothing
+        uncut: Option<HashMap<<Player as gameplay::Player>::ID, Uncut>>,
+        description: String,
+    },
+    Redistribution {
+        /// number of cables in the hand of each player
+        hands: HashMap<<Player as gameplay::Player>::ID, usize>,
+        neutral: Option<usize>,
+        remaining: usize,
+        description: String,
     },
     Cut {
-        player: <Player as gameplay::Player>::ID,
+        /// `None` for the neutral hand
+        player: Option<<Player as gameplay::Player>::ID>,
         cable: Cable,
+        /// milliseconds clients should wait before showing the cable
+        reveal_delay: u64,
+        dramatic: bool,
+        /// What happened in a sentence, for the clients that can't show it
+        description: String,
+    },
+    /// What a player says they hold, to compare with what gets revealed
+    Claim {
+        player: <Player as gameplay::Player>::ID,
+        claim: Claim,
+        description: String,
+    },
+    /// Sent at the end of each game of a series
+    SeriesScore {
+        series: Series,
+        /// Set once the series is over
+        winner: Option<Team>,
+        description: String,
+    },
+    /// The cutter has `seconds` to confirm they want to cut this cable
+    CutPending {
+        cutter: <Player as gameplay::Player>::ID,
+        player: Option<<Player as gameplay::Player>::ID>,
+        index: usize,
+        seconds: u64,
+    },
+    /// The pending cut wasn't confirmed in time
+    CutCancelled {
+        cutter: <Player as gameplay::Player>::ID,
+    },
+    /// Someone agreed to undo the last cut, the host and the player whose cable was cut both have
+    /// to
+    UndoRequest {
+        player: <Player as gameplay::Player>::ID,
+    },
+    /// The last cut was undone, the cable is back in the hand of `player`
+    Undone {
+        player: <Player as gameplay::Player>::ID,
+        index: usize,
+        wire_cutters: <Player as gameplay::Player>::ID,
+        defusing_remaining: usize,
+        description: String,
+    },
+    /// The role of the player changed the last cut
+    RoleUsed {
+        player: <Player as gameplay::Player>::ID,
+        role: Role,
+        description: String,
+    },
+    /// Only sent to the player whose role found out the team of `player`
+    TeamRevealed {
+        player: <Player as gameplay::Player>::ID,
+        team: Team,
+    },
+    /// Everything that was hidden, sent right before the end of the game
+    Reveal {
+        players: HashMap<<Player as gameplay::Player>::ID, Revealed>,
+        teams: TeamSizes,
+        neutral: Option<Vec<Cable>>,
     },
     Win {
         team: Team,
         players: Vec<<Player as gameplay::Player>::ID>,
+        description: String,
+    },
+    SubstituteRequest {
+        player: <Player as gameplay::Player>::ID,
+        name: String,
+    },
+    Substituted {
+        player: <Player as gameplay::Player>::ID,
+        name: String,
+    },
+    /// The grace of a disconnected player is over, the others may vote them out
+    GraceOver {
+        player: <Player as gameplay::Player>::ID,
+    },
+    /// The game is paused until the player comes back, or for `seconds`
+    AutoPause {
+        player: <Player as gameplay::Player>::ID,
+        seconds: u64,
+        description: String,
     },
+    /// Sent every second of an automatic pause
+    PauseCountdown {
+        player: <Player as gameplay::Player>::ID,
+        remaining: u64,
+    },
+    /// The automatic pause for this player is over
+    Resume {
+        player: <Player as gameplay::Player>::ID,
+    },
+    /// The grace of a disconnected player is over, a bot plays for them
+    Takeover {
+        player: <Player as gameplay::Player>::ID,
+        description: String,
+    },
+    RemovalVote {
+        player: <Player as gameplay::Player>::ID,
+        votes: usize,
+        needed: usize,
+    },
+    KickVote {
+        player: <Player as gameplay::Player>::ID,
+        votes: usize,
+        needed: usize,
+    },
+    /// The others voted the player out, their cables are dealt to the rest of the table
+    Kicked {
+        player: <Player as gameplay::Player>::ID,
+        description: String,
+    },
+    AbortVote {
+        votes: usize,
+        needed: usize,
+    },
+    /// Most of the players cancelled the game, nobody wins
+    Aborted {
+        description: &'static str,
+    },
+    /// The player left the game on purpose, they won't come back
+    Forfeit {
+        player: <Player as gameplay::Player>::ID,
+        description: String,
+    },
+    Removed {
+        player: <Player as gameplay::Player>::ID,
+        wire_cutters: <Player as gameplay::Player>::ID,
+        description: String,
+    },
+    Draw {
+        description: &'static str,
+    },
+    Nudge,
+    /// The holder of the wire cutters ran out of time
+    Timeout {
+        player: <Player as gameplay::Player>::ID,
+        description: String,
+    },
+    Waiting {
+        player: <Player as gameplay::Player>::ID,
+    },
+    Signal {
+        from: <Player as gameplay::Player>::ID,
+        data: String,
+    },
+    Speaking {
+        player: <Player as gameplay::Player>::ID,
+        state: bool,
+    },
+    /// Only sent to the player who set the marker
+    Suspicion {
+        player: <Player as gameplay::Player>::ID,
+        level: Suspicion,
+    },
+    Chat(ChatMessage),
+    /// Only sent to the teammates of the author
+    TeamChat(ChatMessage),
 }
 
 impl Message {
     const fn name(&self) -> &'static str {
         match self {
             Self::Error { .. } => "error",
+            Self::Resync => "resync",
+            Self::Expired => "expired",
             Self::Initialize { .. } => "init",
+            Self::Spectate { .. } => "spectate",
+            Self::Spectators { .. } => "spectators",
             Self::Connect { .. } => "connect",
             Self::Disconnect { .. } => "disconnect",
+            Self::RoundCountdown { .. } => "round_countdown",
             Self::RoundStart { .. } => "round_start",
+            Self::RoundEnd { .. } => "round_end",
+            Self::Redistribution { .. } => "redistribution",
             Self::Cut { .. } => "cut",
+            Self::Claim { .. } => "claim",
+            Self::SeriesScore { .. } => "series_score",
+            Self::CutPending { .. } => "cut_pending",
+            Self::CutCancelled { .. } => "cut_cancelled",
+            Self::UndoRequest { .. } => "undo_request",
+            Self::RoleUsed { .. } => "role_used",
+            Self::TeamRevealed { .. } => "team_revealed",
+            Self::Undone { .. } => "undone",
+            Self::Reveal { .. } => "reveal",
             Self::Win { .. } => "win",
+            Self::SubstituteRequest { .. } => "substitute_request",
+            Self::Substituted { .. } => "substituted",
+            Self::GraceOver { .. } => "grace_over",
+            Self::KickVote { .. } => "kick_vote",
+            Self::Kicked { .. } => "kicked",
+            Self::AbortVote { .. } => "abort_vote",
+            Self::Aborted { .. } => "aborted",
+            Self::AutoPause { .. } => "auto_pause",
+            Self::PauseCountdown { .. } => "pause_countdown",
+            Self::Resume { .. } => "resume",
+            Self::Takeover { .. } => "takeover",
+            Self::RemovalVote { .. } => "removal_vote",
+            Self::Forfeit { .. } => "forfeit",
+            Self::Removed { .. } => "removed",
+            Self::Draw { .. } => "draw",
+            Self::Nudge => "nudge",
+            Self::Timeout { .. } => "timeout",
+            Self::Waiting { .. } => "waiting",
+            Self::Signal { .. } => "signal",
+            Self::Speaking { .. } => "speaking",
+            Self::Suspicion { .. } => "suspicion",
+            Self::Chat(_) => "chat",
+            Self::TeamChat(_) => "team_chat",
         }
     }
 }
 
 impl Protected<Game<Player>> {
-    #[allow(clippy::significant_drop_in_scrutinee)]
     fn broadcast(&self, msg: &Message) {
-        for player in self.lock().players().values() {
-            player.sender.send(msg.clone()).unwrap();
+        self.deliver(msg, |_| true, true);
+    }
+
+    /// Send the message to the players `to` accepts, it stays hidden from the others
+    fn broadcast_to(&self, msg: &Message, to: impl Fn(&Player) -> bool) {
+        self.deliver(msg, to, false);
+    }
+
+    fn deliver(&self, msg: &Message, to: impl Fn(&Player) -> bool, public: bool) {
+        let dead = deliver_locked(&mut self.lock(), msg, to, public);
+        self.bury(dead);
+    }
+
+    /// Handle the players [`deliver_locked`] couldn't reach, once the game is unlocked
+    fn bury(&self, dead: Vec<<Player as gameplay::Player>::ID>) {
+        for id in dead {
+            self.mark_disconnected(id);
+        }
+    }
+
+    /// Give the player a new channel to reconnect to, and tell the others they are gone
+    fn mark_disconnected(&self, id: <Player as gameplay::Player>::ID) {
+        let grace = {
+            let mut game = self.lock();
+            let grace = game.rules().reconnect_grace;
+            let Some(player) = game.get_player_mut(id) else {
+                return;
+            };
+            // nobody was reading the messages, the channel was only cut off for holding them too long
+            let unread = player.receiver.is_some();
+            let (sender, receiver) = channel();
+            player.sender = sender;
+            player.receiver = Some(Mutex::new(receiver));
+            if unread {
+                return;
+            }
+            player.disconnected_at.get_or_insert_with(Instant::now);
+            grace
+        };
+
+        self.broadcast(&Message::Disconnect { player: id, grace });
+    }
+}
+
+/// Send a message without letting go of the game, so nothing can come in between the messages of
+/// one action. Returns the players whose stream is gone, for [`Protected::bury`].
+fn deliver_locked(
+    game: &mut Game<Player>,
+    msg: &Message,
+    to: impl Fn(&Player) -> bool,
+    public: bool,
+) -> Vec<<Player as gameplay::Player>::ID> {
+    // a failed send means the stream of the player stopped without cleaning up after itself
+    let mut dead = Vec::new();
+    game.version += 1;
+    for player in game.players().values().filter(|x| to(x)) {
+        if !public {
+            player.tap.mirror(Some(player.id), msg);
+        }
+        if player.sender.send(msg.clone()).is_err() {
+            dead.push(player.id);
+        }
+    }
+    if let Some(player) = game.players().values().next().filter(|_| public) {
+        player.tap.mirror(None, msg);
+    }
+    dead
+}
+
+/// Receive a copy of every message sent in the game from now on
+pub fn watch(game: &Protected<Game<Player>>) -> Receiver<Tapped> {
+    watch_locked(&mut game.lock())
+}
+
+fn watch_locked(game: &mut Game<Player>) -> Receiver<Tapped> {
+    let (sender, receiver) = channel();
+    let tap = game
+        .players()
+        .values()
+        .next()
+        .map(|x| x.tap.clone())
+        .unwrap_or_default();
+    tap.0.lock().unwrap().push(sender);
+
+    let ids: Vec<_> = game.players().keys().copied().collect();
+    for id in ids {
+        game.get_player_mut(id).unwrap().tap = tap.clone();
+    }
+
+    receiver
+}
+
+/// The messages a player receives when they connect
+fn greeting(
+    game: &Game<Player>,
+    id: <Player as gameplay::Player>::ID,
+    config: &Config,
+) -> [Message; 2] {
+    let player = game.get_player(id).unwrap();
+    [
+        Message::Initialize {
+            lobby: game.name().to_owned(),
+            player: id,
+            players: game.players().values().map(Player::clone_data).collect(),
+            team: player.team(),
+            role: player.role,
+            wire_cutters: game.wire_cutters,
+            history: game.history().to_vec(),
+            theme: config.theme(game.rules().theme.as_deref()),
+            spectators: game.spectators,
+            turn_timer: game.rules().turn_timer.map(|seconds| TurnTimer {
+                seconds,
+                remaining: game.turn_deadline.map_or(seconds, |x| {
+                    x.saturating_duration_since(Instant::now()).as_secs()
+                }),
+            }),
+            round: game.round(),
+            self_cuts: game.self_cuts_left(id),
+            neutral: game.neutral_hand().map(<[Cable]>::len),
+            suspicions: player.suspicions.clone(),
+        },
+        Message::RoundStart {
+            round: game.round(),
+            cables: player.hand(),
+        },
+    ]
+}
+
+/// The events a player would receive if they connected now, or a spectator when `id` is `None`
+pub struct PlayerView {
+    id: Option<<Player as gameplay::Player>::ID>,
+    /// Messages waiting for `delay` to pass since they were sent, starting with the greeting
+    pending: VecDeque<(Instant, Message)>,
+    delay: Duration,
+    receiver: Receiver<Tapped>,
+    closed: bool,
+    _spectating: Option<Spectating>,
+}
+
+/// Counts a spectator in the game until dropped
+struct Spectating(WeakProtected<Game<Player>>);
+
+impl Spectating {
+    fn new(game: &Protected<Game<Player>>) -> Self {
+        let spectators = {
+            let mut game = game.lock();
+            game.spectators += 1;
+            game.spectators
+        };
+        game.broadcast(&Message::Spectators { spectators });
+        Self(game.downgrade())
+    }
+}
+
+impl Drop for Spectating {
+    fn drop(&mut self) {
+        let Some(game) = self.0.upgrade() else {
+            return;
+        };
+        let spectators = {
+            let mut game = game.lock();
+            game.spectators = game.spectators.saturating_sub(1);
+            game.spectators
+        };
+        game.broadcast(&Message::Spectators { spectators });
+    }
+}
+
+impl PlayerView {
+    pub fn new(
+        game: &Protected<Game<Player>>,
+        id: <Player as gameplay::Player>::ID,
+        config: &Config,
+    ) -> Option<Self> {
+        let mut game = game.lock();
+        game.get_player(id)?;
+        Some(Self {
+            id: Some(id),
+            pending: greeting(&game, id, config)
+                .map(|x| (Instant::now(), x))
+                .into(),
+            delay: Duration::ZERO,
+            receiver: watch_locked(&mut game),
+            closed: false,
+            _spectating: None,
+        })
+    }
+
+    /// Everything reaches the spectator `spectator_delay` seconds late, the greeting too
+    pub fn spectator(game: &Protected<Game<Player>>, config: &Config) -> Self {
+        let spectating = Spectating::new(game);
+        let mut game = game.lock();
+        let greeting = Message::Spectate {
+            lobby: game.name().to_owned(),
+            players: game.players().values().map(Player::clone_data).collect(),
+            wire_cutters: game.wire_cutters,
+            history: game.history().to_vec(),
+            theme: config.theme(game.rules().theme.as_deref()),
+            spectators: game.spectators,
+            round: game.round(),
+            neutral: game.neutral_hand().map(<[Cable]>::len),
+        };
+        Self {
+            id: None,
+            pending: [(Instant::now(), greeting)].into(),
+            delay: Duration::from_secs(config.spectator_delay),
+            receiver: watch_locked(&mut game),
+            closed: false,
+            _spectating: Some(spectating),
+        }
+    }
+
+    pub async fn next(&mut self) -> Option<Event> {
+        loop {
+            let due = self.pending.front().map(|x| x.0 + self.delay);
+            if due.is_some_and(|x| x <= Instant::now()) {
+                let (_, msg) = self.pending.pop_front().unwrap();
+                return Some(make_event!(msg));
+            }
+            // the receiver is emptied right away while the messages wait, or the channel would
+            // consider this view too slow
+            let wait = tokio::time::sleep_until(due.unwrap_or_else(Instant::now).into());
+            if self.closed {
+                due?;
+                wait.await;
+                continue;
+            }
+            select! {
+                tapped = self.receiver.recv(Tapped::resync) => match tapped {
+                    Some(tapped) if tapped.to.is_none() || tapped.to == self.id => {
+                        self.pending.push_back((tapped.sent, tapped.message));
+                    }
+                    Some(_) => (),
+                    None => self.closed = true,
+                },
+                () = wait, if due.is_some() => (),
+            }
         }
     }
 }
 
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for Protected<Game<Player>> {
-    type Error = ();
+    type Error = NoRoom;
 
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
         let Some(lobby) = request.cookies().get_private("lobby") else {
-            return Outcome::Error((Status::NotFound, ()));
+            return Outcome::Error((Status::NotFound, NoRoom::NotJoined));
         };
-        let games = request
+        let found = request
             .guard::<&State<GlobalState>>()
             .await
             .unwrap()
             .games
-            .lock()
-            .unwrap();
-
-        games
-            .get(lobby.value())
-            .map(Self::clone)
-            .map_or_else(|| Outcome::Error((Status::NotFound, ())), Outcome::Success)
+            .get(lobby.value());
+        match found {
+            Some(room) => Outcome::Success(room),
+            None => NoRoom::check(request, lobby.value()).await,
+        }
     }
 }
 
@@ -189,113 +891,877 @@ struct ConnectionGuard {
     id: <Player as gameplay::Player>::ID,
     // we need the Option here because the destructor takes self by reference
     // which mean we need Option::take to save the receiver from being destroyed
-    receiver: Option<UnboundedReceiver<Message>>,
-    games: Option<Weak<Mutex<HashMap<String, Protected<Game<Player>>>>>>,
+    receiver: Option<Receiver<Message>>,
+    games: Option<Weak<Registry<Game<Player>>>>,
+    stats: Arc<Mutex<Stats>>,
+    config: Config,
+    janitor: JanitorHandle,
 }
 
 impl Drop for ConnectionGuard {
     fn drop(&mut self) {
-        self.game
-            .broadcast(&Message::Disconnect { player: self.id });
+        let game = self.game.clone();
+        let id = self.id;
+        let receiver = self.receiver.take().unwrap();
+        let games = self.games.take().unwrap();
+        let stats = Arc::clone(&self.stats);
+        let config = self.config.clone();
+        self.janitor
+            .schedule(move || disconnect(&game, id, receiver, games, stats, config));
+    }
+}
 
-        let mut game = self.game.lock();
+fn disconnect(
+    game: &Protected<Game<Player>>,
+    id: <Player as gameplay::Player>::ID,
+    receiver: Receiver<Message>,
+    games: Weak<Registry<Game<Player>>>,
+    stats: Arc<Mutex<Stats>>,
+    config: Config,
+) {
+    let (grace, disconnected_at, already) = {
+        let mut game = game.lock();
+        let grace = game.rules().reconnect_grace;
+        // the player may have been removed from the game while connected, or replaced by a bot
+        let Some(player) = game.get_player_mut(id).filter(|x| !x.forfeited) else {
+            return;
+        };
+        // a stream cut off for being too slow was given a new channel already
+        if player.sender.id() == receiver.id() {
+            player.receiver.replace(Mutex::new(receiver));
+        }
+        let already = player.disconnected_at.is_some();
+        let disconnected_at = *player.disconnected_at.get_or_insert_with(Instant::now);
+        (grace, disconnected_at, already)
+    };
+    if !already {
+        game.broadcast(&Message::Disconnect { player: id, grace });
+        auto_pause(game, id, disconnected_at, &games);
+    }
 
-        game.get_player_mut(self.id)
-            .unwrap()
-            .receiver
-            .replace(Mutex::new(self.receiver.take().unwrap()));
-        let id = game.name().to_owned();
-        let game_empty = !game.players().values().any(PlayingPlayer::connected);
-        drop(game);
-
-        if game_empty {
-            let games = self.games.take().unwrap();
-            tokio::spawn(async move {
-                tokio::time::sleep(Duration::from_secs(60 * 5)).await;
-                let games = games.upgrade()?;
-                {
-                    let mut games = games.lock().unwrap();
-
-                    if !games
-                        .get(&id)?
-                        .lock()
-                        .players()
-                        .values()
-                        .any(PlayingPlayer::connected)
-                    {
-                        games.remove(&id);
-                    }
-                }
+    let game_ref = game.downgrade();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(grace)).await;
+        let games = games.upgrade()?;
+        let game = game_ref.upgrade()?;
+        if !registered(&games, &game)
+            || game.lock().get_player(id)?.disconnected_at != Some(disconnected_at)
+        {
+            return None;
+        }
 
-                Some(())
+        let (anyone_connected, vote_removal, bot_takeover) = {
+            let game = game.lock();
+            (
+                game.players().values().any(PlayingPlayer::connected),
+                game.rules().vote_removal,
+                game.rules().bot_takeover,
+            )
+        };
+        if anyone_connected && bot_takeover {
+            let description = describe::takeover(&game.lock(), id);
+            game.broadcast(&Message::Takeover {
+                player: id,
+                description,
             });
+            take_over(&game, id, disconnected_at, &games, stats, config);
+        } else if anyone_connected && vote_removal {
+            game.broadcast(&Message::GraceOver { player: id });
+        } else if anyone_connected {
+            remove_player(&games, &stats, &config, &game, id);
+        } else {
+            // nobody came back, give up on the whole game
+            unregister(&games, &game);
         }
-    }
+
+        Some(())
+    });
 }
 
-fn send_round(game: &Protected<Game<Player>>) {
-    #[allow(clippy::significant_drop_in_scrutinee)]
-    for player in game.lock().players().values() {
-        player
-            .sender
-            .send(Message::RoundStart {
-                cables: player.cables().to_owned(),
-            })
-            .unwrap();
+fn registered(games: &Registry<Game<Player>>, game: &Protected<Game<Player>>) -> bool {
+    let id = game.lock().name().to_owned();
+    games.get(&id).is_some_and(|x| x.ptr_eq(game))
+}
+
+/// Remove the game from the registry, returns false if it was already removed
+fn unregister(games: &Registry<Game<Player>>, game: &Protected<Game<Player>>) -> bool {
+    let id = game.lock().name().to_owned();
+    let mut games = games.shard(&id);
+    if games.get(&id).is_some_and(|x| x.ptr_eq(game)) {
+        games.remove(&id);
+        true
+    } else {
+        false
     }
 }
 
-fn game_won(
-    state: &State<GlobalState>,
+fn end_game(
+    games: &Registry<Game<Player>>,
+    stats: &Mutex<Stats>,
+    config: &Config,
     game: &Protected<Game<Player>>,
-    team: Team,
-    jar: &CookieJar<'_>,
+    winner: Option<Team>,
 ) {
-    let winning_players = game
-        .lock()
-        .players()
-        .values()
-        .filter(|p| p.team() == team)
-        .map(gameplay::Player::id)
-        .collect();
-    game.broadcast(&Message::Win {
-        team,
-        players: winning_players,
-    });
+    if !unregister(games, game) {
+        return;
+    }
 
-    let lobby = &game.lock().name().to_owned();
-    state.games.lock().unwrap().remove(lobby);
+    let (series, theme) = {
+        let game = game.lock();
+        // the rules are recorded with the new score, for the rematch to continue the series
+        let mut rules = game.rules().clone();
+        if let Some(series) = &mut rules.series {
+            series.record(winner);
+        }
+        stats.lock().unwrap().record(
+            game.name(),
+            game.players().values().chain(game.forfeited()).map(|p| {
+                let key = p.stats_key.as_deref().unwrap_or(&p.name);
+                (
+                    p.id,
+                    (!p.no_stats).then_some((key, p.name.as_str())),
+                    p.team,
+                    p.forfeited,
+                )
+            }),
+            winner,
+            &rules,
+            game.history(),
+        );
+        (rules.series, config.theme(rules.theme.as_deref()))
+    };
 
-    jar.remove_private("lobby");
-    jar.remove_private("id");
-    jar.remove_private("name");
+    let (players, teams, neutral) = {
+        let game = game.lock();
+        let players: HashMap<_, _> = game
+            .players()
+            .values()
+            .map(|p| {
+                let revealed = Revealed {
+                    team: p.team,
+                    role: p.role,
+                    cables: p.hand(),
+                };
+                (p.id, revealed)
+            })
+            .collect();
+        (
+            players,
+            game.team_sizes(),
+            game.neutral_hand().map(<[Cable]>::to_vec),
+        )
+    };
+    game.broadcast(&Message::Reveal {
+        players,
+        teams,
+        neutral,
+    });
+    if let Some(series) = series {
+        game.broadcast(&Message::SeriesScore {
+            winner: series.winner(),
+            description: describe::series(&theme, &series),
+            series,
+        });
+    }
+
+    match winner {
+        Some(team) => broadcast_win(game, config, team),
+        None => game.broadcast(&Message::Draw {
+            description: describe::DRAW,
+        }),
+    }
 }
 
-// WARNING: EventStream is broken with rust 1.74.X, stay on 1.73.X until this is fixed
-#[get("/game/events")]
-#[must_use]
-fn events<'a>(
-    game: Option<Protected<Game<Player>>>,
-    state: &'a State<GlobalState>,
-    jar: &'a CookieJar<'_>,
-    mut end: Shutdown,
-) -> EventStream![Event + 'a] {
-    EventStream! {
-        let Some(game) = game else {
-            yield make_event!(Message::Error {
-                reason: "You are not in a game"
-            });
+/// Take a player out of the game for good and deal their cables to the others
+fn remove_player(
+    games: &Registry<Game<Player>>,
+    stats: &Mutex<Stats>,
+    config: &Config,
+    game: &Protected<Game<Player>>,
+    id: <Player as gameplay::Player>::ID,
+) {
+    let (outcome, wire_cutters, remaining, description) = {
+        let mut game = game.lock();
+        let Some(player) = game.get_player(id) else {
             return;
         };
-
-        let Some(Ok(id)) = jar.get_private("id").map(|x| x.value().parse::<<Player as gameplay::Player>::ID>()) else {
-            yield make_event!(Message::Error {
-                reason: "Invalid player id"
-            });
+        let name = player.name.clone();
+        let outcome = if player.forfeited {
+            game.forfeit(id)
+        } else {
+            game.remove_player(id)
+        };
+        let Some(outcome) = outcome else {
             return;
         };
+        let description = describe::removed(&game, &name);
+        (
+            outcome,
+            game.wire_cutters,
+            game.players().len(),
+            description,
+        )
+    };
+    game.broadcast(&Message::Removed {
+        player: id,
+        wire_cutters,
+        description,
+    });
 
-        if game.lock().get_player(id).is_none() {
+    if remaining < 2 {
+        end_game(games, stats, config, game, None);
+        return;
+    }
+
+    match outcome {
+        CutOutcome::Win(team) => end_game(games, stats, config, game, Some(team)),
+        CutOutcome::RoundEnd => {
+            let msg = {
+                let game = game.lock();
+                round_end(&game, config, game.round(), game.hands())
+            };
+            game.broadcast(&msg);
+            if game.lock().next_round() {
+                end_game(games, stats, config, game, Some(Team::Moriarty));
+            } else {
+                send_round(game);
+            }
+        }
+        CutOutcome::Nothing => send_round(game),
+    }
+}
+
+fn round_end(
+    game: &Game<Player>,
+    config: &Config,
+    round: usize,
+    hands: HashMap<<Player as gameplay::Player>::ID, Vec<Cable>>,
+) -> Message {
+    let (cuts, uncut, description) = {
+        let uncut = match game.rules().round_reveal {
+            RoundReveal::Nothing => None,
+            RoundReveal::Defusing => Some(
+                hands
+                    .into_iter()
+                    .map(|(id, cables)| {
+                        let defusing = cables.iter().filter(|x| **x == Cable::Defusing).count();
+                        (id, Uncut::Defusing(defusing))
+                    })
+                    .collect(),
+            ),
+            RoundReveal::Hands => Some(
+                hands
+                    .into_iter()
+                    .map(|(id, mut cables)| {
+                        cables.sort_unstable();
+                        (id, Uncut::Hands(cables))
+                    })
+                    .collect(),
+            ),
+        };
+        let cuts: Vec<_> = game
+            .history()
+            .iter()
+            .filter(|x| {
+                matches!(x, HistoryEntry::Cut { round: cut, .. } | HistoryEntry::NeutralCut { round: cut, .. } if *cut == round)
+            })
+            .copied()
+            .collect();
+        let theme = config.theme(game.rules().theme.as_deref());
+        let description = describe::round_end(&theme, round, &cuts);
+        (cuts, uncut, description)
+    };
+    Message::RoundEnd {
+        round,
+        cuts,
+        uncut,
+        description,
+    }
+}
+
+fn send_round(game: &Protected<Game<Player>>) {
+    {
+        let game = game.lock();
+        for player in game.players().values() {
+            player.send(Message::RoundStart {
+                round: game.round(),
+                cables: player.hand(),
+            });
+        }
+    }
+
+    let (hands, neutral) = {
+        let game = game.lock();
+        let hands: HashMap<_, _> = game
+            .players()
+            .values()
+            .map(|p| (p.id, p.cables.len()))
+            .collect();
+        (hands, game.neutral_hand().map(<[Cable]>::len))
+    };
+    let remaining = hands.values().sum::<usize>() + neutral.unwrap_or(0);
+    game.broadcast(&Message::Redistribution {
+        hands,
+        neutral,
+        remaining,
+        description: describe::redistribution(remaining),
+    });
+}
+
+/// Announce the next round, and deal the hands once the countdown is over
+fn start_round(
+    game: &Protected<Game<Player>>,
+    games: &Arc<Registry<Game<Player>>>,
+    config: &Config,
+) {
+    let seconds = config.round_countdown;
+    if seconds == 0 {
+        send_round(game);
+        schedule_nudge(game, games, config);
+        return;
+    }
+
+    game.lock().paused = true;
+    game.broadcast(&Message::RoundCountdown { seconds });
+
+    let game_ref = game.downgrade();
+    let games = Arc::clone(games);
+    let config = config.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(seconds)).await;
+        let game = game_ref.upgrade()?;
+        game.lock().paused = false;
+        send_round(&game);
+        schedule_nudge(&game, &games, &config);
+
+        Some(())
+    });
+}
+
+/// Remind the wire cutter holder to play if nobody cut anything for a while, nobody is reminded
+/// while the game is paused
+pub fn schedule_nudge(
+    game: &Protected<Game<Player>>,
+    games: &Arc<Registry<Game<Player>>>,
+    config: &Config,
+) {
+    let Some(delay) = config.nudge_after else {
+        return;
+    };
+    let broadcast_waiting = config.broadcast_waiting;
+    let game_ref = game.downgrade();
+    let games_ref = Arc::downgrade(games);
+    let turn = game.lock().turn();
+
+    common::spawn_supervised(recovery(game, config), async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(delay)).await;
+            let game = game_ref.upgrade()?;
+            let games = games_ref.upgrade()?;
+            // nobody waits for a cut once the game is over
+            if !registered(&games, &game) {
+                break;
+            }
+
+            let holder = {
+                let game = game.lock();
+                if game.turn() != turn {
+                    break;
+                }
+                if game.is_paused() {
+                    continue;
+                }
+                let holder = game.get_player(game.wire_cutters)?;
+                holder.send(Message::Nudge);
+                holder.id
+            };
+
+            if broadcast_waiting {
+                game.broadcast(&Message::Waiting { player: holder });
+            }
+        }
+
+        Some(())
+    });
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Seat<'a> {
+    id: <Player as gameplay::Player>::ID,
+    name: &'a str,
+    team: Team,
+    cables: &'a [Cable],
+}
+
+impl<'a> Seat<'a> {
+    fn new(player: &'a Player) -> Self {
+        Self {
+            id: player.id,
+            name: &player.name,
+            team: player.team,
+            cables: &player.cables,
+        }
+    }
+}
+
+/// First line of a game log, with everything the players only see part of
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Setup<'a> {
+    at: u64,
+    event: &'static str,
+    players: Vec<Seat<'a>>,
+    wire_cutters: <Player as gameplay::Player>::ID,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct LogEntry<'a> {
+    /// Milliseconds since the start of the log
+    at: u64,
+    event: &'static str,
+    #[serde(flatten)]
+    tapped: &'a Tapped,
+}
+
+/// Write every message of the game to `game_log_dir` once it is over, one JSON object per line
+pub fn schedule_log(game: &Protected<Game<Player>>, config: &Config) {
+    let Some(dir) = config.game_log_dir.clone() else {
+        return;
+    };
+    let (mut receiver, setup, path) = {
+        let mut game = game.lock();
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs());
+        let path = dir.join(format!("{}-{started}.jsonl", game.name()));
+        let setup = json::to_string(&Setup {
+            at: 0,
+            event: "setup",
+            players: game.players().values().map(Seat::new).collect(),
+            wire_cutters: game.wire_cutters,
+        })
+        .unwrap();
+        (watch_locked(&mut game), setup, path)
+    };
+
+    tokio::spawn(async move {
+        let start = Instant::now();
+        let mut lines = vec![setup];
+        // the channel closes with the game
+        while let Some(tapped) = receiver.recv(Tapped::resync).await {
+            #[allow(clippy::cast_possible_truncation)]
+            let entry = LogEntry {
+                at: start.elapsed().as_millis() as u64,
+                event: tapped.name(),
+                tapped: &tapped,
+            };
+            lines.push(json::to_string(&entry).unwrap());
+        }
+        lines.push(String::new());
+
+        if let Err(e) = fs::create_dir_all(&dir).and_then(|()| fs::write(&path, lines.join("\n"))) {
+            rocket::warn!(
+                "Could not write the log of the game to {}: {e}",
+                path.display()
+            );
+        }
+    });
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Snapshot<'a> {
+    code: &'a str,
+    players: Vec<Seat<'a>>,
+    wire_cutters: <Player as gameplay::Player>::ID,
+    host: <Player as gameplay::Player>::ID,
+    paused: bool,
+    turn: usize,
+    defusing_remaining: usize,
+    history: &'a [HistoryEntry<<Player as gameplay::Player>::ID>],
+}
+
+/// Recover `game` like after a failed request, for the background tasks changing it
+fn recovery(game: &Protected<Game<Player>>, config: &Config) -> impl FnOnce() + Send + 'static {
+    let game_ref = game.downgrade();
+    let config = config.clone();
+    move || {
+        if let Some(game) = game_ref.upgrade() {
+            recover(&game, &config);
+        }
+    }
+}
+
+/// Save the game to `crash_dir` after a failed request, and make the clients fetch its state again
+pub fn recover(game: &Protected<Game<Player>>, config: &Config) {
+    if let Some(dir) = &config.crash_dir {
+        let locked = game.lock();
+        let taken_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs());
+        let path = dir.join(format!("{}-{taken_at}.json", locked.name()));
+        let snapshot = json::to_pretty_string(&Snapshot {
+            code: locked.name(),
+            players: locked.players().values().map(Seat::new).collect(),
+            wire_cutters: locked.wire_cutters,
+            host: locked.host,
+            paused: locked.paused,
+            turn: locked.turn(),
+            defusing_remaining: locked.defusing_remaining(),
+            history: locked.history(),
+        })
+        .unwrap();
+        drop(locked);
+
+        match fs::create_dir_all(dir).and_then(|()| fs::write(&path, snapshot)) {
+            Ok(()) => rocket::warn!("Saved the game to {}", path.display()),
+            Err(e) => rocket::warn!("Could not save the game to {}: {e}", path.display()),
+        }
+    }
+
+    game.broadcast(&Message::Resync);
+}
+
+/// Drop the game if nobody showed up shortly after it was created
+pub fn schedule_abandon_check(game: &Protected<Game<Player>>, games: &Arc<Registry<Game<Player>>>) {
+    let game_ref = game.downgrade();
+    let games_ref = Arc::downgrade(games);
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(120)).await;
+        let game = game_ref.upgrade()?;
+        let games = games_ref.upgrade()?;
+
+        if !game.lock().players().values().any(PlayingPlayer::connected) {
+            unregister(&games, &game);
+        }
+
+        Some(())
+    });
+}
+
+/// Stop the game once it has been running for too long
+pub fn schedule_time_limit(game: &Protected<Game<Player>>, state: &GlobalState, config: &Config) {
+    let Some(duration) = config.max_game_duration else {
+        return;
+    };
+    let winner = config.timeout_outcome.winner();
+    let game_ref = game.downgrade();
+    let games_ref = Arc::downgrade(&state.games);
+    let stats = Arc::clone(&state.stats);
+    let on_panic = recovery(game, config);
+    let config = config.clone();
+
+    common::spawn_supervised(on_panic, async move {
+        tokio::time::sleep(Duration::from_secs(duration)).await;
+        let game = game_ref.upgrade()?;
+        let games = games_ref.upgrade()?;
+        end_game(&games, &stats, &config, &game, winner);
+
+        Some(())
+    });
+}
+
+/// Cut a random cable for the holder of the wire cutters once they spent `turn_timer` seconds on
+/// their turn. A pause gives them the full time again.
+pub fn schedule_turn_timer(game: &Protected<Game<Player>>, state: &GlobalState, config: &Config) {
+    let Some(seconds) = game.lock().rules().turn_timer else {
+        return;
+    };
+    let game_ref = game.downgrade();
+    let games_ref = Arc::downgrade(&state.games);
+    let stats = Arc::clone(&state.stats);
+    let on_panic = recovery(game, config);
+    let config = config.clone();
+
+    common::spawn_supervised(on_panic, async move {
+        let mut turn = None;
+        loop {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            let game = game_ref.upgrade()?;
+            let games = games_ref.upgrade()?;
+            if !registered(&games, &game) {
+                break;
+            }
+
+            let timed_out = {
+                let mut locked = game.lock();
+                if locked.is_paused() {
+                    locked.turn_deadline = None;
+                    None
+                } else if turn != Some(locked.turn()) || locked.turn_deadline.is_none() {
+                    turn = Some(locked.turn());
+                    locked.turn_deadline = Some(Instant::now() + Duration::from_secs(seconds));
+                    None
+                } else if locked.turn_deadline.is_some_and(|x| x <= Instant::now()) {
+                    locked.turn_deadline = None;
+                    let holder = locked.wire_cutters;
+                    let choice =
+                        View::new(&locked, holder).and_then(|view| RandomAgent.decide(&view));
+                    Some((holder, choice))
+                } else {
+                    None
+                }
+            };
+            let Some((holder, choice)) = timed_out else {
+                continue;
+            };
+
+            let description = describe::timeout(&game.lock(), holder);
+            game.broadcast(&Message::Timeout {
+                player: holder,
+                description,
+            });
+            if let Some((player, index)) = choice {
+                if apply_cut(&games, &stats, &config, &game, holder, Some(player), index)
+                    == Ok(true)
+                {
+                    break;
+                }
+            }
+        }
+
+        Some(())
+    });
+}
+
+/// Pause the game while the player is away, for `auto_pause` seconds at most
+fn auto_pause(
+    game: &Protected<Game<Player>>,
+    id: <Player as gameplay::Player>::ID,
+    disconnected_at: Instant,
+    games: &Weak<Registry<Game<Player>>>,
+) {
+    let (seconds, description) = {
+        let mut game = game.lock();
+        let Some(seconds) = game.rules().auto_pause else {
+            return;
+        };
+        game.waiting_for.insert(id, disconnected_at);
+        (seconds, describe::auto_pause(&game, id, seconds))
+    };
+    game.broadcast(&Message::AutoPause {
+        player: id,
+        seconds,
+        description,
+    });
+
+    let game_ref = game.downgrade();
+    let games = Weak::clone(games);
+    tokio::spawn(async move {
+        for remaining in (0..seconds).rev() {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            let game = game_ref.upgrade()?;
+            let back = game
+                .lock()
+                .get_player(id)
+                .is_none_or(|x| x.disconnected_at != Some(disconnected_at));
+            if back || remaining == 0 {
+                break;
+            }
+            game.broadcast(&Message::PauseCountdown {
+                player: id,
+                remaining,
+            });
+        }
+
+        let game = game_ref.upgrade()?;
+        let games = games.upgrade()?;
+        {
+            // the player may have dropped again since, the pause is the new disconnection's now
+            let mut game = game.lock();
+            if game.waiting_for.get(&id) != Some(&disconnected_at) {
+                return None;
+            }
+            game.waiting_for.remove(&id);
+        }
+        if registered(&games, &game) {
+            game.broadcast(&Message::Resume { player: id });
+        }
+
+        Some(())
+    });
+}
+
+/// Cut a random cable for the disconnected player whenever they hold the wire cutters, until they
+/// come back
+fn take_over(
+    game: &Protected<Game<Player>>,
+    id: <Player as gameplay::Player>::ID,
+    disconnected_at: Instant,
+    games: &Arc<Registry<Game<Player>>>,
+    stats: Arc<Mutex<Stats>>,
+    config: Config,
+) {
+    let game_ref = game.downgrade();
+    let games_ref = Arc::downgrade(games);
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(config.bot_delay)).await;
+            let game = game_ref.upgrade()?;
+            let games = games_ref.upgrade()?;
+            if !registered(&games, &game) {
+                break;
+            }
+
+            let choice = {
+                let game = game.lock();
+                if game.get_player(id)?.disconnected_at != Some(disconnected_at) {
+                    break;
+                }
+                if game.is_paused() || game.wire_cutters != id {
+                    continue;
+                }
+                View::new(&game, id).and_then(|view| RandomAgent.decide(&view))
+            };
+            if let Some((player, index)) = choice {
+                if apply_cut(&games, &stats, &config, &game, id, Some(player), index) == Ok(true) {
+                    break;
+                }
+            }
+        }
+
+        Some(())
+    });
+}
+
+fn broadcast_win(game: &Protected<Game<Player>>, config: &Config, team: Team) {
+    let (winning_players, description) = {
+        let game = game.lock();
+        let theme = config.theme(game.rules().theme.as_deref());
+        let winning_players = game
+            .players()
+            .values()
+            .filter(|p| p.team() == team)
+            .map(gameplay::Player::id)
+            .collect();
+        (winning_players, describe::win(&game, &theme, team))
+    };
+    game.broadcast(&Message::Win {
+        team,
+        players: winning_players,
+        description,
+    });
+}
+
+/// Play the seat `id` from the server instead of an event stream. `decide` is asked for a cut
+/// whenever the seat holds the wire cutters, a random one is made if it has no valid answer.
+pub fn play_remotely<F, Fut>(
+    game: Protected<Game<Player>>,
+    id: <Player as gameplay::Player>::ID,
+    games: Arc<Registry<Game<Player>>>,
+    stats: Arc<Mutex<Stats>>,
+    config: Config,
+    decide: F,
+) where
+    F: Fn(GameState) -> Fut + Send + 'static,
+    Fut: Future<Output = Option<(<Player as gameplay::Player>::ID, Option<usize>)>> + Send,
+{
+    let Some(receiver) = game
+        .lock()
+        .get_player_mut(id)
+        .and_then(|player| player.receiver.take())
+    else {
+        return;
+    };
+    let mut receiver = receiver.into_inner().unwrap();
+    game.broadcast(&Message::Connect { player: id });
+
+    common::spawn_supervised(recovery(&game, &config), async move {
+        // every message may mean it's now the turn of the seat
+        while receiver.recv(|| Message::Resync).await.is_some() {
+            if !registered(&games, &game) {
+                break;
+            }
+            let state = {
+                let game = game.lock();
+                if game.is_paused() || game.wire_cutters != id {
+                    continue;
+                }
+                GameState::new(&game, id)
+            };
+            let Some(state) = state else {
+                break;
+            };
+
+            let choice = decide(state).await.and_then(|(player, index)| {
+                let index = match index {
+                    Some(index) => index,
+                    None => {
+                        let count = game.lock().get_player(player)?.cables.len();
+                        (count > 0).then(|| thread_rng().gen_range(0..count))?
+                    }
+                };
+                Some((player, index))
+            });
+            let mut result = choice.map(|(player, index)| {
+                apply_cut(&games, &stats, &config, &game, id, Some(player), index)
+            });
+            if !matches!(result, Some(Ok(_))) {
+                let fallback = {
+                    let game = game.lock();
+                    View::new(&game, id).and_then(|view| RandomAgent.decide(&view))
+                };
+                result = fallback.map(|(player, index)| {
+                    apply_cut(&games, &stats, &config, &game, id, Some(player), index)
+                });
+            }
+
+            if result == Some(Ok(true)) {
+                break;
+            }
+        }
+    });
+}
+
+// WARNING: EventStream is broken with rust 1.74.X, stay on 1.73.X until this is fixed
+#[get("/game/events")]
+#[must_use]
+fn events<'a>(
+    game: Result<Protected<Game<Player>>, NoRoom>,
+    state: &'a State<GlobalState>,
+    config: &'a State<Config>,
+    id: Result<PlayerId, &'static str>,
+    slot: Result<StreamSlot, Refused>,
+    mut end: Shutdown,
+) -> Shed<EventStream![Event + 'a]> {
+    Shed::new(slot.as_ref().err().copied(), EventStream! {
+        let _slot = match slot {
+            Ok(slot) => slot,
+            Err(refused) => {
+                let event = make_event!(refused);
+                yield match refused.retry_after() {
+                    Some(seconds) => event.with_retry(Duration::from_secs(seconds)),
+                    None => event,
+                };
+                return;
+            }
+        };
+        let game = match game {
+            Ok(game) => game,
+            Err(NoRoom::Expired) => {
+                yield make_event!(Message::Expired);
+                return;
+            }
+            Err(_) => {
+                yield make_event!(Message::Error {
+                    reason: "You are not in a game"
+                });
+                return;
+            }
+        };
+
+        let id = match id {
+            Ok(id) => id.0,
+            Err(reason) => {
+                yield make_event!(Message::Error { reason });
+                return;
+            }
+        };
+
+        if game.lock().get_player(id).is_none() {
             yield make_event!(Message::Error {
                     reason: "You are not part of this game",
                 });
@@ -308,23 +1774,25 @@ fn events<'a>(
                 });
             return;
         };
+        {
+            let mut game = game.lock();
+            let player = game.get_player_mut(id).unwrap();
+            player.disconnected_at = None;
+            player.removal_votes.clear();
+        }
         let mut receiver = receiver.into_inner().unwrap();
-        // discard all previous messages
-        while receiver.try_recv().is_ok() {}
-
-        let msg = {
+        let _tracked = {
             let game = game.lock();
-            let lobby_name = game.name().to_owned();
-            let player_list = game.players().values().map(Player::clone_data).collect();
-            let team = game.get_player(id).unwrap().team();
-            let wire_cutters = game.wire_cutters;
-            drop(game);
-            Message::Initialize { lobby: lobby_name, players: player_list, team, wire_cutters }
+            let depth = game.get_player(id).unwrap().sender.depth();
+            state.connections.track(Kind::Game, game.name(), id, depth)
         };
-        yield make_event!(msg);
-        yield make_event!(&Message::RoundStart {
-            cables: game.lock().get_player(id).unwrap().cables().to_owned()
-        });
+        // discard all previous messages
+        receiver.clear();
+
+        let greeting = greeting(&game.lock(), id, config);
+        for msg in greeting {
+            yield make_event!(msg);
+        }
 
         game.broadcast(&Message::Connect { player: id });
 
@@ -333,83 +1801,1425 @@ fn events<'a>(
             id,
             receiver: Some(receiver),
             games: Some(Arc::downgrade(&state.games)),
+            stats: Arc::clone(&state.stats),
+            config: Config::clone(config),
+            janitor: state.janitor.handle(),
         };
 
         let receiver = guard.receiver.as_mut().unwrap();
 
-        loop {
-            let Some(msg) = (select! {
-                msg = receiver.recv() => msg,
-                () = &mut end => {
-                    yield make_event!(Message::Error {
-                        reason: "Server closed",
-                    });
-                    break
-                },
-            }) else { break; };
-
+        while let Some(msg) = select! {
+            msg = receiver.recv(|| Message::Resync) => msg,
+            () = &mut end => {
+                yield make_event!(Message::Error {
+                    reason: "Server closed",
+                });
+                return;
+            },
+        } {
             yield make_event!(msg.clone());
 
-            if matches!(msg, Message::Win { .. }) {
+            if matches!(msg, Message::Win { .. } | Message::Draw { .. } | Message::Aborted { .. }) {
+                break;
+            }
+            if matches!(msg, Message::Kicked { player, .. } if player == id) {
                 break;
             }
         }
-    }.heartbeat(Duration::from_secs(5))
+    }.heartbeat(Duration::from_secs(5)))
+}
+
+pub const NO_GAME: &str = "There is no game with this code";
+
+/// The public messages of a game, starting with a `spectate` event. The stream ends with the game
+/// and has to be opened again after a `resync` event.
+pub fn spectator_stream(
+    view: Option<PlayerView>,
+    slot: Result<StreamSlot, Refused>,
+    mut end: Shutdown,
+) -> Shed<EventStream![]> {
+    Shed::new(
+        slot.as_ref().err().copied(),
+        EventStream! {
+            let _slot = match slot {
+                Ok(slot) => slot,
+                Err(refused) => {
+                    let event = make_event!(refused);
+                    yield match refused.retry_after() {
+                        Some(seconds) => event.with_retry(Duration::from_secs(seconds)),
+                        None => event,
+                    };
+                    return;
+                }
+            };
+            let Some(mut view) = view else {
+                yield make_event!(Message::Error { reason: NO_GAME });
+                return;
+            };
+            while let Some(event) = select! {
+                event = view.next() => event,
+                () = &mut end => None,
+            } {
+                yield event;
+            }
+        }
+        .heartbeat(Duration::from_secs(5)),
+    )
+}
+
+/// Watch a running game without taking part in it, the hands of the players stay hidden
+#[get("/game/spectate?<lobby>")]
+#[allow(clippy::needless_pass_by_value)]
+fn spectate(
+    lobby: &str,
+    state: &State<GlobalState>,
+    config: &State<Config>,
+    slot: Result<StreamSlot, Refused>,
+    end: Shutdown,
+) -> Shed<EventStream![]> {
+    let view = state
+        .games
+        .get(&lobby.to_uppercase())
+        .map(|game| PlayerView::spectator(&game, config));
+    spectator_stream(view, slot, end)
 }
 
-#[get("/game/cut?<player>")]
+/// Cut the cable at `index` in the face-down hand of `player`. Hands are only shuffled when they
+/// are dealt, the positions stay the same for the whole round. With `confirm_cuts`, the cut only
+/// happens once `/game/cut/confirm` is called.
+#[get("/game/cut?<player>&<index>")]
 #[allow(clippy::needless_pass_by_value)]
 fn cut(
     player: <Player as gameplay::Player>::ID,
+    index: usize,
     game: Protected<Game<Player>>,
     state: &State<GlobalState>,
-    jar: &CookieJar<'_>,
+    config: &State<Config>,
+    id: PlayerId,
 ) -> Result<(), BadRequest<&'static str>> {
-    let Some(Ok(id)) = jar
-        .get_private("id")
-        .map(|x| x.value().parse::<<Player as gameplay::Player>::ID>())
-    else {
-        return Err(BadRequest("Invalid player id"));
-    };
+    let id = id.0;
 
-    if game.lock().get_player(id).is_none() {
-        return Err(BadRequest("You are not part of this game"));
-    };
+    if game.lock().rules().confirm_cuts {
+        return propose_cut(&game, config, id, Some(player), index).map_err(BadRequest);
+    }
 
-    if game.lock().get_player(player).is_none() {
-        return Err(BadRequest(
-            "The player you specified is not part of this game",
-        ));
-    };
+    // the cookies stay once the game is over, for `/game/rematch`
+    apply_cut(
+        &state.games,
+        &state.stats,
+        config,
+        &game,
+        id,
+        Some(player),
+        index,
+    )
+    .map_err(BadRequest)?;
 
-    let result = game.lock().cut(id, player);
-    let (cable, outcome) = match result {
-        Ok(x) => x,
-        Err(errors::Cut::DontHaveWireCutter) => {
-            return Err(BadRequest("You don't have the wire cutter"))
-        }
-        Err(errors::Cut::CannotSelfCut) => {
-            return Err(BadRequest("You can't cut one of your own cables"))
-        }
-    };
+    Ok(())
+}
 
-    game.broadcast(&Message::Cut { player, cable });
+/// Cut the cable at `index` in the neutral hand of a small table, the wire cutters stay with the
+/// cutter. Same as `/game/cut` otherwise.
+#[get("/game/cut/neutral?<index>")]
+#[allow(clippy::needless_pass_by_value)]
+fn cut_neutral(
+    index: usize,
+    game: Protected<Game<Player>>,
+    state: &State<GlobalState>,
+    config: &State<Config>,
+    id: PlayerId,
+) -> Result<(), BadRequest<&'static str>> {
+    let id = id.0;
 
-    match outcome {
-        CutOutcome::Nothing => (),
-        CutOutcome::Win(team) => game_won(state, &game, team, jar),
-        CutOutcome::RoundEnd => {
-            if game.lock().next_round() {
-                game_won(state, &game, Team::Moriarty, jar);
-            } else {
-                send_round(&game);
-            }
-        }
+    if game.lock().rules().confirm_cuts {
+        return propose_cut(&game, config, id, None, index).map_err(BadRequest);
     }
 
+    apply_cut(&state.games, &state.stats, config, &game, id, None, index).map_err(BadRequest)?;
+
     Ok(())
 }
 
-pub fn routes() -> Vec<rocket::Route> {
-    routes![events, cut]
+/// Remember the cut until it is confirmed, or cancel it after `cut_confirm_delay` seconds
+fn propose_cut(
+    game: &Protected<Game<Player>>,
+    config: &Config,
+    id: <Player as gameplay::Player>::ID,
+    player: Option<<Player as gameplay::Player>::ID>,
+    index: usize,
+) -> Result<(), &'static str> {
+    let since = Instant::now();
+    {
+        let mut game = game.lock();
+        let cables = match player {
+            Some(player) => {
+                game.check_cut(id, player).map_err(cut_error)?;
+                game.get_player(player).map(|x| x.cables.len())
+            }
+            None => {
+                game.check_neutral_cut(id).map_err(cut_error)?;
+                game.neutral_hand().map(<[Cable]>::len)
+            }
+        };
+        if cables.is_none_or(|x| index >= x) {
+            return Err(cut_error(errors::Cut::InvalidCable));
+        }
+        game.get_player_mut(id).unwrap().pending_cut = Some(PendingCut {
+            player,
+            index,
+            since,
+        });
+    }
+    let seconds = config.cut_confirm_delay;
+    game.broadcast(&Message::CutPending {
+        cutter: id,
+        player,
+        index,
+        seconds,
+    });
+
+    let game_ref = game.downgrade();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(seconds)).await;
+        let game = game_ref.upgrade()?;
+        {
+            let mut game = game.lock();
+            let cutter = game.get_player_mut(id)?;
+            if cutter.pending_cut.is_none_or(|x| x.since != since) {
+                return None;
+            }
+            cutter.pending_cut = None;
+        }
+        game.broadcast(&Message::CutCancelled { cutter: id });
+
+        Some(())
+    });
+
+    Ok(())
+}
+
+#[get("/game/cut/confirm")]
+#[allow(clippy::needless_pass_by_value)]
+fn confirm_cut(
+    game: Protected<Game<Player>>,
+    state: &State<GlobalState>,
+    config: &State<Config>,
+    id: PlayerId,
+) -> Result<(), BadRequest<&'static str>> {
+    let id = id.0;
+
+    let Some(pending) = game
+        .lock()
+        .get_player_mut(id)
+        .and_then(|x| x.pending_cut.take())
+    else {
+        return Err(BadRequest("You have no cut to confirm"));
+    };
+    apply_cut(
+        &state.games,
+        &state.stats,
+        config,
+        &game,
+        id,
+        pending.player,
+        pending.index,
+    )
+    .map_err(BadRequest)?;
+
+    Ok(())
+}
+
+/// Agree to undo the last cut, it happens once both the host and the player whose cable was cut
+/// agreed. Cuts ending a round or the game can't be undone.
+#[get("/game/undo")]
+#[allow(clippy::needless_pass_by_value)]
+fn undo(game: Protected<Game<Player>>, id: PlayerId) -> Result<(), BadRequest<&'static str>> {
+    let id = id.0;
+
+    let undone = {
+        let mut game = game.lock();
+        let Some(last) = game.last_cut().cloned() else {
+            return Err(BadRequest("There is no cut to undo"));
+        };
+        if id != game.host && id != last.cutted {
+            return Err(BadRequest(
+                "Only the host and the player whose cable was cut can undo it",
+            ));
+        }
+        game.get_player_mut(id).unwrap().undo_consent = Some(last.turn);
+        let agreed = [game.host, last.cutted].iter().all(|x| {
+            game.get_player(*x)
+                .is_some_and(|x| x.undo_consent == Some(last.turn))
+        });
+        if agreed {
+            game.undo().map_err(|e| match e {
+                errors::Undo::NothingToUndo => BadRequest("There is no cut to undo"),
+            })?;
+            // the next cut gets the same turn, it needs its own consents
+            let ids: Vec<_> = game.players().keys().copied().collect();
+            for id in ids {
+                game.get_player_mut(id).unwrap().undo_consent = None;
+            }
+            Some(Message::Undone {
+                player: last.cutted,
+                index: last.index,
+                wire_cutters: game.wire_cutters,
+                defusing_remaining: game.defusing_remaining(),
+                description: describe::undone(&game, last.cutting, last.cutted),
+            })
+        } else {
+            None
+        }
+    };
+
+    game.broadcast(&undone.unwrap_or(Message::UndoRequest { player: id }));
+
+    Ok(())
+}
+
+const fn cut_error(error: errors::Cut) -> &'static str {
+    match error {
+        errors::Cut::DontHaveWireCutter => "You don't have the wire cutter",
+        errors::Cut::CannotSelfCut => "You can't cut one of your own cables",
+        errors::Cut::InvalidCable => "This player doesn't have a cable there",
+        errors::Cut::CannotCutBack => "You can't cut the player who just cut you",
+        errors::Cut::Paused => "The game is paused",
+        errors::Cut::NotInGame => "You are not part of this game",
+        errors::Cut::UnknownPlayer => "The player you specified is not part of this game",
+        errors::Cut::NoNeutralHand => "There is no neutral hand in this game",
+        errors::Cut::NeutralAlreadyCut => {
+            "You already cut the neutral hand, you must cut a player now"
+        }
+    }
+}
+
+/// Make `id` cut a cable of `player`, or of the neutral hand when `None`, and announce what
+/// follows, returns whether the game is over
+fn apply_cut(
+    games: &Arc<Registry<Game<Player>>>,
+    stats: &Mutex<Stats>,
+    config: &Config,
+    game: &Protected<Game<Player>>,
+    id: <Player as gameplay::Player>::ID,
+    player: Option<<Player as gameplay::Player>::ID>,
+    index: usize,
+) -> Result<bool, &'static str> {
+    let (then, dead) = {
+        let mut locked = game.lock();
+        let events = match player {
+            Some(player) => locked.handle_cut(id, player, index),
+            None => locked.handle_neutral_cut(id, index),
+        }
+        .map_err(cut_error)?;
+        // nobody may cut before the countdown of the new round starts
+        if config.round_countdown > 0 && events.iter().any(|x| matches!(x, CutEvent::NextRound)) {
+            locked.paused = true;
+        }
+
+        // everything is sent before letting go of the game, for the messages to describe it as
+        // it was right after the cut
+        let theme = config.theme(locked.rules().theme.as_deref());
+        let mut then = None;
+        let mut dead = Vec::new();
+        for event in events {
+            let (msg, to) = match event {
+                CutEvent::Cut {
+                    cable,
+                    defusing_remaining,
+                } => {
+                    let dramatic = defusing_remaining <= config.dramatic_threshold;
+                    let description =
+                        describe::cut(&locked, &theme, id, player, cable, defusing_remaining);
+                    let msg = Message::Cut {
+                        player,
+                        cable,
+                        reveal_delay: if dramatic {
+                            config.dramatic_reveal_delay
+                        } else {
+                            config.reveal_delay
+                        },
+                        dramatic,
+                        description,
+                    };
+                    (msg, None)
+                }
+                CutEvent::RoundEnd { round, hands } => {
+                    (round_end(&locked, config, round, hands), None)
+                }
+                CutEvent::Role {
+                    holder,
+                    role,
+                    effect,
+                } => match effect {
+                    Effect::Replace(cable) => {
+                        let description = describe::role_used(&locked, &theme, holder, role, cable);
+                        let msg = Message::RoleUsed {
+                            player: holder,
+                            role,
+                            description,
+                        };
+                        (msg, None)
+                    }
+                    Effect::RevealTeam { to, player, team } => {
+                        (Message::TeamRevealed { player, team }, Some(to))
+                    }
+                },
+                CutEvent::Win(_) | CutEvent::NextRound => {
+                    then = Some(event);
+                    break;
+                }
+            };
+            dead.extend(match to {
+                Some(to) => deliver_locked(&mut locked, &msg, |x| x.id == to, false),
+                None => deliver_locked(&mut locked, &msg, |_| true, true),
+            });
+        }
+        (then, dead)
+    };
+    game.bury(dead);
+
+    match then {
+        Some(CutEvent::Win(team)) => {
+            end_game(games, stats, config, game, Some(team));
+            return Ok(true);
+        }
+        Some(CutEvent::NextRound) => {
+            start_round(game, games, config);
+            return Ok(false);
+        }
+        _ => (),
+    }
+    schedule_nudge(game, games, config);
+
+    Ok(false)
+}
+
+/// Tell everyone how many defusing cables and whether the bomb are in the hand of the player, which
+/// may be a bluff. A new claim replaces the previous one of the round.
+#[get("/game/claim?<defusing>&<bomb>")]
+#[allow(clippy::needless_pass_by_value)]
+fn claim(
+    defusing: usize,
+    bomb: bool,
+    game: Protected<Game<Player>>,
+    config: &State<Config>,
+    id: PlayerId,
+) -> Result<(), BadRequest<&'static str>> {
+    let id = id.0;
+
+    let claim = Claim { defusing, bomb };
+    let description = {
+        let mut game = game.lock();
+        game.claim(id, claim).map_err(|e| match e {
+            errors::Claim::NotInGame => BadRequest("You are not part of this game"),
+            errors::Claim::TooManyCables => BadRequest("You don't have that many cables"),
+        })?;
+        let theme = config.theme(game.rules().theme.as_deref());
+        describe::claim(&game, &theme, id, claim)
+    };
+    game.broadcast(&Message::Claim {
+        player: id,
+        claim,
+        description,
+    });
+
+    Ok(())
+}
+
+/// Send the players of the game that just ended to a new lobby together, opened by the first of
+/// them to ask. They keep their seat id and name.
+#[get("/game/rematch")]
+#[allow(clippy::needless_pass_by_value)]
+fn rematch(
+    state: &State<GlobalState>,
+    config: &State<Config>,
+    jar: &CookieJar<'_>,
+) -> Result<Redirect, BadRequest<&'static str>> {
+    let (Some(code), Some(Ok(id))) = (
+        jar.get_private("lobby"),
+        jar.get_private("id")
+            .map(|x| x.value().parse::<<Player as gameplay::Player>::ID>()),
+    ) else {
+        return Err(BadRequest("You didn't play any game"));
+    };
+    if state.games.get(code.value()).is_some() {
+        return Err(BadRequest("The game isn't over"));
+    }
+
+    let Some((record, mut rules)) = state
+        .stats
+        .lock()
+        .unwrap()
+        .games()
+        .iter()
+        .rev()
+        .find(|x| x.code == code.value())
+        .filter(|x| x.players.iter().any(|x| x.id == id))
+        .map(|x| (x.id, x.rules.clone()))
+    else {
+        return Err(BadRequest("You didn't play in this game"));
+    };
+
+    let lobby = {
+        let mut rematches = state.rematches.lock().unwrap();
+        rematches.retain(|_, lobby| state.lobbys.get(lobby).is_some());
+        rematches
+            .entry(record)
+            .or_insert_with(|| {
+                // a daily challenge can only be played once
+                rules.daily = None;
+                rules.seed = None;
+                let party = jar.get_private("party");
+                lobby::open(state, config, None, |lobby| {
+                    lobby.rules = rules;
+                    if let Some(party) = party {
+                        party.value().clone_into(&mut lobby.party);
+                    }
+                })
+            })
+            .clone()
+    };
+    jar.add_private(("lobby", lobby));
+
+    Ok(Redirect::to("/lobby.html"))
+}
+
+/// Give up on the game, the others get the cables instead of waiting for the player to come back
+#[get("/game/leave")]
+#[must_use]
+fn leave(
+    game: Option<Protected<Game<Player>>>,
+    state: &State<GlobalState>,
+    config: &State<Config>,
+    jar: &CookieJar<'_>,
+    id: Result<PlayerId, &'static str>,
+) -> Redirect {
+    if let (Some(game), Ok(PlayerId(id))) = (game, id) {
+        if game.lock().get_player(id).is_some() {
+            let description = describe::forfeit(&game.lock(), id);
+            game.broadcast(&Message::Forfeit {
+                player: id,
+                description,
+            });
+            forfeit(state, config, &game, id);
+        }
+    }
+
+    jar.remove_private("lobby");
+    jar.remove_private("id");
+    jar.remove_private("seat");
+    jar.remove_private("name");
+
+    Redirect::to("/gameMenu.html")
+}
+
+/// Take the player out of the game with a loss, a bot finishes it for them if the rules say so
+fn forfeit(
+    state: &GlobalState,
+    config: &Config,
+    game: &Protected<Game<Player>>,
+    id: <Player as gameplay::Player>::ID,
+) {
+    let takeover = {
+        let mut game = game.lock();
+        let takeover = game.rules().forfeit_takeover;
+        let Some(player) = game.get_player_mut(id) else {
+            return;
+        };
+        player.forfeited = true;
+        if takeover {
+            // the stream of the player is left behind, the bot reads the messages instead
+            let (sender, receiver) = channel();
+            player.sender = sender;
+            player.receiver = Some(Mutex::new(receiver));
+            player.disconnected_at = None;
+        }
+        takeover
+    };
+
+    if takeover {
+        let games = Arc::clone(&state.games);
+        let stats = Arc::clone(&state.stats);
+        bot::play(game.clone(), id, games, stats, config.clone());
+    } else {
+        remove_player(&state.games, &state.stats, config, game, id);
+    }
+}
+
+/// How many of `voters` players must agree for the table to decide something: most of them, and
+/// never a single one when someone else could disagree
+fn majority(voters: usize) -> usize {
+    (voters / 2 + 1).max(voters.min(2))
+}
+
+/// The players who voted and how many must, among the ones who didn't abandon the game besides
+/// `target`
+fn tally(
+    game: &Game<Player>,
+    target: Option<<Player as gameplay::Player>::ID>,
+    voted: impl Fn(&Player) -> bool,
+) -> (usize, usize) {
+    let grace = Duration::from_secs(game.rules().reconnect_grace);
+    let voters: Vec<_> = game
+        .players()
+        .values()
+        .filter(|p| Some(p.id) != target && !p.abandoned(grace))
+        .collect();
+    let votes = voters.iter().filter(|p| voted(p)).count();
+    (votes, majority(voters.len()))
+}
+
+/// Whether `id` may vote in the game
+fn check_voter(
+    game: &Game<Player>,
+    id: <Player as gameplay::Player>::ID,
+) -> Result<(), &'static str> {
+    if game.get_player(id).is_some_and(PlayingPlayer::connected) {
+        Ok(())
+    } else {
+        Err("You are not part of this game")
+    }
+}
+
+/// Add the vote of `id` to take `player` out of the game, returns the votes and how many are
+/// needed
+fn vote_out(
+    game: &mut Game<Player>,
+    id: <Player as gameplay::Player>::ID,
+    player: <Player as gameplay::Player>::ID,
+) -> Result<(usize, usize), &'static str> {
+    let Some(target) = game.get_player_mut(player) else {
+        return Err("The player you specified is not part of this game");
+    };
+    target.removal_votes.insert(id);
+    let votes = target.removal_votes.clone();
+    Ok(tally(game, Some(player), |p| votes.contains(&p.id)))
+}
+
+/// Vote a player whose grace is over out of the game, they are removed once most of the others
+/// agree
+#[get("/game/vote_removal?<player>")]
+fn vote_removal(
+    player: <Player as gameplay::Player>::ID,
+    game: Protected<Game<Player>>,
+    state: &State<GlobalState>,
+    config: &State<Config>,
+    id: PlayerId,
+) -> Result<(), BadRequest<&'static str>> {
+    let id = id.0;
+
+    let (votes, needed) = {
+        let mut game = game.lock();
+        if !game.rules().vote_removal {
+            return Err(BadRequest("Players can't be voted out in this game"));
+        }
+        check_voter(&game, id).map_err(BadRequest)?;
+        let grace = Duration::from_secs(game.rules().reconnect_grace);
+        if game.get_player(player).is_some_and(|x| !x.abandoned(grace)) {
+            return Err(BadRequest("This player may still come back"));
+        }
+        vote_out(&mut game, id, player).map_err(BadRequest)?
+    };
+
+    game.broadcast(&Message::RemovalVote {
+        player,
+        votes,
+        needed,
+    });
+    if votes >= needed {
+        remove_player(&state.games, &state.stats, config, &game, player);
+    }
+
+    Ok(())
+}
+
+/// Vote to kick a player, connected or not, they are removed once most of the others agree
+#[get("/game/vote_kick?<player>")]
+fn vote_kick(
+    player: <Player as gameplay::Player>::ID,
+    game: Protected<Game<Player>>,
+    state: &State<GlobalState>,
+    config: &State<Config>,
+    id: PlayerId,
+) -> Result<(), BadRequest<&'static str>> {
+    let id = id.0;
+
+    let (votes, needed) = {
+        let mut game = game.lock();
+        check_voter(&game, id).map_err(BadRequest)?;
+        if id == player {
+            return Err(BadRequest("You cannot kick yourself"));
+        }
+        vote_out(&mut game, id, player).map_err(BadRequest)?
+    };
+
+    game.broadcast(&Message::KickVote {
+        player,
+        votes,
+        needed,
+    });
+    if votes >= needed {
+        let description = describe::kicked(&game.lock(), player);
+        game.broadcast(&Message::Kicked {
+            player,
+            description,
+        });
+        remove_player(&state.games, &state.stats, config, &game, player);
+    }
+
+    Ok(())
+}
+
+/// Vote to cancel the game, it ends without a winner once most of the players agree
+#[get("/game/vote_abort")]
+#[allow(clippy::needless_pass_by_value)]
+fn vote_abort(
+    game: Protected<Game<Player>>,
+    state: &State<GlobalState>,
+    jar: &CookieJar<'_>,
+    id: PlayerId,
+) -> Result<(), BadRequest<&'static str>> {
+    let id = id.0;
+
+    let (votes, needed) = {
+        let mut game = game.lock();
+        check_voter(&game, id).map_err(BadRequest)?;
+        game.get_player_mut(id).unwrap().abort_vote = true;
+        tally(&game, None, |p| p.abort_vote)
+    };
+
+    game.broadcast(&Message::AbortVote { votes, needed });
+    if votes >= needed && unregister(&state.games, &game) {
+        game.broadcast(&Message::Aborted {
+            description: describe::ABORTED,
+        });
+        jar.remove_private("lobby");
+        jar.remove_private("id");
+        jar.remove_private("name");
+    }
+
+    Ok(())
+}
+
+#[get("/game/substitute?<lobby>&<player>&<name>")]
+fn substitute(
+    lobby: &str,
+    player: <Player as gameplay::Player>::ID,
+    name: String,
+    state: &State<GlobalState>,
+    config: &State<Config>,
+    jar: &CookieJar<'_>,
+) -> Result<(), BadRequest<&'static str>> {
+    if account::check_name(&name, config, jar).is_err() {
+        return Err(BadRequest("This name is reserved"));
+    }
+    let lobby_name = lobby.to_uppercase();
+    let Some(game) = state.games.get(&lobby_name) else {
+        return Err(BadRequest("Game not found"));
+    };
+
+    let token = {
+        let mut game = game.lock();
+        let (name, token) =
+            reserve_seat(&mut game, config, jar, player, name, false).map_err(BadRequest)?;
+        game.get_player(game.host)
+            .unwrap()
+            .send(Message::SubstituteRequest { player, name });
+        token
+    };
+
+    jar.add_private(("substitute_lobby", lobby_name));
+    jar.add_private(("substitute_seat", player.to_string()));
+    jar.add_private(("substitute_token", token));
+
+    Ok(())
+}
+
+#[get("/game/substitute/approve?<player>")]
+#[allow(clippy::needless_pass_by_value)]
+fn approve_substitute(
+    player: <Player as gameplay::Player>::ID,
+    game: Protected<Game<Player>>,
+    id: PlayerId,
+) -> Result<(), BadRequest<&'static str>> {
+    let id = id.0;
+
+    let mut game = game.lock();
+    if game.host != id {
+        return Err(BadRequest("Only the host can approve substitutes"));
+    }
+    let Some(substitute) = game
+        .get_player_mut(player)
+        .and_then(|x| x.substitute.as_mut())
+    else {
+        return Err(BadRequest("Nobody asked to take this seat"));
+    };
+    substitute.approved = true;
+
+    Ok(())
+}
+
+/// Let someone take a seat without asking the host, returns the token to give to
+/// `/game/substitute/claim`
+pub fn invite(game: &mut Game<Player>, seat: <Player as gameplay::Player>::ID) -> Option<String> {
+    let player = game.get_player_mut(seat)?;
+    let token = Alphanumeric.sample_string(&mut thread_rng(), 16);
+    player.substitute = Some(Substitute {
+        token: token.clone(),
+        name: player.name.clone(),
+        approved: true,
+    });
+
+    Some(token)
+}
+
+/// Keep the seat of the disconnected `player` for someone named `name`, returns the name they will
+/// have and the token to give to `/game/substitute/claim`. A seat `approved` without asking the
+/// host also needs the grace of the player to be over.
+fn reserve_seat(
+    game: &mut Game<Player>,
+    config: &Config,
+    jar: &CookieJar<'_>,
+    player: <Player as gameplay::Player>::ID,
+    name: String,
+    approved: bool,
+) -> Result<(String, String), &'static str> {
+    // a player moving to another seat would see the hand and team of both
+    if jar
+        .get_private("id")
+        .and_then(|x| x.value().parse().ok())
+        .is_some_and(|id| game.get_player(id).is_some())
+    {
+        return Err("You already have a seat in this game");
+    }
+    let grace = Duration::from_secs(game.rules().reconnect_grace);
+    let Some(name) = config.name_collision.resolve(name, |name| {
+        game.players()
+            .values()
+            .any(|x| x.id != player && x.name == name)
+    }) else {
+        return Err("Someone in this game already uses this name");
+    };
+    let Some(seat) = game.get_player_mut(player) else {
+        return Err("The player you specified is not part of this game");
+    };
+    if seat.connected() {
+        return Err("This player is still connected");
+    }
+    if approved && seat.disconnected_at.is_none_or(|x| x.elapsed() < grace) {
+        return Err("This player may still come back");
+    }
+    if seat.substitute.as_ref().is_some_and(|x| x.approved) {
+        return Err("Someone is already taking this seat");
+    }
+    let token = Alphanumeric.sample_string(&mut thread_rng(), 16);
+    seat.substitute = Some(Substitute {
+        token: token.clone(),
+        name: name.clone(),
+        approved,
+    });
+
+    Ok((name, token))
+}
+
+/// Take the seat of a player whose grace is over without asking the host, with their hand and
+/// team
+#[get("/game/replace?<lobby>&<player>&<name>")]
+fn replace(
+    lobby: &str,
+    player: <Player as gameplay::Player>::ID,
+    name: String,
+    state: &State<GlobalState>,
+    config: &State<Config>,
+    jar: &CookieJar<'_>,
+) -> Result<Redirect, BadRequest<&'static str>> {
+    if account::check_name(&name, config, jar).is_err() {
+        return Err(BadRequest("This name is reserved"));
+    }
+    let lobby_name = lobby.to_uppercase();
+    let Some(game) = state.games.get(&lobby_name) else {
+        return Err(BadRequest("Game not found"));
+    };
+
+    let (_, token) =
+        reserve_seat(&mut game.lock(), config, jar, player, name, true).map_err(BadRequest)?;
+    claim_seat(&game, player, &token, jar).map_err(BadRequest)?;
+
+    Ok(Redirect::to("/game.html"))
+}
+
+/// Give the seat reserved with `token` to the client, the previous occupant can't play it anymore
+fn claim_seat(
+    game: &Protected<Game<Player>>,
+    seat: <Player as gameplay::Player>::ID,
+    token: &str,
+    jar: &CookieJar<'_>,
+) -> Result<(), &'static str> {
+    let seat_token = Alphanumeric.sample_string(&mut thread_rng(), 16);
+    let (lobby, name, party) = {
+        let mut game = game.lock();
+        let Some(player) = game.get_player_mut(seat) else {
+            return Err("This seat doesn't exist anymore");
+        };
+        match &player.substitute {
+            Some(x) if x.token == token && x.approved => (),
+            Some(x) if x.token == token => return Err("The host hasn't approved your request yet"),
+            _ => return Err("Someone else is taking this seat"),
+        }
+        if player.connected() {
+            return Err("This player is still connected");
+        }
+
+        player.name = player.substitute.take().unwrap().name;
+        player.seat_token = Some(seat_token.clone());
+        // the notes and the results of the previous occupant aren't theirs
+        player.suspicions.clear();
+        player.no_stats = jar.get_private("no_stats").is_some();
+        player.stats_key = stats::key(jar);
+        let name = player.name.clone();
+        (game.name().to_owned(), name, game.party.clone())
+    };
+    game.broadcast(&Message::Substituted {
+        player: seat,
+        name: name.clone(),
+    });
+
+    jar.add_private(("lobby", lobby));
+    jar.add_private(("id", seat.to_string()));
+    jar.add_private(("seat", seat_token));
+    jar.add_private(("name", name));
+    jar.add_private(("party", party));
+
+    Ok(())
+}
+
+#[get("/game/substitute/claim")]
+fn claim_substitute(
+    state: &State<GlobalState>,
+    jar: &CookieJar<'_>,
+) -> Result<Redirect, BadRequest<&'static str>> {
+    let (Some(lobby), Some(Ok(seat)), Some(token)) = (
+        jar.get_private("substitute_lobby"),
+        jar.get_private("substitute_seat")
+            .map(|x| x.value().parse::<<Player as gameplay::Player>::ID>()),
+        jar.get_private("substitute_token"),
+    ) else {
+        return Err(BadRequest("You didn't ask to substitute anyone"));
+    };
+    let Some(game) = state.games.get(lobby.value()) else {
+        return Err(BadRequest("Game not found"));
+    };
+
+    claim_seat(&game, seat, token.value(), jar).map_err(BadRequest)?;
+    jar.remove_private("substitute_lobby");
+    jar.remove_private("substitute_seat");
+    jar.remove_private("substitute_token");
+
+    Ok(Redirect::to("/game.html"))
+}
+
+/// Relays a WebRTC signaling blob (SDP offer/answer or ICE candidate) to another member of the room
+#[post("/game/signal?<to>", data = "<data>")]
+#[allow(clippy::needless_pass_by_value)]
+fn signal(
+    to: <Player as gameplay::Player>::ID,
+    data: String,
+    game: Protected<Game<Player>>,
+    id: PlayerId,
+) -> Result<(), BadRequest<&'static str>> {
+    let id = id.0;
+
+    let game = game.lock();
+    if game.get_player(id).is_none() {
+        return Err(BadRequest("You are not part of this game"));
+    }
+    let Some(player) = game.get_player(to) else {
+        return Err(BadRequest(
+            "The player you specified is not part of this game",
+        ));
+    };
+    if id == to {
+        return Err(BadRequest("You cannot signal yourself"));
+    }
+
+    player.send(Message::Signal { from: id, data });
+    Ok(())
+}
+
+#[get("/game/speaking?<state>")]
+#[allow(clippy::needless_pass_by_value)]
+fn speaking(
+    state: bool,
+    game: Protected<Game<Player>>,
+    id: PlayerId,
+) -> Result<(), BadRequest<&'static str>> {
+    let id = id.0;
+
+    if game.lock().get_player(id).is_none() {
+        return Err(BadRequest("You are not part of this game"));
+    }
+
+    game.broadcast(&Message::Speaking { player: id, state });
+    Ok(())
+}
+
+/// Privately mark `player` as trusted or suspected, `unknown` removes the marker
+#[get("/game/suspect?<player>&<level>")]
+#[allow(clippy::needless_pass_by_value)]
+fn suspect(
+    player: <Player as gameplay::Player>::ID,
+    level: Suspicion,
+    game: Protected<Game<Player>>,
+    id: PlayerId,
+) -> Result<(), BadRequest<&'static str>> {
+    let id = id.0;
+
+    {
+        let mut game = game.lock();
+        if game.get_player(player).is_none() {
+            return Err(BadRequest(
+                "The player you specified is not part of this game",
+            ));
+        }
+        if player == id {
+            return Err(BadRequest("You can't mark yourself"));
+        }
+        let Some(suspicions) = game.get_player_mut(id).map(|x| &mut x.suspicions) else {
+            return Err(BadRequest("You are not part of this game"));
+        };
+        if level == Suspicion::Unknown {
+            suspicions.remove(&player);
+        } else {
+            suspicions.insert(player, level);
+        }
+    }
+    game.broadcast_to(&Message::Suspicion { player, level }, |x| x.id == id);
+
+    Ok(())
+}
+
+/// Table talk, seen by everyone in the game unless `team` is set. Only the Moriarty players know
+/// their teammates, so they are the only ones with a team chat.
+#[post("/game/chat?<team>", data = "<message>")]
+#[allow(clippy::needless_pass_by_value)]
+fn chat(
+    team: Option<bool>,
+    message: String,
+    game: Protected<Game<Player>>,
+    id: PlayerId,
+) -> Result<(), BadRequest<&'static str>> {
+    let id = id.0;
+
+    let (msg, sender_team) = {
+        let game = game.lock();
+        let Some(player) = game.get_player(id) else {
+            return Err(BadRequest("You are not part of this game"));
+        };
+        let msg = ChatMessage::new(id, &player.name, &message).map_err(BadRequest)?;
+        (msg, player.team)
+    };
+    if team == Some(true) {
+        if sender_team != Team::Moriarty {
+            return Err(BadRequest("Only the Moriarty team has a team chat"));
+        }
+        game.broadcast_to(&Message::TeamChat(msg), |x| x.team == sender_team);
+    } else {
+        game.broadcast(&Message::Chat(msg));
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct GameState {
+    lobby: String,
+    player: <Player as gameplay::Player>::ID,
+    players: Vec<PlayerData>,
+    team: Team,
+    role: Option<Role>,
+    cables: Arc<[Cable]>,
+    wire_cutters: <Player as gameplay::Player>::ID,
+    paused: bool,
+    round: usize,
+    defusing_remaining: usize,
+    history: Vec<HistoryEntry<<Player as gameplay::Player>::ID>>,
+    suspicions: HashMap<<Player as gameplay::Player>::ID, Suspicion>,
+}
+
+impl GameState {
+    /// What `id` knows about the game
+    fn new(game: &Game<Player>, id: <Player as gameplay::Player>::ID) -> Option<Self> {
+        let player = game.get_player(id)?;
+        Some(Self {
+            lobby: game.name().to_owned(),
+            player: id,
+            players: game.players().values().map(Player::clone_data).collect(),
+            team: player.team(),
+            role: player.role,
+            cables: player.hand(),
+            wire_cutters: game.wire_cutters,
+            paused: game.is_paused(),
+            round: game.round(),
+            defusing_remaining: game.defusing_remaining(),
+            history: game.history().to_vec(),
+            suspicions: player.suspicions.clone(),
+        })
+    }
+}
+
+type History = Vec<HistoryEntry<<Player as gameplay::Player>::ID>>;
+
+/// Everything that happened since the start of the game, as in `init`
+#[get("/game/history")]
+#[allow(clippy::needless_pass_by_value)]
+fn history(
+    game: Protected<Game<Player>>,
+    id: PlayerId,
+) -> Result<Json<History>, BadRequest<&'static str>> {
+    let id = id.0;
+
+    let game = game.lock();
+    if game.get_player(id).is_none() {
+        return Err(BadRequest("You are not part of this game"));
+    }
+    Ok(Json(game.history().to_vec()))
+}
+
+/// Polling alternative to `/game/events`
+#[get("/game/state")]
+#[allow(clippy::needless_pass_by_value)]
+fn poll(
+    game: Protected<Game<Player>>,
+    if_none_match: IfNoneMatch,
+    id: PlayerId,
+) -> Result<Polled<Json<GameState>>, BadRequest<&'static str>> {
+    let id = id.0;
+
+    let game = game.lock();
+    if game.get_player(id).is_none() {
+        return Err(BadRequest("You are not part of this game"));
+    }
+    // the state contains the hand of the player, so it must not be shared with another seat
+    Ok(Polled::new(
+        &format!("{id}-{}", game.version),
+        &if_none_match,
+        || Json(GameState::new(&game, id).unwrap()),
+    ))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    #[allow(unused_mut)]
+    let mut routes = routes![
+        events,
+        spectate,
+        cut,
+        cut_neutral,
+        confirm_cut,
+        undo,
+        claim,
+        rematch,
+        leave,
+        vote_removal,
+        vote_kick,
+        vote_abort,
+        substitute,
+        approve_substitute,
+        claim_substitute,
+        replace,
+        signal,
+        speaking,
+        suspect,
+        chat,
+        history,
+        poll
+    ];
+    #[cfg(feature = "debug-endpoints")]
+    routes.extend(debug::routes());
+
+    routes
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        common,
+        config::Config,
+        testing::{self, Server},
+    };
+    use rocket::{
+        http::Status,
+        serde::json::{self, Value},
+    };
+    use std::time::{Duration, Instant};
+
+    /// Have the holder of the wire cutters cut the first cable of someone else
+    async fn cut_anyone(game: &mut crate::testing::Game<'_>) -> usize {
+        let state = game.sessions[0].json("/game/state").await;
+        let cutters = &state["wire_cutters"];
+        let target = state["players"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|x| x["id"] != *cutters && x["hand_size"] != 0)
+            .unwrap()["id"]
+            .clone();
+        for session in &mut game.sessions {
+            if session.json("/game/state").await["player"] == *cutters {
+                let (status, body) = session
+                    .get(&format!("/game/cut?player={target}&index=0"))
+                    .await;
+                assert_eq!(status, Status::Ok, "{body}");
+                return state["round"].as_u64().unwrap() as usize;
+            }
+        }
+        unreachable!()
+    }
+
+    #[rocket::async_test]
+    async fn round_end_comes_between_the_rounds() {
+        let server = Server::new(Config::default()).await;
+        // the bomb may be cut before the end of the first round
+        for attempt in 0..20 {
+            let mut game = server.game(&format!("ROUND{attempt}"), 4, "").await;
+            loop {
+                let round = cut_anyone(&mut game).await;
+                let (event, cut) = game.events[0].next().await.unwrap();
+                assert_eq!(event, "cut");
+                assert!(cut["description"].is_string());
+
+                let (status, state) = game.sessions[0].get("/game/state").await;
+                if status != Status::Ok {
+                    break;
+                }
+                if json::from_str::<Value>(&state).unwrap()["round"] == round {
+                    continue;
+                }
+
+                // the last cut of the round is in its summary, and comes before the next one
+                let (event, end) = game.events[0].next().await.unwrap();
+                assert_eq!(event, "round_end");
+                assert_eq!(end["round"], round);
+                assert_eq!(end["cuts"].as_array().unwrap().len(), 4);
+                let (event, start) = game.events[0].next().await.unwrap();
+                assert_eq!(event, "round_start");
+                assert_eq!(start["round"], round + 1);
+                return;
+            }
+        }
+        panic!("no game lasted a round");
+    }
+
+    #[rocket::async_test]
+    async fn nobody_is_nudged_during_a_pause() {
+        let config = Config {
+            nudge_after: Some(1),
+            ..Config::default()
+        };
+        let server = Server::new(config).await;
+        let mut game = server.game("NUDGE", 4, "").await;
+        let state = game.sessions[0].json("/game/state").await;
+        let holder = (1..=4)
+            .find(|&i| testing::id(&state, i) == state["wire_cutters"])
+            .unwrap();
+        let events = &mut game.events[holder - 1];
+        events.until("nudge").await;
+
+        server.state().games.get("NUDGE").unwrap().lock().paused = true;
+        while let Some((event, _)) = events.next().await {
+            assert_ne!(event, "nudge");
+        }
+        server.state().games.get("NUDGE").unwrap().lock().paused = false;
+        events.until("nudge").await;
+    }
+
+    #[rocket::async_test]
+    async fn spectators_are_kept_behind() {
+        let config = Config {
+            spectator_delay: 1,
+            ..Config::default()
+        };
+        let server = Server::new(config).await;
+        let mut game = server.game("WATCH", 4, "").await;
+        let mut watcher = server.session(9);
+
+        let opened = Instant::now();
+        let mut spectator = watcher.events("/game/spectate?lobby=WATCH").await;
+        spectator.until("spectate").await;
+        assert!(opened.elapsed() >= Duration::from_secs(1));
+
+        let sent = Instant::now();
+        game.sessions[0].post("/game/chat", "hello").await;
+        game.events[1].until("chat").await;
+        assert!(sent.elapsed() < Duration::from_secs(1));
+        spectator.until("chat").await;
+        assert!(sent.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[rocket::async_test]
+    async fn panicking_task_resyncs_the_game() {
+        let config = Config::default();
+        let server = Server::new(config.clone()).await;
+        let mut game = server.game("PANIC", 4, "").await;
+
+        let room = server.state().games.get("PANIC").unwrap();
+        let locked = room.clone();
+        common::spawn_supervised(super::recovery(&room, &config), async move {
+            let _game = locked.lock();
+            panic!("the task failed");
+        });
+
+        game.events[0].until("resync").await;
+        game.sessions[0].json("/game/state").await;
+    }
+
+    #[rocket::async_test]
+    async fn forfeiter_leaves_the_seat_to_a_bot() {
+        let server = Server::new(Config::default()).await;
+        let mut game = server.game("FORFEI", 4, "forfeit_takeover=true").await;
+        let p2 = game.id(2).await;
+
+        let redirect = game.sessions[1].redirect("/game/leave").await;
+        assert_eq!(redirect, "/gameMenu.html");
+        assert_eq!(game.events[0].until("forfeit").await["player"], p2);
+        // the bot plays in place of the stream of the player
+        assert_eq!(game.events[0].until("connect").await["player"], p2);
+        assert!(!game.sessions[1].has_cookie("lobby"));
+        let state = game.sessions[0].json("/game/state").await;
+        assert_eq!(state["players"].as_array().unwrap().len(), 4);
+    }
+
+    #[rocket::async_test]
+    async fn forfeiter_cables_are_dealt() {
+        let server = Server::new(Config::default()).await;
+        let mut game = server.game("LEAVER", 4, "").await;
+        let p2 = game.id(2).await;
+
+        game.sessions[1].redirect("/game/leave").await;
+        assert_eq!(game.events[0].until("removed").await["player"], p2);
+        let state = game.sessions[0].json("/game/state").await;
+        let players = state["players"].as_array().unwrap();
+        assert_eq!(players.len(), 3);
+        let cables: u64 = players
+            .iter()
+            .map(|x| x["hand_size"].as_u64().unwrap())
+            .sum();
+        assert_eq!(cables, 20);
+    }
+
+    #[test]
+    fn majority_needs_two_voters() {
+        assert_eq!(super::majority(1), 1);
+        assert_eq!(super::majority(2), 2);
+        assert_eq!(super::majority(3), 2);
+        assert_eq!(super::majority(4), 3);
+    }
+
+    #[rocket::async_test]
+    async fn kick_needs_the_players_who_left() {
+        let server = Server::new(Config::default()).await;
+        let mut game = server.game("KICKER", 4, "").await;
+        let p2 = game.id(2).await;
+        // p3 and p4 may still come back
+        game.events.truncate(2);
+        game.sessions[0].post("/game/chat", "hello").await;
+        game.events[0].until("disconnect").await;
+        game.events[0].until("disconnect").await;
+
+        game.sessions[0]
+            .get(&format!("/game/vote_kick?player={p2}"))
+            .await;
+        let vote = game.events[0].until("kick_vote").await;
+        assert_eq!(
+            (vote["votes"].clone(), vote["needed"].clone()),
+            (1.into(), 2.into())
+        );
+        let state = game.sessions[0].json("/game/state").await;
+        assert_eq!(state["players"].as_array().unwrap().len(), 4);
+    }
+
+    #[rocket::async_test]
+    async fn most_of_the_table_aborts() {
+        let server = Server::new(Config::default()).await;
+        let mut game = server.game("ABORTS", 4, "").await;
+        for (i, needed) in [(0, 3), (1, 3)] {
+            let (status, _) = game.sessions[i].get("/game/vote_abort").await;
+            assert_eq!(status, Status::Ok);
+            let vote = game.events[0].until("abort_vote").await;
+            assert_eq!(
+                (vote["votes"].clone(), vote["needed"].clone()),
+                ((i + 1).into(), needed.into())
+            );
+        }
+        game.sessions[2].get("/game/vote_abort").await;
+        game.events[0].until("aborted").await;
+    }
+
+    /// p2 left the four players game, whose grace is over right away
+    async fn abandoned<'a>(server: &'a Server, code: &str) -> crate::testing::Game<'a> {
+        let mut game = server
+            .game(code, 4, "reconnect_grace=0&vote_removal=true")
+            .await;
+        drop(game.events.remove(1));
+        // a dead stream is only noticed when something is sent to it
+        game.sessions[0].post("/game/chat", "hello").await;
+        game.events[0].until("grace_over").await;
+        game
+    }
+
+    #[rocket::async_test]
+    async fn replaced_player_loses_the_seat() {
+        let server = Server::new(Config::default()).await;
+        let mut game = abandoned(&server, "REPLAC").await;
+        let seat = game.id(2).await;
+
+        let mut p5 = server.session(5);
+        let (status, _) = p5
+            .get(&format!("/game/replace?lobby=REPLAC&player={seat}&name=p5"))
+            .await;
+        assert_eq!(status, Status::SeeOther);
+        assert_eq!(p5.json("/game/state").await["player"], seat);
+
+        let p2 = &mut game.sessions[1];
+        assert_eq!(
+            p2.refused("/game/state").await,
+            "Someone else took your seat"
+        );
+        let mut events = p2.events("/game/events").await;
+        assert_eq!(
+            events.until("error").await["reason"],
+            "Someone else took your seat"
+        );
+    }
+
+    #[rocket::async_test]
+    async fn substitute_needs_the_approval_of_the_host() {
+        let server = Server::new(Config::default()).await;
+        let mut game = abandoned(&server, "SUBSTI").await;
+        let seat = game.id(2).await;
+
+        let mut p5 = server.session(5);
+        let (status, _) = p5
+            .get(&format!(
+                "/game/substitute?lobby=SUBSTI&player={seat}&name=p5"
+            ))
+            .await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(
+            p5.refused("/game/substitute/claim").await,
+            "The host hasn't approved your request yet"
+        );
+
+        let (status, _) = game.sessions[0]
+            .get(&format!("/game/substitute/approve?player={seat}"))
+            .await;
+        assert_eq!(status, Status::Ok);
+        let (status, _) = p5.get("/game/substitute/claim").await;
+        assert_eq!(status, Status::SeeOther);
+        assert_eq!(p5.json("/game/state").await["player"], seat);
+        assert_eq!(
+            game.sessions[1].refused("/game/state").await,
+            "Someone else took your seat"
+        );
+    }
 }
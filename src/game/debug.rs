@@ -0,0 +1,119 @@
+//! Routes forcing the state of a game, to reproduce edge cases by hand
+use super::{end_game, send_round, start_round, Message, Player};
+use crate::{
+    admin::Admin,
+    common::GlobalState,
+    config::Config,
+    gameplay::{self, Cable, Team},
+};
+use rocket::{
+    get, post,
+    response::status::BadRequest,
+    routes,
+    serde::{json::Json, Deserialize},
+    State,
+};
+
+type ID = <Player as gameplay::Player>::ID;
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Hand {
+    player: ID,
+    cables: Vec<Cable>,
+}
+
+#[post("/debug/games/<code>/hand", data = "<hand>")]
+#[allow(clippy::needless_pass_by_value)]
+fn hand(
+    _admin: Admin,
+    code: &str,
+    hand: Json<Hand>,
+    state: &State<GlobalState>,
+) -> Result<(), BadRequest<&'static str>> {
+    let game = state
+        .games
+        .get(&code.to_uppercase())
+        .ok_or(BadRequest("Game not found"))?;
+    let Hand { player, cables } = hand.into_inner();
+    if !game.lock().force_hand(player, cables) {
+        return Err(BadRequest("This player is not part of this game"));
+    }
+    send_round(&game);
+
+    Ok(())
+}
+
+#[get("/debug/games/<code>/wire_cutters?<player>")]
+#[allow(clippy::needless_pass_by_value)]
+fn wire_cutters(
+    _admin: Admin,
+    code: &str,
+    player: ID,
+    state: &State<GlobalState>,
+) -> Result<(), BadRequest<&'static str>> {
+    let game = state
+        .games
+        .get(&code.to_uppercase())
+        .ok_or(BadRequest("Game not found"))?;
+    if !game.lock().force_wire_cutters(player) {
+        return Err(BadRequest("This player is not part of this game"));
+    }
+    // clients only learn who has the wire cutters from the cuts
+    game.broadcast(&Message::Resync);
+
+    Ok(())
+}
+
+/// Deal the remaining cables again, as if every player was cut this round
+#[get("/debug/games/<code>/next_round")]
+#[allow(clippy::needless_pass_by_value)]
+fn next_round(
+    _admin: Admin,
+    code: &str,
+    state: &State<GlobalState>,
+    config: &State<Config>,
+) -> Result<(), BadRequest<&'static str>> {
+    let game = state
+        .games
+        .get(&code.to_uppercase())
+        .ok_or(BadRequest("Game not found"))?;
+    let over = {
+        let mut game = game.lock();
+        if game.paused {
+            return Err(BadRequest("The game is paused"));
+        }
+        let over = game.next_round();
+        if !over && config.round_countdown > 0 {
+            game.paused = true;
+        }
+        over
+    };
+
+    if over {
+        end_game(
+            &state.games,
+            &state.stats,
+            config,
+            &game,
+            Some(Team::Moriarty),
+        );
+    } else {
+        start_round(&game, &state.games, config);
+    }
+
+    Ok(())
+}
+
+/// Panic while holding the lock of the game, to check that it survives
+#[get("/debug/games/<code>/panic")]
+#[allow(clippy::needless_pass_by_value)]
+fn panic(_admin: Admin, code: &str, state: &State<GlobalState>) -> Option<()> {
+    let game = state.games.get(&code.to_uppercase())?;
+    let _locked = game.lock();
+    panic!("panicking on purpose in {code}");
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![hand, wire_cutters, next_round, panic]
+}
@@ -0,0 +1,189 @@
+//! What happened in plain sentences, for screen readers and text-only clients
+
+use super::Player;
+use crate::{
+    config::Theme,
+    gameplay::{self, Cable, Claim, Game, HistoryEntry, Room, Series, Team},
+    roles::Role,
+};
+
+type ID = <Player as gameplay::Player>::ID;
+
+fn name(game: &Game<Player>, id: ID) -> &str {
+    game.get_player(id).map_or("Someone", |x| x.name.as_str())
+}
+
+/// `a, b and c`
+fn list(names: &[&str]) -> String {
+    match names {
+        [] => String::new(),
+        [name] => (*name).to_owned(),
+        [rest @ .., last] => format!("{} and {last}", rest.join(", ")),
+    }
+}
+
+pub fn cut(
+    game: &Game<Player>,
+    theme: &Theme,
+    cutter: ID,
+    owner: Option<ID>,
+    cable: Cable,
+    defusing_remaining: usize,
+) -> String {
+    let whose = match owner {
+        None => "the neutral cables".to_owned(),
+        Some(owner) if owner == cutter => "their own cables".to_owned(),
+        Some(owner) => format!("{}'s cables", name(game, owner)),
+    };
+    let cable_name = theme.cable(cable).to_lowercase();
+    let mut description = format!(
+        "{} cut one of {whose}: it was a {cable_name} cable.",
+        name(game, cutter)
+    );
+    if cable != Cable::Bomb {
+        let defusing = theme.cable(Cable::Defusing).to_lowercase();
+        description.push_str(&match defusing_remaining {
+            1 => format!(" 1 {defusing} cable remains."),
+            n => format!(" {n} {defusing} cables remain."),
+        });
+    }
+
+    description
+}
+
+pub fn claim(game: &Game<Player>, theme: &Theme, id: ID, claim: Claim) -> String {
+    let defusing = theme.cable(Cable::Defusing).to_lowercase();
+    let bomb = theme.cable(Cable::Bomb).to_lowercase();
+    format!(
+        "{} claims {} {defusing} cable{} and {} {bomb}.",
+        name(game, id),
+        claim.defusing,
+        if claim.defusing == 1 { "" } else { "s" },
+        if claim.bomb { "the" } else { "no" },
+    )
+}
+
+pub fn round_end(theme: &Theme, round: usize, cuts: &[HistoryEntry<ID>]) -> String {
+    let counts: Vec<_> = [
+        Cable::Safe,
+        Cable::Defusing,
+        Cable::Bomb,
+        Cable::ShortFuse,
+        Cable::DecoyBomb,
+    ]
+    .into_iter()
+    .filter_map(|cable| {
+        let count = cuts
+            .iter()
+            .filter(|x| {
+                matches!(x, HistoryEntry::Cut { cable: cut, .. } | HistoryEntry::NeutralCut { cable: cut, .. } if *cut == cable)
+            })
+            .count();
+        (count > 0).then(|| format!("{count} {}", theme.cable(cable).to_lowercase()))
+    })
+    .collect();
+    match cuts.len() {
+        0 => format!("Round {} is over.", round + 1),
+        1 => format!(
+            "Round {} is over, 1 cable was cut: {}.",
+            round + 1,
+            counts[0]
+        ),
+        n => format!(
+            "Round {} is over, {n} cables were cut: {}.",
+            round + 1,
+            list(&counts.iter().map(String::as_str).collect::<Vec<_>>())
+        ),
+    }
+}
+
+pub fn win(game: &Game<Player>, theme: &Theme, team: Team) -> String {
+    let winners: Vec<_> = game
+        .players()
+        .values()
+        .filter(|x| x.team == team)
+        .map(|x| x.name.as_str())
+        .collect();
+    format!("Victory for {}: {}.", theme.team(team), list(&winners))
+}
+
+pub fn series(theme: &Theme, series: &Series) -> String {
+    let score = format!(
+        "{} {}, {} {}",
+        theme.team(Team::Sherlock),
+        series.sherlock,
+        theme.team(Team::Moriarty),
+        series.moriarty,
+    );
+    match series.winner() {
+        Some(team) => format!("{} wins the series, {score}.", theme.team(team)),
+        None => format!(
+            "{score}, the first team to {} wins the series.",
+            series.target
+        ),
+    }
+}
+
+pub const DRAW: &str = "The game ends in a draw.";
+
+pub const ABORTED: &str = "The players cancelled the game.";
+
+pub fn removed(game: &Game<Player>, name: &str) -> String {
+    format!(
+        "{name} was removed from the game, {} has the wire cutters.",
+        self::name(game, game.wire_cutters)
+    )
+}
+
+pub fn timeout(game: &Game<Player>, id: ID) -> String {
+    format!(
+        "{} took too long, a random cable is cut for them.",
+        name(game, id)
+    )
+}
+
+pub fn auto_pause(game: &Game<Player>, id: ID, seconds: u64) -> String {
+    format!(
+        "{} disconnected, the game is paused for {seconds} seconds.",
+        name(game, id)
+    )
+}
+
+pub fn takeover(game: &Game<Player>, id: ID) -> String {
+    format!("A bot plays for {} until they come back.", name(game, id))
+}
+
+pub fn role_used(
+    game: &Game<Player>,
+    theme: &Theme,
+    holder: ID,
+    role: Role,
+    cable: Cable,
+) -> String {
+    format!(
+        "{} was the {}, the cable counts as a {} cable.",
+        name(game, holder),
+        role.name(),
+        theme.cable(cable).to_lowercase()
+    )
+}
+
+pub fn undone(game: &Game<Player>, cutting: ID, cutted: ID) -> String {
+    format!(
+        "The cut of {} in the hand of {} was undone.",
+        name(game, cutting),
+        name(game, cutted)
+    )
+}
+
+pub fn kicked(game: &Game<Player>, id: ID) -> String {
+    format!("{} was voted out of the game.", name(game, id))
+}
+
+pub fn forfeit(game: &Game<Player>, id: ID) -> String {
+    format!("{} left the game.", name(game, id))
+}
+
+pub fn redistribution(remaining: usize) -> String {
+    format!("The cables were dealt again, {remaining} are in play.")
+}
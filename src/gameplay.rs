@@ -1,9 +1,20 @@
+use crate::roles::{self, Effect, Role};
 use rand::{
+    rngs::StdRng,
     seq::{IteratorRandom, SliceRandom},
-    thread_rng,
+    thread_rng, Rng, SeedableRng,
+};
+use rocket::{
+    serde::{Deserialize, Serialize},
+    FromFormField,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    hash::Hash,
+    time::{Instant, SystemTime, UNIX_EPOCH},
 };
-use rocket::serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fmt::Debug, hash::Hash};
 
 macro_rules! repeated_vec {
     ($($quantity:expr => $value:expr),*) => {{
@@ -21,6 +32,13 @@ macro_rules! repeated_vec {
     };
 }
 
+pub const MIN_PLAYERS: usize = 4;
+/// With [`Rules::small_table`]
+pub const SMALL_TABLE_MIN_PLAYERS: usize = 2;
+/// Tables of more than [`OFFICIAL_MAX_PLAYERS`] follow the expansion rules
+pub const MAX_PLAYERS: usize = 12;
+pub const OFFICIAL_MAX_PLAYERS: usize = 8;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(crate = "rocket::serde")]
 #[serde(rename_all = "lowercase")]
@@ -29,17 +47,415 @@ pub enum Team {
     Moriarty,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 #[serde(crate = "rocket::serde")]
 #[serde(rename_all = "lowercase")]
 pub enum Cable {
     Safe,
     Defusing,
     Bomb,
+    /// Counts as two safe cuts, the round ends sooner
+    #[serde(rename = "short_fuse")]
+    ShortFuse,
+    /// Harmless, counts as a safe cut
+    #[serde(rename = "decoy_bomb")]
+    DecoyBomb,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(default)]
+pub struct Rules {
+    /// Up to [`MAX_PLAYERS`]
+    pub max_players: usize,
+    /// Allow games of two or three players, a neutral hand is dealt along with theirs
+    pub small_table: bool,
+    /// Seconds a disconnected player has to come back
+    pub reconnect_grace: u64,
+    /// Once the grace is over, let the others vote the player out instead of removing them
+    pub vote_removal: bool,
+    /// Once the grace is over, a bot plays for the player until they come back, this takes
+    /// precedence over `vote_removal`
+    pub bot_takeover: bool,
+    /// A bot plays the rest of the game for whoever leaves it, their cables are dealt to the
+    /// others otherwise
+    pub forfeit_takeover: bool,
+    /// Seconds the game is paused when someone disconnects, unless they come back sooner
+    pub auto_pause: Option<u64>,
+    /// Forbid cutting the player who just cut you
+    pub no_cut_back: bool,
+    /// Cuts only happen once the cutter confirms them
+    pub confirm_cuts: bool,
+    /// How many times per game each player may cut one of their own cables
+    pub self_cuts: u8,
+    /// House rule letting players cut their own cables as often as they like, `self_cuts` is
+    /// ignored then
+    pub free_self_cuts: bool,
+    pub teams: TeamDistribution,
+    /// Exact size of each team chosen by the host, `teams` is ignored then
+    pub team_sizes: Option<TeamSizes>,
+    /// Give the Sherlock cards to the players who were Moriarty many times in a row
+    pub balance_teams: bool,
+    /// Seed used for every random decision of the game. Never sent to the clients, anyone knowing
+    /// it could replay the deal.
+    #[serde(skip_serializing)]
+    pub seed: Option<u64>,
+    /// Day of the daily challenge this game is for
+    pub daily: Option<u64>,
+    /// Record the game in the stats under pseudonyms instead of the names of the players
+    pub anonymous: bool,
+    /// Names shown for the teams and cables, the default ones if unset
+    pub theme: Option<String>,
+    /// Seconds the holder of the wire cutters has to cut, a random cable is cut for them after that
+    pub turn_timer: Option<u64>,
+    /// Moriarty wins once this many rounds were played, even if cables remain
+    pub max_rounds: usize,
+    pub deck: DeckBuilder,
+    pub round_reveal: RoundReveal,
+    /// Score of the games played so far, when playing a series
+    pub series: Option<Series>,
+    /// Dealt at the start of the game, each to a random player of its team
+    pub roles: Vec<Role>,
+    /// Deal the short fuses and decoy bombs of the deck
+    pub variant_cables: bool,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            max_players: OFFICIAL_MAX_PLAYERS,
+            small_table: false,
+            reconnect_grace: 60 * 5,
+            vote_removal: false,
+            bot_takeover: false,
+            forfeit_takeover: false,
+            auto_pause: None,
+            no_cut_back: false,
+            confirm_cuts: false,
+            self_cuts: 0,
+            free_self_cuts: false,
+            teams: TeamDistribution::Official,
+            team_sizes: None,
+            balance_teams: false,
+            seed: None,
+            daily: None,
+            anonymous: false,
+            theme: None,
+            turn_timer: None,
+            max_rounds: 4,
+            deck: DeckBuilder::default(),
+            round_reveal: RoundReveal::Nothing,
+            series: None,
+            roles: Vec::new(),
+            variant_cables: false,
+        }
+    }
+}
+
+/// Consecutive games of the same players, won by the first team to win `target` of them
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(default)]
+pub struct Series {
+    pub target: u32,
+    pub sherlock: u32,
+    pub moriarty: u32,
+    /// Sorted ids of the players, the score starts over when they change
+    pub roster: Vec<u32>,
+}
+
+impl Series {
+    pub fn new(target: u32) -> Self {
+        Self {
+            target,
+            ..Self::default()
+        }
+    }
+
+    pub fn record(&mut self, winner: Option<Team>) {
+        match winner {
+            Some(Team::Sherlock) => self.sherlock += 1,
+            Some(Team::Moriarty) => self.moriarty += 1,
+            None => (),
+        }
+    }
+
+    pub const fn winner(&self) -> Option<Team> {
+        if self.sherlock >= self.target {
+            Some(Team::Sherlock)
+        } else if self.moriarty >= self.target {
+            Some(Team::Moriarty)
+        } else {
+            None
+        }
+    }
+
+    /// Called when a game of the series starts, the score is kept only if the same players
+    /// continue a series nobody won yet
+    pub fn seat(&mut self, mut roster: Vec<u32>) {
+        roster.sort_unstable();
+        if self.roster != roster || self.winner().is_some() {
+            *self = Self {
+                roster,
+                ..Self::new(self.target)
+            };
+        }
+    }
+}
+
+/// What everyone learns about the uncut cables when a round ends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, FromFormField)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "lowercase")]
+pub enum RoundReveal {
+    Nothing,
+    /// How many defusing cables each player had left
+    Defusing,
+    /// The whole hands, for casual games
+    Hands,
+}
+
+/// The cables dealt at the start of a game, for a given number of players
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(default)]
+pub struct DeckBuilder {
+    pub bombs: usize,
+    pub defusing_per_player: usize,
+    /// Safe cables fill every hand up to this size
+    pub hand_size: usize,
+    /// Only dealt with `Rules::variant_cables`
+    pub short_fuses: usize,
+    /// Only dealt with `Rules::variant_cables`
+    pub decoy_bombs: usize,
+}
+
+impl Default for DeckBuilder {
+    fn default() -> Self {
+        Self {
+            bombs: 1,
+            defusing_per_player: 1,
+            hand_size: 5,
+            short_fuses: 1,
+            decoy_bombs: 1,
+        }
+    }
+}
+
+impl DeckBuilder {
+    /// Check that the deck can be dealt to any number of players, see [`Self::build`]. With
+    /// `small_table`, the neutral hand counts as a player.
+    pub fn validate(&self, variants: bool, small_table: bool) -> Result<(), errors::Deck> {
+        if self.bombs == 0 {
+            return Err(errors::Deck::NoBomb);
+        }
+        if self.defusing_per_player == 0 {
+            return Err(errors::Deck::NoDefusing);
+        }
+        if self.hand_size > 10 {
+            return Err(errors::Deck::HandTooBig);
+        }
+        let variants = if variants {
+            self.short_fuses + self.decoy_bombs
+        } else {
+            0
+        };
+        let min_players = if small_table {
+            SMALL_TABLE_MIN_PLAYERS + 1
+        } else {
+            MIN_PLAYERS
+        };
+        for player_count in min_players..=MAX_PLAYERS {
+            if self.defusing_per_player * player_count + self.bombs + variants
+                > self.hand_size * player_count
+            {
+                return Err(errors::Deck::DoesntFit);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the cables and how many of them are defusing ones, with the variant cables if
+    /// `variants` is set. Safe cables are left out when the deck doesn't
+    /// [validate](Self::validate).
+    pub fn build(&self, player_count: usize, variants: bool) -> (Vec<Cable>, usize) {
+        let defusing = self.defusing_per_player * player_count;
+        let (short_fuses, decoy_bombs) = if variants {
+            (self.short_fuses, self.decoy_bombs)
+        } else {
+            (0, 0)
+        };
+        let safe = (self.hand_size * player_count)
+            .saturating_sub(defusing + self.bombs + short_fuses + decoy_bombs);
+
+        (
+            repeated_vec![
+                safe => Cable::Safe,
+                defusing => Cable::Defusing,
+                self.bombs => Cable::Bomb,
+                short_fuses => Cable::ShortFuse,
+                decoy_bombs => Cable::DecoyBomb
+            ],
+            defusing,
+        )
+    }
+}
+
+impl Rules {
+    /// The rules of the daily challenge, identical for everyone on a given day. The deal can only
+    /// be worked out by those who know `secret`.
+    pub fn daily(day: u64, secret: &str) -> Self {
+        #[allow(clippy::cast_possible_truncation)]
+        let preset = Preset::ALL[(day % Preset::ALL.len() as u64) as usize];
+        let hash = Sha256::new()
+            .chain_update(secret)
+            .chain_update(day.to_le_bytes())
+            .finalize();
+        Self {
+            seed: Some(u64::from_le_bytes(hash[..8].try_into().unwrap())),
+            daily: Some(day),
+            ..preset.rules()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, FromFormField)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum Preset {
+    Classic,
+    Quick,
+    Chaos,
+    #[field(value = "large_table")]
+    LargeTable,
+}
+
+impl Preset {
+    pub const ALL: [Self; 4] = [Self::Classic, Self::Quick, Self::Chaos, Self::LargeTable];
+
+    pub fn rules(self) -> Rules {
+        let classic = Rules::default();
+        match self {
+            Self::Classic => classic,
+            Self::Quick => Rules {
+                reconnect_grace: 60 * 2,
+                teams: TeamDistribution::Exact,
+                turn_timer: Some(30),
+                ..classic
+            },
+            Self::Chaos => Rules {
+                self_cuts: 1,
+                ..classic
+            },
+            Self::LargeTable => Rules {
+                no_cut_back: true,
+                balance_teams: true,
+                ..classic
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, FromFormField)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "lowercase")]
+pub enum TeamDistribution {
+    /// Deal the role cards of the official table, some of them may be left out
+    Official,
+    /// Use exactly the number of Moriarty cards of the official table
+    Exact,
+}
+
+impl TeamDistribution {
+    pub fn teams(self, player_count: usize) -> Vec<Team> {
+        let (sherlock, moriarty) = match player_count {
+            // small table variant
+            2 => (1, 1),
+            3 => (2, 1),
+            4..=5 => (3, 2),
+            6 => (4, 2),
+            7..=8 => (5, 3),
+            9..=10 => (6, 4),
+            11..=12 => (7, 5),
+            _ => unreachable!(),
+        };
+
+        match self {
+            Self::Official => repeated_vec![sherlock => Team::Sherlock, moriarty => Team::Moriarty],
+            Self::Exact => {
+                repeated_vec![player_count - moriarty => Team::Sherlock, moriarty => Team::Moriarty]
+            }
+        }
+    }
+}
+
+/// How many players are in each team
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TeamSizes {
+    pub sherlock: usize,
+    pub moriarty: usize,
+}
+
+impl TeamSizes {
+    pub const fn total(self) -> usize {
+        self.sherlock + self.moriarty
+    }
+
+    pub fn teams(self) -> Vec<Team> {
+        repeated_vec![self.sherlock => Team::Sherlock, self.moriarty => Team::Moriarty]
+    }
+
+    pub fn count(teams: &[Team]) -> Self {
+        let sherlock = teams.iter().filter(|x| **x == Team::Sherlock).count();
+        Self {
+            sherlock,
+            moriarty: teams.len() - sherlock,
+        }
+    }
+}
+
+/// Something that happened during a game, in a form that allows rebuilding the table
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum HistoryEntry<ID> {
+    Cut {
+        round: usize,
+        cutting: ID,
+        cutted: ID,
+        cable: Cable,
+        /// Seconds since the Unix epoch, 0 in games recorded before it was kept
+        #[serde(default)]
+        timestamp: u64,
+    },
+    /// The wire cutters changed hands without a cut, because their holder left
+    Pass { round: usize, from: ID, to: ID },
+    Claim {
+        round: usize,
+        player: ID,
+        claim: Claim,
+    },
+    /// A cable of the neutral hand was cut, see [`Rules::small_table`]
+    NeutralCut {
+        round: usize,
+        cutting: ID,
+        cable: Cable,
+        timestamp: u64,
+    },
+}
+
+/// What a player says their hand holds, nothing forces it to be true
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Claim {
+    pub defusing: usize,
+    pub bomb: bool,
 }
 
 pub trait Player {
-    type ID: Eq + Hash + Clone + Copy + Debug;
+    type ID: Eq + Ord + Hash + Clone + Copy + Debug;
 
     fn id(&self) -> Self::ID;
     fn name(&self) -> &str;
@@ -63,46 +479,169 @@ pub trait PlayingPlayer: Player {
 
     fn cables(&self) -> &[Cable];
     fn set_cables(&mut self, cables: Vec<Cable>);
-    fn cut_cable(&mut self) -> Cable;
+    /// Cut the cable at `index` in the hand, the order of the hand is fixed for the whole round
+    fn cut_cable(&mut self, index: usize) -> Cable;
+    /// Put back the cable that was just cut at `index`
+    fn restore_cable(&mut self, index: usize, cable: Cable);
+
+    fn role(&self) -> Option<Role>;
+    fn set_role(&mut self, role: Option<Role>);
 }
 
 #[derive(Debug)]
 pub struct Lobby<PLAYER: WaitingPlayer> {
     name: String,
     players: HashMap<PLAYER::ID, PLAYER>,
+    host: Option<PLAYER::ID>,
+    pub rules: Rules,
+    /// Identifies the group of people playing together, kept from room to room
+    pub party: String,
+    /// Bumped on every change clients can see
+    pub version: u64,
+    /// Set once the lobby became a game, nobody can join it anymore
+    started: bool,
+    /// Asked to everyone joining when set
+    pub password: Option<Password>,
+    /// Players the host kicked, they can't come back with the same id
+    kicked: HashSet<PLAYER::ID>,
+    /// Clients the host kicked, they can't come back under another name either
+    pub banned: HashSet<String>,
+    /// When the lobby is closed for having been open too long
+    pub closes_at: Option<Instant>,
+}
+
+/// Salted hash of a password
+#[derive(Debug, Clone)]
+pub struct Password {
+    salt: [u8; 16],
+    hash: [u8; 32],
+}
+
+impl Password {
+    pub fn new(password: &str) -> Self {
+        let salt = thread_rng().gen();
+        Self {
+            salt,
+            hash: Self::hash(&salt, password),
+        }
+    }
+
+    fn hash(salt: &[u8; 16], password: &str) -> [u8; 32] {
+        Sha256::new()
+            .chain_update(salt)
+            .chain_update(password)
+            .finalize()
+            .into()
+    }
+
+    pub fn matches(&self, password: &str) -> bool {
+        // compare every byte, so the time taken doesn't tell how much of the hash matched
+        Self::hash(&self.salt, password)
+            .iter()
+            .zip(self.hash)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+    }
 }
 
 impl<PLAYER: WaitingPlayer> Lobby<PLAYER> {
     pub fn new(name: String) -> Self {
         Self {
-            name,
             players: HashMap::new(),
+            host: None,
+            rules: Rules::default(),
+            party: name.clone(),
+            version: 0,
+            started: false,
+            password: None,
+            kicked: HashSet::new(),
+            banned: HashSet::new(),
+            closes_at: None,
+            name,
         }
     }
 
+    /// Whether `password` lets someone in, anything does when the lobby has no password
+    pub fn check_password(&self, password: Option<&str>) -> bool {
+        self.password
+            .as_ref()
+            .is_none_or(|x| password.is_some_and(|password| x.matches(password)))
+    }
+
     pub fn add_player(&mut self, player: PLAYER) -> Result<(), errors::Join> {
-        if self.players.len() >= 8 {
+        if self.started {
+            return Err(errors::Join::Started);
+        }
+        if self.players.len() >= self.rules.max_players {
             return Err(errors::Join::GameFull);
         }
 
         if self.players.contains_key(&player.id()) {
             return Err(errors::Join::AlreadyConnected);
         }
+        if self.kicked.contains(&player.id()) {
+            return Err(errors::Join::Kicked);
+        }
+        self.host.get_or_insert(player.id());
         self.players.insert(player.id(), player);
 
         Ok(())
     }
 
-    pub fn remove_player(&mut self, id: PLAYER::ID) {
+    /// Returns whether someone else became the host
+    pub fn remove_player(&mut self, id: PLAYER::ID) -> bool {
         self.players.remove(&id);
+
+        if self.host == Some(id) {
+            self.host = self.players.keys().choose(&mut thread_rng()).copied();
+            return true;
+        }
+        false
+    }
+
+    /// Remove the player for good, returns whether someone else became the host
+    pub fn kick(&mut self, id: PLAYER::ID) -> bool {
+        self.kicked.insert(id);
+        self.remove_player(id)
+    }
+
+    pub const fn host(&self) -> Option<PLAYER::ID> {
+        self.host
+    }
+
+    pub fn is_host(&self, id: PLAYER::ID) -> bool {
+        self.host == Some(id)
     }
 
     pub fn may_start(&self) -> bool {
-        self.players.len() >= 4 && self.players.values().all(WaitingPlayer::ready)
+        let min_players = if self.rules.small_table {
+            SMALL_TABLE_MIN_PLAYERS
+        } else {
+            MIN_PLAYERS
+        };
+        self.players.len() >= min_players
+            && self.players.values().all(WaitingPlayer::ready)
+            && self
+                .rules
+                .team_sizes
+                .is_none_or(|x| x.total() == self.players.len())
     }
 
-    pub fn start<T: PlayingPlayer<ID = PLAYER::ID>>(&self) -> Game<T> {
-        Game::new(self.name.clone(), &self.players)
+    /// `moriarty_streaks` is only used when teams are balanced
+    pub fn start<T: PlayingPlayer<ID = PLAYER::ID>>(
+        &mut self,
+        moriarty_streaks: &HashMap<PLAYER::ID, u32>,
+    ) -> Game<T> {
+        self.started = true;
+        let mut game = Game::new(
+            self.name.clone(),
+            &self.players,
+            self.host.unwrap(),
+            self.rules.clone(),
+            moriarty_streaks,
+        );
+        game.party.clone_from(&self.party);
+        game
     }
 }
 
@@ -124,51 +663,213 @@ impl<PLAYER: WaitingPlayer> Room<PLAYER> for Lobby<PLAYER> {
     }
 }
 
+/// A predefined table, for puzzles and tutorials
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Scenario {
+    #[serde(default)]
+    pub description: String,
+    pub seats: Vec<Seat>,
+    /// Index of the seat holding the wire cutters
+    #[serde(default)]
+    pub wire_cutters: usize,
+    #[serde(default)]
+    pub rules: Rules,
+    /// Played in order once the seats are set up
+    #[serde(default)]
+    pub actions: Vec<Action>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// The holder of the wire cutters cuts a cable of a seat
+    Cut { seat: usize, index: usize },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Seat {
+    pub name: String,
+    pub team: Team,
+    pub cables: Vec<Cable>,
+}
+
+/// A seat of a scenario that has been given an id
+struct ScenarioPlayer<'a, ID> {
+    id: ID,
+    seat: &'a Seat,
+}
+
+impl<ID: Eq + Ord + Hash + Clone + Copy + Debug> Player for ScenarioPlayer<'_, ID> {
+    type ID = ID;
+
+    fn id(&self) -> Self::ID {
+        self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.seat.name
+    }
+}
+
+impl<ID: Eq + Ord + Hash + Clone + Copy + Debug> WaitingPlayer for ScenarioPlayer<'_, ID> {
+    fn ready(&self) -> bool {
+        true
+    }
+}
+
 pub struct Game<PLAYER: PlayingPlayer> {
     name: String,
     players: HashMap<PLAYER::ID, PLAYER>,
     pub wire_cutters: PLAYER::ID,
+    pub host: PLAYER::ID,
+    pub party: String,
+    /// No cable can be cut while the game is paused
+    pub paused: bool,
+    /// Disconnected players the game is paused for and when they left, see [`Rules::auto_pause`]
+    pub waiting_for: HashMap<PLAYER::ID, Instant>,
+    /// People watching the game without playing it
+    pub spectators: usize,
+    /// When the current turn runs out, unset while there is no turn timer or the game is paused
+    pub turn_deadline: Option<Instant>,
+    /// Bumped on every change clients can see
+    pub version: u64,
+    /// The player who gave the wire cutters to their current holder
+    last_cutter: Option<PLAYER::ID>,
+    self_cuts: HashMap<PLAYER::ID, u8>,
+    /// Unset once the last cut can't be undone anymore
+    last_cut: Option<LastCut<PLAYER::ID>>,
+    /// Players whose role already replaced a cable
+    spent_roles: HashSet<PLAYER::ID>,
+    /// What the roles did during the last cut, for `handle_cut` to announce it
+    role_effects: Vec<(PLAYER::ID, Role, Effect<PLAYER::ID>)>,
+    /// Claims made during the current round
+    claims: HashMap<PLAYER::ID, Claim>,
+    /// Cables nobody holds, that anyone may cut, only dealt on small tables
+    neutral: Option<Vec<Cable>>,
+    /// The holder of the wire cutters already cut the neutral hand, they must cut a player now
+    neutral_cut: bool,
+    /// The teams as they were dealt, whoever left since
+    team_sizes: TeamSizes,
     defusing_remaining: usize,
     cutted_count: usize,
+    turn: usize,
+    round: usize,
+    history: Vec<HistoryEntry<PLAYER::ID>>,
+    /// Players who left the game on their own, they still lose it
+    forfeited: Vec<PLAYER>,
+    rules: Rules,
+    rng: StdRng,
 }
 
-impl<PLAYER: PlayingPlayer> Game<PLAYER> {
-    const fn cables_count(player_count: usize) -> (usize, usize, usize) {
-        let defusing = player_count;
-        let bomb = 1;
-        let safe = player_count * 5 - defusing - bomb;
-
-        (safe, defusing, bomb)
-    }
+/// What is needed to undo a cut
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LastCut<ID> {
+    pub cutting: ID,
+    pub cutted: ID,
+    pub index: usize,
+    pub cable: Cable,
+    pub turn: usize,
+    last_cutter: Option<ID>,
+    /// Whether the neutral hand had been cut during the turn
+    neutral_cut: bool,
+    /// What the cable counted as, see [`Effect::Replace`]
+    counted: Cable,
+    /// Players whose role was spent by the cut
+    spent: Vec<ID>,
+}
 
+impl<PLAYER: PlayingPlayer> Game<PLAYER> {
     pub fn new<T: WaitingPlayer<ID = PLAYER::ID>>(
         name: String,
         players: &HashMap<T::ID, T>,
+        host: PLAYER::ID,
+        rules: Rules,
+        moriarty_streaks: &HashMap<PLAYER::ID, u32>,
     ) -> Self {
-        let mut teams = match players.len() {
-            4..=5 => repeated_vec![3 => Team::Sherlock, 2 => Team::Moriarty],
-            6 => repeated_vec![4 => Team::Sherlock, 2 => Team::Moriarty],
-            7..=8 => repeated_vec![5 => Team::Sherlock, 3 => Team::Moriarty],
-            _ => unreachable!(),
-        };
-        teams.shuffle(&mut thread_rng());
+        let mut rng = rules
+            .seed
+            .map_or_else(StdRng::from_entropy, StdRng::seed_from_u64);
 
-        let players: HashMap<_, _> = players
-            .iter()
+        let mut teams = rules
+            .team_sizes
+            .map_or_else(|| rules.teams.teams(players.len()), TeamSizes::teams);
+        teams.shuffle(&mut rng);
+        teams.truncate(players.len());
+        let team_sizes = TeamSizes::count(&teams);
+
+        // the order of the map changes from one process to the next, the same seed must give the
+        // same table
+        let mut order: Vec<_> = players.iter().collect();
+        order.sort_unstable_by_key(|(id, _)| **id);
+        let ids: Vec<_> = order.iter().map(|(id, _)| **id).collect();
+        if rules.balance_teams {
+            // the longer the streak, the more likely the player gets one of the first Sherlock cards
+            teams.sort_unstable_by_key(|team| *team == Team::Moriarty);
+            let mut weighted: Vec<_> = order
+                .into_iter()
+                .map(|player| {
+                    let streak = moriarty_streaks.get(player.0).copied().unwrap_or(0);
+                    (rng.gen::<f64>() * f64::from(streak + 1), player)
+                })
+                .collect();
+            weighted.sort_by(|a, b| b.0.total_cmp(&a.0));
+            order = weighted.into_iter().map(|(_, player)| player).collect();
+        }
+
+        let mut players: HashMap<_, _> = order
+            .into_iter()
             .zip(teams)
             .map(|((id, player), team)| (*id, PLAYER::new(player, team)))
             .collect();
+        // a role is only dealt if someone of its team doesn't have one yet
+        for role in &rules.roles {
+            if let Some(id) = ids
+                .iter()
+                .filter(|id| players[*id].team() == role.team() && players[*id].role().is_none())
+                .choose(&mut rng)
+            {
+                players.get_mut(id).unwrap().set_role(Some(*role));
+            }
+        }
 
-        let (safe_cables, defusing_cables, bomb) = Self::cables_count(players.len());
-        let cables = repeated_vec![safe_cables => Cable::Safe, defusing_cables => Cable::Defusing, bomb => Cable::Bomb];
+        let neutral = (rules.small_table && players.len() < MIN_PLAYERS).then(Vec::new);
+        let (cables, defusing_cables) = rules.deck.build(
+            players.len() + usize::from(neutral.is_some()),
+            rules.variant_cables,
+        );
 
-        let wire_cutters = *players.keys().choose(&mut thread_rng()).unwrap();
+        let wire_cutters = *ids.iter().choose(&mut rng).unwrap();
         let mut new = Self {
+            party: name.clone(),
+            version: 0,
             name,
             players,
             wire_cutters,
+            host,
+            paused: false,
+            waiting_for: HashMap::new(),
+            spectators: 0,
+            turn_deadline: None,
+            last_cutter: None,
+            self_cuts: HashMap::new(),
+            last_cut: None,
+            spent_roles: HashSet::new(),
+            role_effects: Vec::new(),
+            claims: HashMap::new(),
+            neutral,
+            neutral_cut: false,
+            team_sizes,
             defusing_remaining: defusing_cables,
             cutted_count: 0,
+            turn: 0,
+            round: 0,
+            history: Vec::new(),
+            forfeited: Vec::new(),
+            rules,
+            rng,
         };
 
         new.distribute_cables(cables);
@@ -176,61 +877,611 @@ impl<PLAYER: PlayingPlayer> Game<PLAYER> {
         new
     }
 
+    /// Build a game from a scenario, `ids` gives the id of each seat, the first one being the host
+    pub fn from_scenario(
+        name: String,
+        scenario: &Scenario,
+        ids: &[PLAYER::ID],
+    ) -> Result<Self, errors::Scenario> {
+        let players: HashMap<_, _> = scenario
+            .seats
+            .iter()
+            .zip(ids)
+            .map(|(seat, id)| {
+                let mut player = PLAYER::new(&ScenarioPlayer { id: *id, seat }, seat.team);
+                player.set_cables(seat.cables.clone());
+                (*id, player)
+            })
+            .collect();
+        let defusing_remaining = scenario
+            .seats
+            .iter()
+            .flat_map(|seat| &seat.cables)
+            .filter(|cable| **cable == Cable::Defusing)
+            .count();
+        let rules = scenario.rules.clone();
+
+        let mut game = Self {
+            party: name.clone(),
+            version: 0,
+            name,
+            players,
+            wire_cutters: ids[scenario.wire_cutters.min(ids.len() - 1)],
+            host: ids[0],
+            paused: false,
+            waiting_for: HashMap::new(),
+            spectators: 0,
+            turn_deadline: None,
+            last_cutter: None,
+            self_cuts: HashMap::new(),
+            last_cut: None,
+            spent_roles: HashSet::new(),
+            role_effects: Vec::new(),
+            claims: HashMap::new(),
+            neutral: None,
+            neutral_cut: false,
+            team_sizes: TeamSizes::count(
+                &scenario.seats.iter().map(|x| x.team).collect::<Vec<_>>(),
+            ),
+            defusing_remaining,
+            cutted_count: 0,
+            turn: 0,
+            round: 0,
+            history: Vec::new(),
+            forfeited: Vec::new(),
+            rng: rules
+                .seed
+                .map_or_else(StdRng::from_entropy, StdRng::seed_from_u64),
+            rules,
+        };
+
+        for action in &scenario.actions {
+            match *action {
+                Action::Cut { seat, index } => {
+                    let cutted = *ids.get(seat).ok_or(errors::Scenario::UnknownSeat)?;
+                    let events = game.handle_cut(game.wire_cutters, cutted, index)?;
+                    if events.iter().any(|x| matches!(x, CutEvent::Win(_))) {
+                        return Err(errors::Scenario::GameOver);
+                    }
+                }
+            }
+        }
+
+        Ok(game)
+    }
+
     fn distribute_cables(&mut self, mut cables: Vec<Cable>) {
-        cables.shuffle(&mut thread_rng());
+        cables.shuffle(&mut self.rng);
+
+        // deal one by one so nothing is lost when the count isn't a multiple of the player count
+        let seats = self.seats();
+        let hand_size = cables.len().div_ceil(seats);
+        let mut hands: Vec<_> = (0..seats).map(|_| Vec::with_capacity(hand_size)).collect();
+        for (i, cable) in cables.into_iter().enumerate() {
+            hands[i % seats].push(cable);
+        }
+        if let Some(neutral) = &mut self.neutral {
+            *neutral = hands.pop().unwrap();
+        }
+        for (id, hand) in self.sorted_ids().into_iter().zip(hands) {
+            self.players.get_mut(&id).unwrap().set_cables(hand);
+        }
+    }
+
+    /// Ids of the players in an order that doesn't depend on the map, see [`Self::new`]
+    fn sorted_ids(&self) -> Vec<PLAYER::ID> {
+        let mut ids: Vec<_> = self.players.keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Remove a player from the game, handing their uncut cables to the remaining players.
+    ///
+    /// Returns `None` if the player isn't part of the game.
+    pub fn remove_player(&mut self, id: PLAYER::ID) -> Option<CutOutcome> {
+        self.take_player(id).map(|(_, outcome)| outcome)
+    }
+
+    /// [`Self::remove_player`], for a player who is still counted among the losers
+    pub fn forfeit(&mut self, id: PLAYER::ID) -> Option<CutOutcome> {
+        let (player, outcome) = self.take_player(id)?;
+        self.forfeited.push(player);
+        Some(outcome)
+    }
+
+    pub fn forfeited(&self) -> &[PLAYER] {
+        &self.forfeited
+    }
+
+    fn take_player(&mut self, id: PLAYER::ID) -> Option<(PLAYER, CutOutcome)> {
+        let player = self.players.remove(&id)?;
+        self.claims.remove(&id);
+        self.last_cut = None;
+        if self.players.is_empty() {
+            return Some((player, CutOutcome::Nothing));
+        }
+
+        if self.wire_cutters == id {
+            self.wire_cutters = *self.sorted_ids().iter().choose(&mut self.rng).unwrap();
+            self.last_cutter = None;
+            self.history.push(HistoryEntry::Pass {
+                round: self.round,
+                from: id,
+                to: self.wire_cutters,
+            });
+        }
+        if self.host == id {
+            let ids = self.sorted_ids();
+            self.host = *ids
+                .iter()
+                .filter(|id| self.players[*id].connected())
+                .choose(&mut self.rng)
+                .unwrap_or(&ids[0]);
+        }
+
+        let mut cables = player.cables().to_owned();
+        cables.shuffle(&mut self.rng);
+        let mut remaining = self.sorted_ids();
+        remaining.shuffle(&mut self.rng);
+        let mut hands: HashMap<_, Vec<Cable>> = HashMap::new();
+        for (i, cable) in cables.into_iter().enumerate() {
+            let id = remaining[i % remaining.len()];
+            hands
+                .entry(id)
+                .or_insert_with(|| self.players[&id].cables().to_owned())
+                .push(cable);
+        }
+        // the new cables would be at the end of the hands otherwise
+        for id in remaining {
+            if let Some(mut hand) = hands.remove(&id) {
+                hand.shuffle(&mut self.rng);
+                self.players.get_mut(&id).unwrap().set_cables(hand);
+            }
+        }
+
+        let outcome = if self.cutted_count >= self.seats() {
+            CutOutcome::RoundEnd
+        } else {
+            CutOutcome::Nothing
+        };
+        Some((player, outcome))
+    }
 
-        let cables_per_player = cables.len() / self.players.len();
-        for player in self.players.values_mut() {
-            player.set_cables(cables.split_off(cables.len() - cables_per_player));
+    /// Whether `cutting` may cut one of the cables of `cutted` right now
+    pub fn check_cut(&self, cutting: PLAYER::ID, cutted: PLAYER::ID) -> Result<(), errors::Cut> {
+        if self.is_paused() {
+            return Err(errors::Cut::Paused);
+        }
+        if cutting != self.wire_cutters {
+            return Err(errors::Cut::DontHaveWireCutter);
+        }
+        if !self.players.contains_key(&cutted) {
+            return Err(errors::Cut::UnknownPlayer);
         }
+        if cutted == cutting && self.self_cuts_left(cutting) == Some(0) {
+            return Err(errors::Cut::CannotSelfCut);
+        }
+        // with only two players left there would be nobody else to cut
+        if self.rules.no_cut_back && self.last_cutter == Some(cutted) && self.players.len() > 2 {
+            return Err(errors::Cut::CannotCutBack);
+        }
+
+        Ok(())
     }
 
     pub fn cut(
         &mut self,
         cutting: PLAYER::ID,
         cutted: PLAYER::ID,
+        index: usize,
     ) -> Result<(Cable, CutOutcome), errors::Cut> {
+        self.check_cut(cutting, cutted)?;
+
+        let player = self.players.get_mut(&cutted).unwrap();
+        if index >= player.cables().len() {
+            return Err(errors::Cut::InvalidCable);
+        }
+        let cut_cable = player.cut_cable(index);
+        let (cable, spent) = self.apply_roles(&roles::Cut {
+            cutting,
+            cutting_team: self.players[&cutting].team(),
+            cutted,
+            cutted_team: self.players[&cutted].team(),
+            cable: cut_cable,
+        });
+        self.last_cut = Some(LastCut {
+            cutting,
+            cutted,
+            index,
+            cable: cut_cable,
+            turn: self.turn,
+            last_cutter: self.last_cutter,
+            neutral_cut: self.neutral_cut,
+            counted: cable,
+            spent,
+        });
+        if cutted == cutting {
+            *self.self_cuts.entry(cutting).or_default() += 1;
+        } else {
+            self.last_cutter = Some(cutting);
+        }
+        self.neutral_cut = false;
+        self.wire_cutters = cutted;
+        self.turn += 1;
+        self.history.push(HistoryEntry::Cut {
+            round: self.round,
+            cutting,
+            cutted,
+            cable,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |x| x.as_secs()),
+        });
+
+        Ok((cable, self.count_cut(cable)))
+    }
+
+    /// Whether `cutting` may cut one of the cables of the neutral hand right now
+    pub fn check_neutral_cut(&self, cutting: PLAYER::ID) -> Result<(), errors::Cut> {
+        if self.is_paused() {
+            return Err(errors::Cut::Paused);
+        }
         if cutting != self.wire_cutters {
             return Err(errors::Cut::DontHaveWireCutter);
         }
-        if cutted == cutting {
-            return Err(errors::Cut::CannotSelfCut);
+        if self.neutral.is_none() {
+            return Err(errors::Cut::NoNeutralHand);
+        }
+        if self.neutral_cut {
+            return Err(errors::Cut::NeutralAlreadyCut);
         }
 
-        let cable = self.players.get_mut(&cutted).unwrap().cut_cable();
-        self.wire_cutters = cutted;
+        Ok(())
+    }
+
+    /// Cut the cable at `index` in the neutral hand. The holder of the wire cutters keeps them, but
+    /// has to cut a player before they may cut the neutral hand again.
+    pub fn cut_neutral(
+        &mut self,
+        cutting: PLAYER::ID,
+        index: usize,
+    ) -> Result<(Cable, CutOutcome), errors::Cut> {
+        self.check_neutral_cut(cutting)?;
+
+        let neutral = self.neutral.as_mut().unwrap();
+        if index >= neutral.len() {
+            return Err(errors::Cut::InvalidCable);
+        }
+        let cable = neutral.remove(index);
+        self.role_effects.clear();
+        self.last_cut = None;
+        self.neutral_cut = true;
+        self.turn += 1;
+        self.history.push(HistoryEntry::NeutralCut {
+            round: self.round,
+            cutting,
+            cable,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |x| x.as_secs()),
+        });
+
+        Ok((cable, self.count_cut(cable)))
+    }
+
+    /// Count a cable that was just cut, and tell whether it ends the round or the game
+    fn count_cut(&mut self, cable: Cable) -> CutOutcome {
         match cable {
-            Cable::Safe => self.cutted_count += 1,
+            Cable::Safe | Cable::DecoyBomb => self.cutted_count += 1,
+            Cable::ShortFuse => self.cutted_count += 2,
             Cable::Defusing => {
                 self.defusing_remaining -= 1;
                 self.cutted_count += 1;
             }
-            Cable::Bomb => return Ok((cable, CutOutcome::Win(Team::Moriarty))),
+            Cable::Bomb => {
+                self.last_cut = None;
+                return CutOutcome::Win(Team::Moriarty);
+            }
         }
         if self.defusing_remaining == 0 {
-            return Ok((cable, CutOutcome::Win(Team::Sherlock)));
+            self.last_cut = None;
+            return CutOutcome::Win(Team::Sherlock);
         }
 
-        if self.cutted_count == self.players.len() {
-            Ok((cable, CutOutcome::RoundEnd))
+        if self.cutted_count >= self.seats() {
+            self.last_cut = None;
+            CutOutcome::RoundEnd
         } else {
-            Ok((cable, CutOutcome::Nothing))
+            CutOutcome::Nothing
+        }
+    }
+
+    /// Number of hands dealt, the neutral one included
+    fn seats(&self) -> usize {
+        self.players.len() + usize::from(self.neutral.is_some())
+    }
+
+    /// The uncut cables of the neutral hand, if there is one
+    pub fn neutral_hand(&self) -> Option<&[Cable]> {
+        self.neutral.as_deref()
+    }
+
+    /// How many times `id` may still cut one of their own cables, `None` if there is no limit
+    pub fn self_cuts_left(&self, id: PLAYER::ID) -> Option<u8> {
+        (!self.rules.free_self_cuts).then(|| {
+            let used = self.self_cuts.get(&id).copied().unwrap_or(0);
+            self.rules.self_cuts.saturating_sub(used)
+        })
+    }
+
+    /// Let the roles of the players involved change the cut, returns what the cable counts as and
+    /// the players whose role got spent
+    fn apply_roles(&mut self, cut: &roles::Cut<PLAYER::ID>) -> (Cable, Vec<PLAYER::ID>) {
+        self.role_effects.clear();
+        let mut cable = cut.cable;
+        let mut spent = Vec::new();
+        let mut holders = vec![cut.cutting];
+        if cut.cutted != cut.cutting {
+            holders.push(cut.cutted);
+        }
+        for holder in holders {
+            let Some(role) = self.players[&holder].role() else {
+                continue;
+            };
+            let Some(effect) = role.on_cut(holder, cut, self.spent_roles.contains(&holder)) else {
+                continue;
+            };
+            if let Effect::Replace(by) = effect {
+                if self.spent_roles.insert(holder) {
+                    spent.push(holder);
+                }
+                cable = by;
+            }
+            self.role_effects.push((holder, role, effect));
+        }
+
+        (cable, spent)
+    }
+
+    /// The last cut, if it can still be undone
+    pub const fn last_cut(&self) -> Option<&LastCut<PLAYER::ID>> {
+        self.last_cut.as_ref()
+    }
+
+    /// Put the last cable cut back where it was, as if it was never cut
+    pub fn undo(&mut self) -> Result<LastCut<PLAYER::ID>, errors::Undo> {
+        let last = self.last_cut.take().ok_or(errors::Undo::NothingToUndo)?;
+        let player = self
+            .players
+            .get_mut(&last.cutted)
+            .ok_or(errors::Undo::NothingToUndo)?;
+        player.restore_cable(last.index, last.cable);
+
+        if last.cutted == last.cutting {
+            if let Some(count) = self.self_cuts.get_mut(&last.cutting) {
+                *count -= 1;
+            }
+        }
+        for holder in &last.spent {
+            self.spent_roles.remove(holder);
+        }
+        self.last_cutter = last.last_cutter;
+        self.neutral_cut = last.neutral_cut;
+        self.wire_cutters = last.cutting;
+        self.turn -= 1;
+        self.cutted_count -= if last.counted == Cable::ShortFuse {
+            2
+        } else {
+            1
+        };
+        if last.counted == Cable::Defusing {
+            self.defusing_remaining += 1;
+        }
+        if let Some(position) = self
+            .history
+            .iter()
+            .rposition(|x| matches!(x, HistoryEntry::Cut { .. }))
+        {
+            self.history.remove(position);
+        }
+
+        Ok(last)
+    }
+
+    /// Validate and apply a cut, along with the end of round or of game it causes.
+    ///
+    /// Returns what happened, in the order it has to be announced.
+    pub fn handle_cut(
+        &mut self,
+        cutting: PLAYER::ID,
+        cutted: PLAYER::ID,
+        index: usize,
+    ) -> Result<Vec<CutEvent<PLAYER::ID>>, errors::Cut> {
+        if !self.players.contains_key(&cutting) {
+            return Err(errors::Cut::NotInGame);
+        }
+        if !self.players.contains_key(&cutted) {
+            return Err(errors::Cut::UnknownPlayer);
+        }
+
+        let defusing_remaining = self.defusing_remaining;
+        let round = self.round;
+        let (cable, outcome) = self.cut(cutting, cutted, index)?;
+
+        Ok(self.cut_events(cable, defusing_remaining, round, outcome))
+    }
+
+    /// Same as [`Self::handle_cut`], for a cable of the neutral hand
+    pub fn handle_neutral_cut(
+        &mut self,
+        cutting: PLAYER::ID,
+        index: usize,
+    ) -> Result<Vec<CutEvent<PLAYER::ID>>, errors::Cut> {
+        if !self.players.contains_key(&cutting) {
+            return Err(errors::Cut::NotInGame);
+        }
+
+        let defusing_remaining = self.defusing_remaining;
+        let round = self.round;
+        let (cable, outcome) = self.cut_neutral(cutting, index)?;
+
+        Ok(self.cut_events(cable, defusing_remaining, round, outcome))
+    }
+
+    fn cut_events(
+        &mut self,
+        cable: Cable,
+        defusing_remaining: usize,
+        round: usize,
+        outcome: CutOutcome,
+    ) -> Vec<CutEvent<PLAYER::ID>> {
+        let mut events = vec![CutEvent::Cut {
+            cable,
+            defusing_remaining,
+        }];
+        events.extend(
+            self.role_effects
+                .drain(..)
+                .map(|(holder, role, effect)| CutEvent::Role {
+                    holder,
+                    role,
+                    effect,
+                }),
+        );
+        match outcome {
+            CutOutcome::Nothing => (),
+            CutOutcome::Win(team) => events.push(CutEvent::Win(team)),
+            CutOutcome::RoundEnd => {
+                events.push(CutEvent::RoundEnd {
+                    round,
+                    hands: self.hands(),
+                });
+                if self.next_round() {
+                    events.push(CutEvent::Win(Team::Moriarty));
+                } else {
+                    events.push(CutEvent::NextRound);
+                }
+            }
+        }
+
+        events
+    }
+
+    pub const fn rules(&self) -> &Rules {
+        &self.rules
+    }
+
+    pub const fn team_sizes(&self) -> TeamSizes {
+        self.team_sizes
+    }
+
+    pub const fn defusing_remaining(&self) -> usize {
+        self.defusing_remaining
+    }
+
+    pub fn history(&self) -> &[HistoryEntry<PLAYER::ID>] {
+        &self.history
+    }
+
+    /// Declare the content of the hand of `id` for this round, replacing their previous claim
+    pub fn claim(&mut self, id: PLAYER::ID, claim: Claim) -> Result<(), errors::Claim> {
+        let player = self.players.get(&id).ok_or(errors::Claim::NotInGame)?;
+        if claim.defusing + usize::from(claim.bomb) > player.cables().len() {
+            return Err(errors::Claim::TooManyCables);
+        }
+
+        // only the last claim of the round is kept, so claiming over and over doesn't grow the
+        // history
+        if self.claims.insert(id, claim).is_some() {
+            let round = self.round;
+            self.history.retain(|x| {
+                !matches!(x, HistoryEntry::Claim { round: r, player, .. } if *r == round && *player == id)
+            });
+        }
+        self.history.push(HistoryEntry::Claim {
+            round: self.round,
+            player: id,
+            claim,
+        });
+
+        Ok(())
+    }
+
+    pub const fn claims(&self) -> &HashMap<PLAYER::ID, Claim> {
+        &self.claims
+    }
+
+    /// Number of cuts made since the start of the game
+    pub const fn turn(&self) -> usize {
+        self.turn
+    }
+
+    /// Whether the game is paused, or waiting for someone to come back
+    pub fn is_paused(&self) -> bool {
+        self.paused || !self.waiting_for.is_empty()
+    }
+
+    /// Index of the current round, starting from 0
+    pub const fn round(&self) -> usize {
+        self.round
+    }
+
+    /// The uncut cables of every player
+    pub fn hands(&self) -> HashMap<PLAYER::ID, Vec<Cable>> {
+        self.players
+            .iter()
+            .map(|(id, player)| (*id, player.cables().to_vec()))
+            .collect()
+    }
+
+    /// Replace the hand of a player, returns `false` if they aren't part of the game
+    #[cfg(feature = "debug-endpoints")]
+    pub fn force_hand(&mut self, id: PLAYER::ID, cables: Vec<Cable>) -> bool {
+        let Some(player) = self.players.get_mut(&id) else {
+            return false;
+        };
+        player.set_cables(cables);
+        self.defusing_remaining = self
+            .players
+            .values()
+            .flat_map(|p| p.cables())
+            .filter(|cable| **cable == Cable::Defusing)
+            .count();
+
+        true
+    }
+
+    /// Give the wire cutters to a player, returns `false` if they aren't part of the game
+    #[cfg(feature = "debug-endpoints")]
+    pub fn force_wire_cutters(&mut self, id: PLAYER::ID) -> bool {
+        if !self.players.contains_key(&id) {
+            return false;
         }
+        self.wire_cutters = id;
+        self.last_cutter = None;
+
+        true
     }
 
+    /// Deal the cables again, returns `true` when no round is left and Moriarty wins
     pub fn next_round(&mut self) -> bool {
         self.cutted_count = 0;
+        self.claims.clear();
+        self.last_cut = None;
 
-        let cables: Vec<Cable> = self
-            .players
-            .values_mut()
-            .flat_map(|p| p.cables().to_owned())
-            .collect();
+        let mut cables = Vec::with_capacity(self.players.values().map(|p| p.cables().len()).sum());
+        for id in self.sorted_ids() {
+            cables.extend_from_slice(self.players[&id].cables());
+        }
+        if let Some(neutral) = &self.neutral {
+            cables.extend_from_slice(neutral);
+        }
 
-        if cables.len() == self.players.len() {
+        if cables.len() <= self.seats() || self.round + 1 >= self.rules.max_rounds {
             return true;
         }
 
+        self.round += 1;
         self.distribute_cables(cables);
 
         false
@@ -261,6 +1512,30 @@ pub enum CutOutcome {
     Nothing,
 }
 
+/// Something resulting from a cut, see [`Game::handle_cut`]
+pub enum CutEvent<ID> {
+    /// `defusing_remaining` is the number of defusing cables left before this cut
+    Cut {
+        cable: Cable,
+        defusing_remaining: usize,
+    },
+    Win(Team),
+    /// The round with this index is over, followed by `NextRound` or `Win`
+    RoundEnd {
+        round: usize,
+        /// The uncut cables, before they are dealt again
+        hands: HashMap<ID, Vec<Cable>>,
+    },
+    /// The cables have been dealt again
+    NextRound,
+    /// The role of `holder` changed the cut, right after `Cut`
+    Role {
+        holder: ID,
+        role: Role,
+        effect: Effect<ID>,
+    },
+}
+
 pub mod errors {
     use thiserror::Error;
 
@@ -270,13 +1545,374 @@ pub mod errors {
         GameFull,
         #[error("you are already connected to this game")]
         AlreadyConnected,
+        #[error("this game has already started")]
+        Started,
+        #[error("you were kicked from this game")]
+        Kicked,
     }
 
     #[derive(Error, Debug, Clone, Copy)]
+    #[allow(clippy::enum_variant_names)]
     pub enum Cut {
         #[error("you don't have the wire cutter")]
         DontHaveWireCutter,
         #[error("you can't cut one of your own card")]
         CannotSelfCut,
+        #[error("this player doesn't have a cable at this position")]
+        InvalidCable,
+        #[error("you can't cut the player who just cut you")]
+        CannotCutBack,
+        #[error("the game is paused")]
+        Paused,
+        #[error("you are not part of this game")]
+        NotInGame,
+        #[error("this player is not part of this game")]
+        UnknownPlayer,
+        #[error("there is no neutral hand in this game")]
+        NoNeutralHand,
+        #[error("you already cut the neutral hand, you must cut a player")]
+        NeutralAlreadyCut,
+    }
+
+    #[derive(Error, Debug, Clone, Copy)]
+    pub enum Claim {
+        #[error("you are not part of this game")]
+        NotInGame,
+        #[error("you don't have that many cables")]
+        TooManyCables,
+    }
+
+    #[derive(Error, Debug, Clone, Copy)]
+    pub enum Undo {
+        #[error("there is no cut to undo")]
+        NothingToUndo,
+    }
+
+    #[derive(Error, Debug, Clone, Copy)]
+    pub enum Deck {
+        #[error("the deck needs a bomb")]
+        NoBomb,
+        #[error("the deck needs defusing cables")]
+        NoDefusing,
+        #[error("the hands can't hold more than 10 cables")]
+        HandTooBig,
+        #[error("the bombs and defusing cables don't fit in the hands")]
+        DoesntFit,
+    }
+
+    #[derive(Error, Debug, Clone, Copy)]
+    pub enum Scenario {
+        #[error("an action refers to a seat that doesn't exist")]
+        UnknownSeat,
+        #[error("a scripted cut is not allowed: {0}")]
+        Cut(#[from] Cut),
+        #[error("the scripted actions end the game")]
+        GameOver,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Playing {
+        id: u32,
+        team: Team,
+        cables: Vec<Cable>,
+    }
+
+    impl Player for Playing {
+        type ID = u32;
+
+        fn id(&self) -> Self::ID {
+            self.id
+        }
+
+        fn name(&self) -> &str {
+            "player"
+        }
+    }
+
+    impl PlayingPlayer for Playing {
+        fn new<T: WaitingPlayer<ID = Self::ID>>(player: &T, team: Team) -> Self {
+            Self {
+                id: player.id(),
+                team,
+                cables: Vec::new(),
+            }
+        }
+
+        fn connected(&self) -> bool {
+            true
+        }
+
+        fn team(&self) -> Team {
+            self.team
+        }
+
+        fn cables(&self) -> &[Cable] {
+            &self.cables
+        }
+
+        fn set_cables(&mut self, cables: Vec<Cable>) {
+            self.cables = cables;
+        }
+
+        fn cut_cable(&mut self, index: usize) -> Cable {
+            self.cables.remove(index)
+        }
+
+        fn restore_cable(&mut self, index: usize, cable: Cable) {
+            self.cables.insert(index, cable);
+        }
+
+        fn role(&self) -> Option<Role> {
+            None
+        }
+
+        fn set_role(&mut self, _role: Option<Role>) {}
+    }
+
+    /// Four players with the ids 0 to 3, 0 holds the wire cutters
+    fn game(rules: Rules) -> Game<Playing> {
+        let seat = |team, cables: &[Cable]| Seat {
+            name: "player".to_owned(),
+            team,
+            cables: cables.to_vec(),
+        };
+        let scenario = Scenario {
+            description: String::new(),
+            seats: vec![
+                seat(Team::Sherlock, &[Cable::Safe, Cable::Safe]),
+                seat(Team::Sherlock, &[Cable::Safe, Cable::Defusing]),
+                seat(Team::Moriarty, &[Cable::Bomb, Cable::Safe]),
+                seat(Team::Sherlock, &[Cable::Defusing, Cable::Safe]),
+            ],
+            wire_cutters: 0,
+            rules,
+            actions: Vec::new(),
+        };
+        Game::from_scenario("TEST".to_owned(), &scenario, &[0, 1, 2, 3]).unwrap()
+    }
+
+    #[test]
+    fn default_deck_is_valid() {
+        let deck = DeckBuilder::default();
+        for (variants, small_table) in [(false, false), (true, false), (false, true), (true, true)]
+        {
+            assert!(deck.validate(variants, small_table).is_ok());
+        }
+    }
+
+    #[test]
+    fn deck_needs_bomb_and_defusing() {
+        let deck = DeckBuilder {
+            bombs: 0,
+            ..DeckBuilder::default()
+        };
+        assert!(matches!(
+            deck.validate(false, false),
+            Err(errors::Deck::NoBomb)
+        ));
+        let deck = DeckBuilder {
+            defusing_per_player: 0,
+            ..DeckBuilder::default()
+        };
+        assert!(matches!(
+            deck.validate(false, false),
+            Err(errors::Deck::NoDefusing)
+        ));
+    }
+
+    #[test]
+    fn deck_hand_size() {
+        let deck = DeckBuilder {
+            hand_size: 11,
+            ..DeckBuilder::default()
+        };
+        assert!(matches!(
+            deck.validate(false, false),
+            Err(errors::Deck::HandTooBig)
+        ));
+        let deck = DeckBuilder {
+            hand_size: 10,
+            ..DeckBuilder::default()
+        };
+        assert!(deck.validate(true, true).is_ok());
+    }
+
+    #[test]
+    fn deck_must_fit_every_table() {
+        // 4 players, 4 defusing cables and 4 bombs fill 8 slots
+        let deck = DeckBuilder {
+            bombs: 4,
+            defusing_per_player: 1,
+            hand_size: 2,
+            short_fuses: 1,
+            decoy_bombs: 1,
+        };
+        assert!(deck.validate(false, false).is_ok());
+        // not with the variant cables
+        assert!(matches!(
+            deck.validate(true, false),
+            Err(errors::Deck::DoesntFit)
+        ));
+        // nor at a small table, 3 hands with the neutral one
+        assert!(matches!(
+            deck.validate(false, true),
+            Err(errors::Deck::DoesntFit)
+        ));
+    }
+
+    #[test]
+    fn deck_build() {
+        let deck = DeckBuilder {
+            bombs: 2,
+            ..DeckBuilder::default()
+        };
+        let count = |cables: &[Cable], cable| cables.iter().filter(|x| **x == cable).count();
+
+        let (cables, defusing) = deck.build(5, false);
+        assert_eq!(cables.len(), 25);
+        assert_eq!(defusing, 5);
+        assert_eq!(count(&cables, Cable::Defusing), 5);
+        assert_eq!(count(&cables, Cable::Bomb), 2);
+        assert_eq!(count(&cables, Cable::ShortFuse), 0);
+
+        let (cables, _) = deck.build(5, true);
+        assert_eq!(cables.len(), 25);
+        assert_eq!(count(&cables, Cable::ShortFuse), 1);
+        assert_eq!(count(&cables, Cable::DecoyBomb), 1);
+        assert_eq!(count(&cables, Cable::Safe), 16);
+    }
+
+    #[test]
+    fn daily_seed_needs_the_secret() {
+        let daily = Rules::daily(20_000, "secret");
+        assert_eq!(daily.seed, Rules::daily(20_000, "secret").seed);
+        assert_eq!(daily.daily, Some(20_000));
+        assert_ne!(daily.seed, Rules::daily(20_000, "other").seed);
+        assert_ne!(daily.seed, Rules::daily(20_001, "secret").seed);
+    }
+
+    #[test]
+    fn series_keeps_score_with_same_roster() {
+        let mut series = Series::new(2);
+        series.seat(vec![3, 1, 2]);
+        series.record(Some(Team::Sherlock));
+        series.record(None);
+        // the order of the roster doesn't matter
+        series.seat(vec![1, 2, 3]);
+        assert_eq!((series.sherlock, series.moriarty), (1, 0));
+        assert_eq!(series.roster, [1, 2, 3]);
+        assert_eq!(series.winner(), None);
+
+        series.record(Some(Team::Moriarty));
+        series.record(Some(Team::Sherlock));
+        assert_eq!(series.winner(), Some(Team::Sherlock));
+    }
+
+    #[test]
+    fn series_starts_over() {
+        let mut series = Series::new(2);
+        series.seat(vec![1, 2, 3]);
+        series.record(Some(Team::Moriarty));
+        series.seat(vec![1, 2, 4]);
+        assert_eq!((series.sherlock, series.moriarty), (0, 0));
+        assert_eq!(series.target, 2);
+
+        // once someone won
+        series.record(Some(Team::Moriarty));
+        series.record(Some(Team::Moriarty));
+        assert_eq!(series.winner(), Some(Team::Moriarty));
+        series.seat(vec![1, 2, 4]);
+        assert_eq!((series.sherlock, series.moriarty), (0, 0));
+        assert_eq!(series.winner(), None);
+    }
+
+    #[test]
+    fn removed_cables_are_mixed_into_the_hands() {
+        let mut mixed = false;
+        for seed in 0..20 {
+            let mut game = game(Rules {
+                seed: Some(seed),
+                ..Rules::default()
+            });
+            game.remove_player(1);
+            let hands: Vec<_> = [0, 2, 3]
+                .map(|id| game.get_player(id).unwrap().cables.clone())
+                .into();
+            assert_eq!(hands.iter().map(Vec::len).sum::<usize>(), 8);
+            assert_eq!(game.defusing_remaining(), 2);
+            // the cables of the ones who stayed aren't always first
+            mixed |= hands[0][..2] != [Cable::Safe, Cable::Safe]
+                || hands[1][..2] != [Cable::Bomb, Cable::Safe]
+                || hands[2][..2] != [Cable::Defusing, Cable::Safe];
+        }
+        assert!(mixed);
+    }
+
+    #[test]
+    fn undo_restores_the_cut() {
+        let mut game = game(Rules::default());
+        assert!(matches!(game.undo(), Err(errors::Undo::NothingToUndo)));
+
+        game.handle_cut(0, 3, 0).unwrap();
+        assert_eq!(game.defusing_remaining(), 1);
+        assert_eq!(game.wire_cutters, 3);
+
+        let last = game.undo().unwrap();
+        assert_eq!((last.cutting, last.cutted, last.index), (0, 3, 0));
+        assert_eq!(last.cable, Cable::Defusing);
+        assert_eq!(
+            game.get_player(3).unwrap().cables,
+            [Cable::Defusing, Cable::Safe]
+        );
+        assert_eq!(game.defusing_remaining(), 2);
+        assert_eq!(game.wire_cutters, 0);
+        assert_eq!(game.turn(), 0);
+        assert!(game.history().is_empty());
+        assert!(matches!(game.undo(), Err(errors::Undo::NothingToUndo)));
+    }
+
+    #[test]
+    fn undo_only_the_last_cut() {
+        let mut game = game(Rules::default());
+        game.handle_cut(0, 1, 0).unwrap();
+        game.handle_cut(1, 2, 1).unwrap();
+        game.undo().unwrap();
+        assert_eq!(game.wire_cutters, 1);
+        assert_eq!(game.history().len(), 1);
+        assert_eq!(game.get_player(1).unwrap().cables, [Cable::Defusing]);
+        assert!(matches!(game.undo(), Err(errors::Undo::NothingToUndo)));
+    }
+
+    #[test]
+    fn undo_gives_back_the_self_cut() {
+        let mut game = game(Rules {
+            self_cuts: 1,
+            ..Rules::default()
+        });
+        game.handle_cut(0, 0, 0).unwrap();
+        assert_eq!(game.self_cuts_left(0), Some(0));
+        game.undo().unwrap();
+        assert_eq!(game.self_cuts_left(0), Some(1));
+        assert!(game.handle_cut(0, 0, 0).is_ok());
+    }
+
+    #[test]
+    fn undo_restores_the_last_cutter() {
+        let mut game = game(Rules {
+            no_cut_back: true,
+            ..Rules::default()
+        });
+        game.handle_cut(0, 1, 0).unwrap();
+        game.handle_cut(1, 3, 1).unwrap();
+        game.undo().unwrap();
+        assert!(matches!(
+            game.check_cut(1, 0),
+            Err(errors::Cut::CannotCutBack)
+        ));
+        assert!(game.check_cut(1, 2).is_ok());
     }
 }
@@ -0,0 +1,124 @@
+use crate::{
+    common::GlobalState,
+    gameplay::Team,
+    stats::{GameRecord, RecordedPlayer},
+};
+use rocket::{
+    get, routes,
+    serde::{json::Json, Serialize},
+    uri, FromFormField, State,
+};
+
+const DEFAULT_PAGE: usize = 20;
+const MAX_PAGE: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField)]
+enum Outcome {
+    Sherlock,
+    Moriarty,
+    Draw,
+}
+
+impl Outcome {
+    fn matches(self, winner: Option<Team>) -> bool {
+        match self {
+            Self::Sherlock => winner == Some(Team::Sherlock),
+            Self::Moriarty => winner == Some(Team::Moriarty),
+            Self::Draw => winner.is_none(),
+        }
+    }
+}
+
+/// A game of the history, without its moves
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Summary {
+    pub id: u64,
+    pub code: String,
+    pub ended_at: u64,
+    pub winner: Option<Team>,
+    pub players: Vec<RecordedPlayer>,
+    /// Where to get the moves of the game
+    pub replay: String,
+}
+
+impl From<&GameRecord> for Summary {
+    fn from(record: &GameRecord) -> Self {
+        Self {
+            id: record.id,
+            code: record.code.clone(),
+            ended_at: record.ended_at,
+            winner: record.winner,
+            players: record.players.clone(),
+            replay: uri!(replay(record.id)).to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Page {
+    games: Vec<Summary>,
+    /// Value of `before` to get the next page, `None` on the last one
+    next: Option<u64>,
+}
+
+/// Most recent games first, `before` is the id of the last game of the previous page
+#[get("/history?<before>&<limit>&<player>&<result>")]
+fn list(
+    before: Option<u64>,
+    limit: Option<usize>,
+    player: Option<&str>,
+    result: Option<Outcome>,
+    state: &State<GlobalState>,
+) -> Json<Page> {
+    let limit = limit.unwrap_or(DEFAULT_PAGE).clamp(1, MAX_PAGE);
+    let stats = state.stats.lock().unwrap();
+
+    let mut matching = stats
+        .games()
+        .iter()
+        .rev()
+        .skip_while(|x| before.is_some_and(|before| x.id >= before))
+        .filter(|x| {
+            player.is_none_or(|player| x.players.iter().any(|p| p.name.as_deref() == Some(player)))
+        })
+        .filter(|x| result.is_none_or(|result| result.matches(x.winner)));
+    let games: Vec<Summary> = matching.by_ref().take(limit).map(Summary::from).collect();
+    let next = games
+        .last()
+        .filter(|_| matching.next().is_some())
+        .map(|x| x.id);
+
+    Json(Page { games, next })
+}
+
+/// Every game played in a lobby with this code, most recent first
+#[get("/history/<code>")]
+fn by_code(code: &str, state: &State<GlobalState>) -> Json<Vec<Summary>> {
+    Json(
+        state
+            .stats
+            .lock()
+            .unwrap()
+            .games()
+            .iter()
+            .rev()
+            .filter(|x| x.code.eq_ignore_ascii_case(code))
+            .map(Summary::from)
+            .collect(),
+    )
+}
+
+#[get("/history/game/<id>")]
+fn replay(id: u64, state: &State<GlobalState>) -> Option<Json<GameRecord>> {
+    let stats = state.stats.lock().unwrap();
+    let games = stats.games();
+    // ids are increasing, so the games are sorted by id
+    let index = games.binary_search_by_key(&id, |x| x.id).ok()?;
+    Some(Json(games[index].clone()))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![list, by_code, replay]
+}
@@ -0,0 +1,69 @@
+use crate::common::GlobalState;
+use rocket::{
+    fairing::AdHoc,
+    tokio::{
+        self,
+        sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    },
+};
+use std::sync::Mutex;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Runs the cleanup of closed connections in its own task, so dropping a stream stays cheap
+pub struct Janitor {
+    sender: UnboundedSender<Job>,
+    receiver: Mutex<Option<UnboundedReceiver<Job>>>,
+}
+
+impl Default for Janitor {
+    fn default() -> Self {
+        let (sender, receiver) = unbounded_channel();
+        Self {
+            sender,
+            receiver: Mutex::new(Some(receiver)),
+        }
+    }
+}
+
+impl Janitor {
+    pub fn handle(&self) -> JanitorHandle {
+        JanitorHandle(self.sender.clone())
+    }
+}
+
+#[derive(Clone)]
+pub struct JanitorHandle(UnboundedSender<Job>);
+
+impl JanitorHandle {
+    pub fn schedule(&self, job: impl FnOnce() + Send + 'static) {
+        // the janitor only stops with the server, cleaning up right away is fine then
+        if let Err(e) = self.0.send(Box::new(job)) {
+            (e.0)();
+        }
+    }
+}
+
+/// Starts the janitor once the server is running
+pub fn fairing() -> AdHoc {
+    AdHoc::on_liftoff("Janitor", |rocket| {
+        Box::pin(async move {
+            if let Some(state) = rocket.state::<GlobalState>() {
+                start(state);
+            }
+        })
+    })
+}
+
+/// Run the jobs scheduled in `state`, only the first call does anything
+pub fn start(state: &GlobalState) {
+    let Some(mut receiver) = state.janitor.receiver.lock().unwrap().take() else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        while let Some(job) = receiver.recv().await {
+            job();
+        }
+    });
+}
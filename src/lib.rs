@@ -0,0 +1,4 @@
+// the game logic is also a library so the benchmarks can use it
+pub mod agent;
+pub mod gameplay;
+pub mod roles;
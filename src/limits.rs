@@ -0,0 +1,206 @@
+use crate::{common::GlobalState, config::Config};
+use rocket::{
+    http::Status,
+    request::{FromRequest, Outcome, Request},
+    response::{self, Responder},
+    serde::Serialize,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+pub type StreamCounts = Arc<Mutex<HashMap<IpAddr, usize>>>;
+
+/// One of the event streams a client is allowed to keep open, released on drop
+pub struct StreamSlot {
+    counts: StreamCounts,
+    ip: IpAddr,
+}
+
+/// Why an event stream was refused, sent as the only event of the stream
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(untagged)]
+pub enum Refused {
+    Error { reason: &'static str },
+    Busy { retry_after: u64 },
+}
+
+impl Refused {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Error { .. } => "error",
+            Self::Busy { .. } => "busy",
+        }
+    }
+
+    /// Seconds the client should wait before reconnecting
+    pub const fn retry_after(&self) -> Option<u64> {
+        match self {
+            Self::Error { .. } => None,
+            Self::Busy { retry_after } => Some(*retry_after),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for StreamSlot {
+    type Error = Refused;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(ip) = request.client_ip() else {
+            return Outcome::Error((
+                Status::BadRequest,
+                Refused::Error {
+                    reason: "Unknown address",
+                },
+            ));
+        };
+        let state = request.rocket().state::<GlobalState>().unwrap();
+        let config = request.rocket().state::<Config>().unwrap();
+
+        let mut counts = state.streams.lock().unwrap();
+        if config
+            .max_streams
+            .is_some_and(|max| counts.values().sum::<usize>() >= max)
+        {
+            return Outcome::Error((
+                Status::ServiceUnavailable,
+                Refused::Busy {
+                    retry_after: config.busy_retry_after,
+                },
+            ));
+        }
+        let count = counts.entry(ip).or_default();
+        if config.max_streams_per_ip.is_some_and(|max| *count >= max) {
+            return Outcome::Error((
+                Status::TooManyRequests,
+                Refused::Error {
+                    reason: "Too many connections from your address",
+                },
+            ));
+        }
+        *count += 1;
+        drop(counts);
+
+        Outcome::Success(Self {
+            counts: Arc::clone(&state.streams),
+            ip,
+        })
+    }
+}
+
+/// Adds a `Retry-After` header to the response when the stream was refused because the
+/// server is busy
+pub struct Shed<R> {
+    retry_after: Option<u64>,
+    inner: R,
+}
+
+impl<R> Shed<R> {
+    pub fn new(refused: Option<Refused>, inner: R) -> Self {
+        Self {
+            retry_after: refused.as_ref().and_then(Refused::retry_after),
+            inner,
+        }
+    }
+}
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for Shed<R> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'o> {
+        let mut response = self.inner.respond_to(request)?;
+        if let Some(retry_after) = self.retry_after {
+            response.set_raw_header("Retry-After", retry_after.to_string());
+        }
+        Ok(response)
+    }
+}
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().unwrap();
+        if let Some(count) = counts.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// Above this many tracked addresses, the inactive ones are forgotten
+const MAX_TRACKED: usize = 1024;
+
+#[derive(Debug, Default)]
+struct Activity {
+    actions: VecDeque<Instant>,
+    blocked_until: Option<Instant>,
+}
+
+impl Activity {
+    fn forget_before(&mut self, start: Instant) {
+        while self.actions.front().is_some_and(|x| *x < start) {
+            self.actions.pop_front();
+        }
+    }
+
+    fn is_blocked(&self, now: Instant) -> bool {
+        self.blocked_until.is_some_and(|x| x > now)
+    }
+}
+
+/// Recent lobby creations, joins and leaves of each IP address
+#[derive(Debug, Default)]
+pub struct Abuse(Mutex<HashMap<IpAddr, Activity>>);
+
+/// Proof that the client is allowed to create, join or leave a lobby
+pub struct Throttled;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Throttled {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(ip) = request.client_ip() else {
+            return Outcome::Error((Status::BadRequest, ()));
+        };
+        let state = request.rocket().state::<GlobalState>().unwrap();
+        let config = request.rocket().state::<Config>().unwrap();
+        let Some(max) = config.abuse_max_actions else {
+            return Outcome::Success(Self);
+        };
+
+        let now = Instant::now();
+        let window_start = now.checked_sub(Duration::from_secs(config.abuse_window));
+        let mut activities = state.abuse.0.lock().unwrap();
+        if activities.len() > MAX_TRACKED {
+            activities.retain(|_, activity| {
+                if let Some(start) = window_start {
+                    activity.forget_before(start);
+                }
+                !activity.actions.is_empty() || activity.is_blocked(now)
+            });
+        }
+
+        let activity = activities.entry(ip).or_default();
+        if activity.is_blocked(now) {
+            return Outcome::Error((Status::TooManyRequests, ()));
+        }
+        if let Some(start) = window_start {
+            activity.forget_before(start);
+        }
+        activity.actions.push_back(now);
+
+        if activity.actions.len() > max {
+            rocket::warn!("{ip} is creating, joining and leaving lobbies too fast, blocking it");
+            activity.actions.clear();
+            activity.blocked_until = Some(now + Duration::from_secs(config.abuse_block));
+            return Outcome::Error((Status::TooManyRequests, ()));
+        }
+
+        Outcome::Success(Self)
+    }
+}
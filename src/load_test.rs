@@ -0,0 +1,369 @@
+//! `--load-test [url] [--clients n]`: plays games against a running server with simulated clients
+//! and reports how long it took to answer.
+//!
+//! Every client comes from the same address, the target should run with `max_streams_per_ip` and
+//! `abuse_max_actions` disabled.
+
+use crate::sse;
+use reqwest::{
+    header::{COOKIE, LOCATION, SET_COOKIE},
+    redirect, Response,
+};
+use rocket::{
+    serde::json::{self, Value},
+    tokio::{self, sync::mpsc::UnboundedReceiver, time::timeout},
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Clients of a table, as many as needed to start a game
+const TABLE_SIZE: usize = 4;
+/// A client waiting longer than this for an event gives up on its game
+const STALL: Duration = Duration::from_secs(60);
+
+pub struct Options {
+    target: String,
+    clients: usize,
+}
+
+impl Options {
+    /// `None` when the server should run normally
+    pub fn from_args() -> Option<Self> {
+        let mut args = env::args().skip(1).peekable();
+        let mut target = None;
+        let mut clients = 200;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--load-test" => {
+                    let url = args.next_if(|x| !x.starts_with("--"));
+                    target = Some(url.unwrap_or_else(|| "http://localhost:8000".to_owned()));
+                }
+                "--clients" => {
+                    if let Some(Ok(n)) = args.next().map(|x| x.parse()) {
+                        clients = n;
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        target.map(|target| Self {
+            target: target.trim_end_matches('/').to_owned(),
+            clients: clients.max(TABLE_SIZE),
+        })
+    }
+}
+
+#[derive(Default)]
+struct Report {
+    latencies: Mutex<BTreeMap<&'static str, Vec<Duration>>>,
+    errors: Mutex<BTreeMap<String, usize>>,
+}
+
+impl Report {
+    fn record(&self, what: &'static str, latency: Duration) {
+        self.latencies
+            .lock()
+            .unwrap()
+            .entry(what)
+            .or_default()
+            .push(latency);
+    }
+
+    fn error(&self, error: String) {
+        *self.errors.lock().unwrap().entry(error).or_default() += 1;
+    }
+
+    fn print(&self, elapsed: Duration) {
+        println!("finished in {:.1}s", elapsed.as_secs_f64());
+        println!(
+            "{:<12} {:>7} {:>9} {:>9} {:>9} {:>9}",
+            "", "count", "p50 (ms)", "p90 (ms)", "p99 (ms)", "max (ms)"
+        );
+        for (what, latencies) in self.latencies.lock().unwrap().iter_mut() {
+            latencies.sort_unstable();
+            let percentile = |p: usize| {
+                let index = (latencies.len() * p / 100).min(latencies.len() - 1);
+                latencies[index].as_secs_f64() * 1000.
+            };
+            println!(
+                "{what:<12} {:>7} {:>9.1} {:>9.1} {:>9.1} {:>9.1}",
+                latencies.len(),
+                percentile(50),
+                percentile(90),
+                percentile(99),
+                percentile(100),
+            );
+        }
+
+        let errors = self.errors.lock().unwrap();
+        if !errors.is_empty() {
+            println!("errors:");
+            for (error, count) in errors.iter() {
+                println!("{count:>7} {error}");
+            }
+        }
+    }
+}
+
+/// A simulated browser, keeping its own cookies
+struct Client {
+    http: reqwest::Client,
+    target: Arc<str>,
+    cookies: HashMap<String, String>,
+    report: Arc<Report>,
+}
+
+impl Client {
+    async fn get(&mut self, what: &'static str, path: &str) -> Result<Response, String> {
+        let cookies: Vec<_> = self
+            .cookies
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect();
+
+        let start = Instant::now();
+        let response = self
+            .http
+            .get(format!("{}{path}", self.target))
+            .header(COOKIE, cookies.join("; "))
+            .send()
+            .await
+            .map_err(|e| format!("{what}: {e}"))?;
+        self.report.record(what, start.elapsed());
+
+        for cookie in response.headers().get_all(SET_COOKIE) {
+            let Some((pair, attributes)) = cookie
+                .to_str()
+                .ok()
+                .map(|x| x.split_once(';').unwrap_or((x, "")))
+            else {
+                continue;
+            };
+            let Some((name, value)) = pair.split_once('=') else {
+                continue;
+            };
+            if attributes.contains("Max-Age=0") {
+                self.cookies.remove(name);
+            } else {
+                self.cookies.insert(name.to_owned(), value.to_owned());
+            }
+        }
+
+        let status = response.status();
+        if status.is_client_error() || status.is_server_error() {
+            return Err(format!("{what}: {status}"));
+        }
+        Ok(response)
+    }
+
+    /// Open an event stream, yielding the name and data of its events
+    async fn events(&mut self, what: &'static str, path: &str) -> Result<Events, String> {
+        Ok(sse::read(self.get(what, path).await?))
+    }
+}
+
+type Events = UnboundedReceiver<(String, String)>;
+
+fn parse(data: &str) -> Value {
+    json::from_str(data).unwrap_or(Value::Null)
+}
+
+async fn wait_for(events: &mut Events, name: &str) -> Result<Value, String> {
+    loop {
+        match timeout(STALL, events.recv()).await {
+            Ok(Some((event, data))) if event == name => return Ok(parse(&data)),
+            Ok(Some((event, data))) if event == "error" || event == "kicked" => {
+                return Err(format!(
+                    "{event} while waiting for {name}: {}",
+                    parse(&data)["reason"]
+                ));
+            }
+            Ok(Some(_)) => (),
+            Ok(None) => return Err(format!("stream closed while waiting for {name}")),
+            Err(_) => return Err(format!("no {name} event")),
+        }
+    }
+}
+
+fn as_id(value: &Value) -> u64 {
+    value.as_u64().unwrap_or_default()
+}
+
+/// Follow the game, cutting a random cable whenever the client has the wire cutters
+async fn play(mut client: Client) -> Result<(), String> {
+    let mut events = client.events("game events", "/game/events").await?;
+    let init = wait_for(&mut events, "init").await?;
+    let me = as_id(&init["player"]);
+    let mut wire_cutters = as_id(&init["wire_cutters"]);
+    let mut last_cutter = None;
+    let mut hands: HashMap<u64, u64> = init["players"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|x| (as_id(&x["id"]), x["hand_size"].as_u64().unwrap_or_default()))
+        .collect();
+    let mut cut_sent: Option<Instant> = None;
+    let mut idle = Duration::ZERO;
+
+    loop {
+        if wire_cutters == me && cut_sent.is_none() {
+            let targets: Vec<_> = hands
+                .iter()
+                .filter(|(id, size)| {
+                    **id != me && **size > 0 && (hands.len() <= 2 || last_cutter != Some(**id))
+                })
+                .collect();
+            if let Some((target, size)) =
+                targets.get(rand::random::<usize>() % targets.len().max(1))
+            {
+                let index = rand::random::<u64>() % **size;
+                let sent = Instant::now();
+                // refused while the next round counts down, tried again on the next event
+                if client
+                    .get("cut", &format!("/game/cut?player={target}&index={index}"))
+                    .await
+                    .is_ok()
+                {
+                    cut_sent = Some(sent);
+                }
+            }
+        }
+
+        let (event, data) = match timeout(Duration::from_secs(1), events.recv()).await {
+            Ok(Some((event, data))) => {
+                idle = Duration::ZERO;
+                (event, parse(&data))
+            }
+            Ok(None) => return Err("game stream closed".to_owned()),
+            Err(_) => {
+                idle += Duration::from_secs(1);
+                if idle >= STALL {
+                    return Err("game stalled".to_owned());
+                }
+                continue;
+            }
+        };
+        match event.as_str() {
+            "cut" => {
+                let cutted = as_id(&data["player"]);
+                if let Some(size) = hands.get_mut(&cutted) {
+                    *size = size.saturating_sub(1);
+                }
+                if let Some(sent) = cut_sent.take() {
+                    client.report.record("cut event", sent.elapsed());
+                }
+                if cutted != wire_cutters {
+                    last_cutter = Some(wire_cutters);
+                }
+                wire_cutters = cutted;
+            }
+            "redistribution" => {
+                if let Some(sizes) = data["hands"].as_object() {
+                    hands = sizes
+                        .iter()
+                        .filter_map(|(id, size)| Some((id.parse().ok()?, size.as_u64()?)))
+                        .collect();
+                }
+            }
+            "removed" | "forfeit" => {
+                hands.remove(&as_id(&data["player"]));
+            }
+            "win" | "draw" | "expired" => return Ok(()),
+            "error" => return Err(format!("error: {}", data["reason"])),
+            _ => (),
+        }
+    }
+}
+
+async fn table(number: usize, target: Arc<str>, report: Arc<Report>) -> Result<(), String> {
+    let http = reqwest::Client::builder()
+        .redirect(redirect::Policy::none())
+        .build()
+        .map_err(|e| e.to_string())?;
+    let mut clients: Vec<_> = (0..TABLE_SIZE)
+        .map(|_| Client {
+            http: http.clone(),
+            target: Arc::clone(&target),
+            cookies: HashMap::new(),
+            report: Arc::clone(&report),
+        })
+        .collect();
+
+    let created = clients[0]
+        .get("create", &format!("/lobby/create?name=load-{number}-0"))
+        .await?;
+    let Some(join) = created
+        .headers()
+        .get(LOCATION)
+        .and_then(|x| x.to_str().ok())
+        .map(ToOwned::to_owned)
+    else {
+        return Err("create: no redirection".to_owned());
+    };
+    clients[0].get("join", &join).await?;
+    let code = join
+        .split(['?', '&'])
+        .find_map(|x| x.strip_prefix("lobby="))
+        .ok_or("create: no lobby code")?;
+    for (i, client) in clients.iter_mut().enumerate().skip(1) {
+        client
+            .get(
+                "join",
+                &format!("/lobby/join?lobby={code}&name=load-{number}-{i}"),
+            )
+            .await?;
+    }
+
+    let mut lobbies = Vec::new();
+    for client in &mut clients {
+        lobbies.push(client.events("lobby events", "/lobby/events").await?);
+    }
+    for client in &mut clients {
+        client.get("ready", "/lobby/ready?state=true").await?;
+    }
+    clients[0].get("start", "/lobby/start").await?;
+    for events in &mut lobbies {
+        wait_for(events, "start").await?;
+    }
+
+    let players: Vec<_> = clients.into_iter().map(|x| tokio::spawn(play(x))).collect();
+    for player in players {
+        player.await.map_err(|e| e.to_string())??;
+    }
+
+    Ok(())
+}
+
+pub async fn run(options: Options) {
+    let tables = options.clients / TABLE_SIZE;
+    println!(
+        "playing {tables} games with {} clients against {}",
+        tables * TABLE_SIZE,
+        options.target
+    );
+
+    let target: Arc<str> = options.target.into();
+    let report = Arc::new(Report::default());
+    let start = Instant::now();
+    let handles: Vec<_> = (0..tables)
+        .map(|number| {
+            let target = Arc::clone(&target);
+            let report = Arc::clone(&report);
+            tokio::spawn(async move {
+                if let Err(e) = table(number, target, Arc::clone(&report)).await {
+                    report.error(e);
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        let _ = handle.await;
+    }
+
+    report.print(start.elapsed());
+}
@@ -1,7 +1,21 @@
 use crate::{
-    common::{make_event, GlobalState, Protected},
+    account,
+    admin::Kind,
+    channel::{channel, Sender},
+    chat::ChatMessage,
+    common::{
+        self, make_event, GlobalState, IfNoneMatch, NoRoom, PlayerId, Polled, Protected, Registry,
+    },
+    config::{Config, NameCollision, Theme},
     game,
-    gameplay::{self, errors, Game, Lobby, PlayingPlayer, Room},
+    gameplay::{
+        self, errors, DeckBuilder, Game, Lobby, Password, Preset, Room, RoundReveal, Rules, Series,
+        TeamDistribution, TeamSizes, MAX_PLAYERS, MIN_PLAYERS, SMALL_TABLE_MIN_PLAYERS,
+    },
+    janitor::JanitorHandle,
+    limits::{Refused, Shed, StreamSlot, Throttled},
+    roles::Role,
+    stats,
 };
 use rand::{
     distributions::{Alphanumeric, DistString},
@@ -9,24 +23,23 @@ use rand::{
 };
 use rocket::{
     get,
-    http::{CookieJar, Status},
+    http::{CookieJar, RawStr, Status},
+    post,
     request::{FromRequest, Outcome, Request},
     response::{
+        status::BadRequest,
         stream::{Event, EventStream},
         Redirect,
     },
     routes,
-    serde::Serialize,
-    tokio::{
-        self, select,
-        sync::mpsc::{unbounded_channel, UnboundedSender},
-    },
-    uri, Shutdown, State,
+    serde::{json::Json, Serialize},
+    tokio::{self, select},
+    uri, FromForm, Shutdown, State,
 };
 use std::{
     collections::HashMap,
-    sync::{Arc, Mutex},
-    time::Duration,
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
 };
 
 #[derive(Debug, Clone, Serialize)]
@@ -35,8 +48,25 @@ pub struct Player {
     id: <Self as gameplay::Player>::ID,
     name: String,
     ready: bool,
+    /// Played by a program using the `/bot` endpoints
+    bot: bool,
+    /// Keep the name of the player out of the stats
+    #[serde(skip)]
+    no_stats: bool,
+    /// See [`stats::key`], the stats of the players without one are kept under their name
+    #[serde(skip)]
+    stats_key: Option<String>,
+    #[serde(skip)]
+    sender: Sender<Message>,
     #[serde(skip)]
-    sender: UnboundedSender<Message>,
+    last_active: Instant,
+}
+
+impl Player {
+    /// Send a message to this player only, a dead stream is noticed by the next broadcast
+    fn send(&self, msg: Message) {
+        let _ = self.sender.send(msg);
+    }
 }
 
 impl gameplay::Player for Player {
@@ -65,9 +95,17 @@ enum Message {
     Error {
         reason: &'static str,
     },
+    /// Some messages were dropped because the client is too slow, it must fetch `/lobby/state`
+    Resync,
+    /// The room the client was in doesn't exist anymore
+    Expired,
     Initialize {
         lobby: String,
         players: Vec<Player>,
+        host: Option<<Player as gameplay::Player>::ID>,
+        rules: Rules,
+        /// Seconds left before the lobby is closed, for the clients joining late
+        closes_in: Option<u64>,
     },
     Join {
         player: Player,
@@ -80,18 +118,49 @@ enum Message {
         state: bool,
     },
     Start,
+    /// Only the host can start the game, change the settings and kick players
+    Host {
+        player: Option<<Player as gameplay::Player>::ID>,
+    },
+    Kicked {
+        reason: &'static str,
+    },
+    Closed {
+        reason: &'static str,
+    },
+    Settings {
+        rules: Rules,
+    },
+    Signal {
+        from: <Player as gameplay::Player>::ID,
+        data: String,
+    },
+    Speaking {
+        player: <Player as gameplay::Player>::ID,
+        state: bool,
+    },
+    Chat(ChatMessage),
 }
 
 impl Message {
     const fn name(&self) -> &'static str {
         match self {
-            Self::SelfLeave { .. } => unreachable!(),
+            Self::SelfLeave => unreachable!(),
             Self::Error { .. } => "error",
+            Self::Resync => "resync",
+            Self::Expired => "expired",
             Self::Initialize { .. } => "init",
             Self::Join { .. } => "join",
             Self::Leave { .. } => "leave",
             Self::Ready { .. } => "ready",
-            Self::Start { .. } => "start",
+            Self::Start => "start",
+            Self::Host { .. } => "host",
+            Self::Kicked { .. } => "kicked",
+            Self::Closed { .. } => "closed",
+            Self::Settings { .. } => "settings",
+            Self::Signal { .. } => "signal",
+            Self::Speaking { .. } => "speaking",
+            Self::Chat(_) => "chat",
         }
     }
 }
@@ -99,73 +168,369 @@ impl Message {
 impl Protected<Lobby<Player>> {
     #[allow(clippy::significant_drop_in_scrutinee)]
     fn broadcast(&self, msg: &Message) {
-        for player in self.lock().players().values() {
-            player.sender.send(msg.clone()).unwrap();
+        // a failed send means the stream of the player stopped without cleaning up after itself
+        let mut dead = Vec::new();
+        {
+            let mut lobby = self.lock();
+            lobby.version += 1;
+            for player in lobby.players().values() {
+                if player.sender.send(msg.clone()).is_err() {
+                    dead.push(player.id);
+                }
+            }
+        }
+
+        for id in dead {
+            let new_host = self.lock().remove_player(id);
+            self.announce_leave(id, new_host);
+        }
+    }
+
+    fn announce_leave(&self, id: <Player as gameplay::Player>::ID, new_host: bool) {
+        self.broadcast(&Message::Leave { player: id });
+        if new_host {
+            let host = self.lock().host();
+            self.broadcast(&Message::Host { player: host });
         }
     }
 }
 
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for Protected<Lobby<Player>> {
-    type Error = ();
+    type Error = NoRoom;
 
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
         let Some(lobby) = request.cookies().get_private("lobby") else {
-            return Outcome::Error((Status::NotFound, ()));
+            return Outcome::Error((Status::NotFound, NoRoom::NotJoined));
         };
-        let lobbys = request
+        let found = request
             .guard::<&State<GlobalState>>()
             .await
             .unwrap()
             .lobbys
-            .lock()
-            .unwrap();
-
-        lobbys.get(lobby.value()).map_or_else(
-            || Outcome::Error((Status::NotFound, ())),
-            |x| Outcome::Success(Self::clone(x)),
-        )
+            .get(lobby.value());
+        match found {
+            Some(room) => Outcome::Success(room),
+            None => NoRoom::check(request, lobby.value()).await,
+        }
     }
 }
 
-struct ConnectionGuard<'a> {
-    lobbys: &'a Mutex<HashMap<String, Protected<Lobby<Player>>>>,
+struct ConnectionGuard {
+    lobbys: Weak<Registry<Lobby<Player>>>,
     lobby: Protected<Lobby<Player>>,
     id: <Player as gameplay::Player>::ID,
+    channel: u64,
+    janitor: JanitorHandle,
 }
 
-impl<'a> Drop for ConnectionGuard<'a> {
+impl Drop for ConnectionGuard {
     fn drop(&mut self) {
-        self.lobby.broadcast(&Message::Leave { player: self.id });
+        let lobbys = Weak::clone(&self.lobbys);
+        let lobby = self.lobby.clone();
+        let id = self.id;
+        let channel = self.channel;
+        self.janitor
+            .schedule(move || leave_lobby(lobbys, &lobby, id, channel));
+    }
+}
+
+fn leave_lobby(
+    lobbys: Weak<Registry<Lobby<Player>>>,
+    lobby: &Protected<Lobby<Player>>,
+    id: <Player as gameplay::Player>::ID,
+    channel: u64,
+) {
+    let new_host = {
+        let mut lobby = lobby.lock();
+        // the player may have reconnected in the meantime
+        if lobby
+            .get_player(id)
+            .is_none_or(|player| player.sender.id() != channel)
         {
-            let mut lobby = self.lobby.lock();
-            lobby.remove_player(self.id);
+            return;
+        }
+        lobby.remove_player(id)
+    };
+    lobby.announce_leave(id, new_host);
+    if !lobby.lock().players().is_empty() {
+        return;
+    }
+
+    // give the last player a chance to come back, they may just have refreshed the page
+    let lobby = lobby.downgrade();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        let lobbys = lobbys.upgrade()?;
+        let lobby = lobby.upgrade()?;
+        let name = lobby.lock().name().to_owned();
+        let mut lobbys = lobbys.shard(&name);
+        // the lobby may already have been replaced by another one with the same name, or someone
+        // may have joined in the meantime
+        if lobbys.get(&name)?.ptr_eq(&lobby) && lobby.lock().players().is_empty() {
+            lobbys.remove(&name);
+        }
 
-            if lobby.players().is_empty() {
-                self.lobbys.lock().unwrap().remove(lobby.name());
+        Some(())
+    });
+}
+
+/// Seat a player that is played by the server instead of an event stream, `on_start` is given the
+/// game once the lobby started
+pub fn seat_remote(
+    state: &GlobalState,
+    lobby: &str,
+    name: String,
+    password: Option<&str>,
+    config: &Config,
+    on_start: impl FnOnce(Protected<Game<game::Player>>, <Player as gameplay::Player>::ID)
+        + Send
+        + 'static,
+) -> Result<<Player as gameplay::Player>::ID, &'static str> {
+    let Some(lobby) = state.lobbys.get(&lobby.to_uppercase()) else {
+        return Err("Lobby not found");
+    };
+    let admit = |lobby: &Lobby<Player>| {
+        if lobby.check_password(password) {
+            Ok(())
+        } else {
+            Err(WRONG_PASSWORD)
+        }
+    };
+
+    seat(state, lobby, name, config.name_collision, admit, on_start)
+}
+
+/// Same as [`seat_remote`] for a bot added by the host of the lobby, numbered after the first one
+/// whatever the name policy is
+pub fn add_bot(
+    state: &GlobalState,
+    lobby: Protected<Lobby<Player>>,
+    host: <Player as gameplay::Player>::ID,
+    on_start: impl FnOnce(Protected<Game<game::Player>>, <Player as gameplay::Player>::ID)
+        + Send
+        + 'static,
+) -> Result<<Player as gameplay::Player>::ID, &'static str> {
+    let admit = |lobby: &Lobby<Player>| {
+        if lobby.is_host(host) {
+            Ok(())
+        } else {
+            Err("Only the host can add bots")
+        }
+    };
+
+    seat(
+        state,
+        lobby,
+        "Bot".to_owned(),
+        NameCollision::Suffix,
+        admit,
+        on_start,
+    )
+}
+
+fn seat(
+    state: &GlobalState,
+    lobby: Protected<Lobby<Player>>,
+    name: String,
+    collision: NameCollision,
+    admit: impl FnOnce(&Lobby<Player>) -> Result<(), &'static str>,
+    on_start: impl FnOnce(Protected<Game<game::Player>>, <Player as gameplay::Player>::ID)
+        + Send
+        + 'static,
+) -> Result<<Player as gameplay::Player>::ID, &'static str> {
+    let (sender, mut receiver) = channel();
+    let channel = sender.id();
+    let id = {
+        let mut locked = lobby.lock();
+        admit(&locked)?;
+        let name = pick_name(&locked, name, collision)?;
+        let mut id = random();
+        while locked.players().contains_key(&id) {
+            id = random();
+        }
+        let player = Player {
+            id,
+            name,
+            ready: true,
+            bot: true,
+            no_stats: false,
+            stats_key: None,
+            sender,
+            last_active: Instant::now(),
+        };
+        locked.add_player(player.clone()).map_err(|e| match e {
+            errors::Join::GameFull => "This lobby is full",
+            errors::Join::AlreadyConnected => "You are already connected to this game",
+            errors::Join::Started => "This game has already started",
+            errors::Join::Kicked => KICKED,
+        })?;
+        drop(locked);
+        lobby.broadcast(&Message::Join { player });
+        id
+    };
+
+    let games = Arc::clone(&state.games);
+    let guard = ConnectionGuard {
+        lobbys: Arc::downgrade(&state.lobbys),
+        lobby,
+        id,
+        channel,
+        janitor: state.janitor.handle(),
+    };
+    tokio::spawn(async move {
+        while let Some(msg) = receiver.recv(|| Message::Resync).await {
+            match msg {
+                Message::Start => {
+                    let name = guard.lobby.lock().name().to_owned();
+                    if let Some(game) = games.get(&name) {
+                        on_start(game, id);
+                    }
+                    break;
+                }
+                Message::SelfLeave | Message::Kicked { .. } | Message::Closed { .. } => break,
+                _ => (),
             }
         }
-    }
+        drop(guard);
+    });
+
+    Ok(id)
 }
 
-#[get("/lobby/create?<id>&<name>")]
+/// Kick the player once they stayed unready and inactive for too long
+fn watch_idle(
+    lobby: &Protected<Lobby<Player>>,
+    id: <Player as gameplay::Player>::ID,
+    config: &Config,
+) {
+    let Some(limit) = config.idle_kick_after.map(Duration::from_secs) else {
+        return;
+    };
+    let lobby_ref = lobby.downgrade();
+
+    common::spawn_supervised(recovery(lobby), async move {
+        let mut deadline = Instant::now() + limit;
+        loop {
+            tokio::time::sleep_until(deadline.into()).await;
+            let lobby = lobby_ref.upgrade()?;
+            let mut locked = lobby.lock();
+            let player = locked.get_player(id)?;
+
+            if player.ready {
+                deadline = Instant::now() + limit;
+            } else if player.last_active.elapsed() >= limit {
+                player.send(Message::Kicked {
+                    reason: "You have been inactive for too long",
+                });
+                // they may join again, but not reconnect with the same seat
+                let new_host = locked.kick(id);
+                drop(locked);
+                lobby.announce_leave(id, new_host);
+                return Some(());
+            } else {
+                deadline = player.last_active + limit;
+            }
+        }
+    });
+}
+
+#[get("/lobby/create?<id>&<name>&<daily>&<password>")]
 #[must_use]
-fn create(id: Option<String>, name: String, state: &State<GlobalState>) -> Redirect {
+#[allow(clippy::too_many_arguments)]
+fn create(
+    id: Option<String>,
+    name: String,
+    daily: Option<bool>,
+    password: Option<String>,
+    state: &State<GlobalState>,
+    config: &State<Config>,
+    jar: &CookieJar<'_>,
+    _throttled: Throttled,
+) -> Redirect {
+    if let Err(reserved) = account::check_name(&name, config, jar) {
+        return reserved.redirect();
+    }
+
+    let id = open(state, config, id, |lobby| {
+        lobby.password = password
+            .as_deref()
+            .filter(|x| !x.is_empty())
+            .map(Password::new);
+        if daily == Some(true) {
+            lobby.rules = Rules::daily(stats::today(), &config.daily_secret);
+        }
+        // keep the party together when they move on to a new lobby
+        if let Some(party) = jar.get_private("party") {
+            party.value().clone_into(&mut lobby.party);
+        }
+    });
+
+    // joined right away, the password would end up in the history of the browser otherwise
+    if let Err(reason) = enter(&id, name, password.as_deref(), state, config, jar) {
+        return menu_error(reason);
+    }
+
+    Redirect::to(uri!("/lobby.html"))
+}
+
+/// Register a new lobby, under `id` unless it is taken, and return its code. It is closed if
+/// nobody joined it after a minute.
+pub fn open(
+    state: &GlobalState,
+    config: &Config,
+    id: Option<String>,
+    setup: impl FnOnce(&mut Lobby<Player>),
+) -> String {
     let mut id = id
         .unwrap_or_else(|| Alphanumeric.sample_string(&mut rand::thread_rng(), 6))
         .to_uppercase();
 
-    {
-        let mut lobbys = state.lobbys.lock().unwrap();
-        let games = state.games.lock().unwrap();
+    loop {
+        let mut lobbys = state.lobbys.shard(&id);
+        let games = state.games.shard(&id);
 
-        while lobbys.contains_key(&id) || games.contains_key(&id) {
+        // codes of other nodes are redirected to them, a new one must be ours
+        if lobbys.contains_key(&id) || games.contains_key(&id) || !state.ring.owns(&id) {
+            drop((lobbys, games));
             id = Alphanumeric
                 .sample_string(&mut rand::thread_rng(), 6)
                 .to_uppercase();
+            continue;
         }
 
-        lobbys.insert(id.clone(), Protected::new(Lobby::new(id.clone())));
+        let mut lobby = Lobby::new(id.clone());
+        lobby.closes_at = config
+            .max_lobby_lifetime
+            .map(|lifetime| Instant::now() + Duration::from_secs(lifetime));
+        setup(&mut lobby);
+        lobbys.insert(id.clone(), Protected::new(lobby));
+        break;
+    }
+
+    if let Some(lifetime) = config.max_lobby_lifetime {
+        let lobby = state.lobbys.get(&id).unwrap();
+        let lobby_ref = lobby.downgrade();
+        let lobbys_ref = Arc::downgrade(&state.lobbys);
+        let id = id.clone();
+        common::spawn_supervised(recovery(&lobby), async move {
+            tokio::time::sleep(Duration::from_secs(lifetime)).await;
+            let lobby = lobby_ref.upgrade()?;
+            let lobbys = lobbys_ref.upgrade()?;
+            {
+                let mut lobbys = lobbys.shard(&id);
+                if !lobbys.get(&id)?.ptr_eq(&lobby) {
+                    return None;
+                }
+                lobbys.remove(&id);
+            }
+
+            lobby.broadcast(&Message::Closed {
+                reason: "This lobby has been open for too long",
+            });
+
+            Some(())
+        });
     }
 
     let id_copy = id.clone();
@@ -174,7 +539,7 @@ fn create(id: Option<String>, name: String, state: &State<GlobalState>) -> Redir
         tokio::time::sleep(Duration::from_secs(60)).await;
         let lobbys = lobbys_ref.upgrade()?;
         {
-            let mut lobbys = lobbys.lock().unwrap();
+            let mut lobbys = lobbys.shard(&id);
 
             if lobbys.get(&id)?.lock().players().is_empty() {
                 lobbys.remove(&id);
@@ -184,18 +549,78 @@ fn create(id: Option<String>, name: String, state: &State<GlobalState>) -> Redir
         Some(())
     });
 
-    Redirect::to(uri!(join(id_copy, name)))
+    id_copy
 }
 
-#[get("/lobby/join?<lobby>&<name>")]
+#[get("/lobby/join?<lobby>&<name>&<password>")]
 #[must_use]
-fn join(lobby: &str, name: String, state: &State<GlobalState>, jar: &CookieJar<'_>) -> Redirect {
-    let lobby_name = lobby.to_uppercase();
+fn join(
+    lobby: &str,
+    name: String,
+    password: Option<String>,
+    state: &State<GlobalState>,
+    config: &State<Config>,
+    jar: &CookieJar<'_>,
+    _throttled: Throttled,
+) -> Redirect {
+    if let Err(reserved) = account::check_name(&name, config, jar) {
+        return reserved.redirect();
+    }
+    if let Err(reason) = enter(lobby, name, password.as_deref(), state, config, jar) {
+        return menu_error(reason);
+    }
+
+    Redirect::to(uri!("/lobby.html"))
+}
+
+/// Back to the menu, which shows `reason`
+fn menu_error(reason: &str) -> Redirect {
+    Redirect::to(format!(
+        "/gameMenu.html?error={}",
+        RawStr::new(reason).percent_encode()
+    ))
+}
+
+/// Recover `lobby` like after a failed request, for the background tasks changing it
+fn recovery(lobby: &Protected<Lobby<Player>>) -> impl FnOnce() + Send + 'static {
+    let lobby_ref = lobby.downgrade();
+    move || {
+        if let Some(lobby) = lobby_ref.upgrade() {
+            recover(&lobby);
+        }
+    }
+}
 
-    let lobbys = state.lobbys.lock().unwrap();
-    let Some(lobby) = lobbys.get(&lobby_name).map(Protected::lock) else {
-        return Redirect::to("/gameMenu.html?error=Lobby%20not%20found");
+/// Make the clients fetch the state of the lobby again after a failed request
+pub fn recover(lobby: &Protected<Lobby<Player>>) {
+    lobby.broadcast(&Message::Resync);
+}
+
+const WRONG_PASSWORD: &str = "Wrong password for this lobby";
+const KICKED: &str = "You were removed from this lobby";
+
+/// Give the client the cookies of a new member of the lobby, the player is only added once they
+/// open `/lobby/events`
+pub fn enter(
+    lobby: &str,
+    name: String,
+    password: Option<&str>,
+    state: &GlobalState,
+    config: &Config,
+    jar: &CookieJar<'_>,
+) -> Result<<Player as gameplay::Player>::ID, &'static str> {
+    let lobby_name = lobby.to_uppercase();
+    let Some(lobby) = state.lobbys.get(&lobby_name) else {
+        return Err("Lobby not found");
     };
+    let lobby = lobby.lock();
+    if !lobby.check_password(password) {
+        return Err(WRONG_PASSWORD);
+    }
+    if stats::key(jar).is_some_and(|x| lobby.banned.contains(&x)) {
+        return Err(KICKED);
+    }
+    let name = pick_name(&lobby, name, config.name_collision)?;
 
     let mut id = random();
     while lobby.players().contains_key(&id) {
@@ -205,25 +630,61 @@ fn join(lobby: &str, name: String, state: &State<GlobalState>, jar: &CookieJar<'
     jar.add_private(("lobby", lobby_name));
     jar.add_private(("id", id.to_string()));
     jar.add_private(("name", name));
+    jar.add_private(("party", lobby.party.clone()));
+    jar.remove_private("seat");
+    jar.remove_private("bot");
+    stats::identify(jar);
 
-    Redirect::to(uri!("/lobby.html"))
+    Ok(id)
+}
+
+fn pick_name(
+    lobby: &Lobby<Player>,
+    name: String,
+    collision: NameCollision,
+) -> Result<String, &'static str> {
+    collision
+        .resolve(name, |name| {
+            lobby.players().values().any(|x| x.name == name)
+        })
+        .ok_or("Someone in this lobby already uses this name")
 }
 
 // WARNING: EventStream is broken with rust 1.74.X, stay on 1.73.X until this is fixed
 #[get("/lobby/events")]
 #[must_use]
 fn events<'a>(
-    lobby: Option<Protected<Lobby<Player>>>,
+    lobby: Result<Protected<Lobby<Player>>, NoRoom>,
     state: &'a State<GlobalState>,
+    config: &'a State<Config>,
     jar: &'a CookieJar<'_>,
+    slot: Result<StreamSlot, Refused>,
     mut end: Shutdown,
-) -> EventStream![Event + 'a] {
-    EventStream! {
-        let Some(lobby) = lobby else {
-            yield make_event!(Message::Error {
-                reason: "You are not in a lobby"
-            });
-            return;
+) -> Shed<EventStream![Event + 'a]> {
+    Shed::new(slot.as_ref().err().copied(), EventStream! {
+        let _slot = match slot {
+            Ok(slot) => slot,
+            Err(refused) => {
+                let event = make_event!(refused);
+                yield match refused.retry_after() {
+                    Some(seconds) => event.with_retry(Duration::from_secs(seconds)),
+                    None => event,
+                };
+                return;
+            }
+        };
+        let lobby = match lobby {
+            Ok(lobby) => lobby,
+            Err(NoRoom::Expired) => {
+                yield make_event!(Message::Expired);
+                return;
+            }
+            Err(_) => {
+                yield make_event!(Message::Error {
+                    reason: "You are not in a lobby"
+                });
+                return;
+            }
         };
 
         let Some(Ok(id)) = jar.get_private("id").map(|x| x.value().parse::<<Player as gameplay::Player>::ID>()) else {
@@ -240,39 +701,85 @@ fn events<'a>(
             return;
         };
 
-        let (sender, mut receiver) = unbounded_channel();
-        let player = Player { id, name, ready: false, sender };
+        let (sender, mut receiver) = channel();
+        let _tracked = state.connections.track(Kind::Lobby, lobby.lock().name(), id, sender.depth());
+        let channel = sender.id();
 
-        let result = lobby.lock().add_player(player.clone());
-        match result {
-            Ok(()) => (),
-            Err(errors::Join::GameFull) => {
-                yield make_event!(Message::Error {
-                    reason: "This lobby is full"
-                });
-                return;
-            }
-            Err(errors::Join::AlreadyConnected) => {
-                yield make_event!(Message::Error {
-                    reason: "You are already connected to this game"
-                });
-                return;
+        // after a refresh the previous stream may not have noticed yet that it is gone, take over
+        // its seat so the player keeps their ready state
+        let rejoined = lobby.lock().get_player_mut(id).map(|player| {
+            player.sender = sender.clone();
+            player.last_active = Instant::now();
+        }).is_some();
+
+        if !rejoined {
+            let stats_key = stats::key(jar);
+            let ready = jar.get_private("auto_ready").is_some()
+                || stats_key.as_deref().is_some_and(|x| state.stats.lock().unwrap().auto_ready(x));
+            let bot = jar.get_private("bot").is_some();
+            let no_stats = jar.get_private("no_stats").is_some();
+            let player = Player { id, name, ready, bot, no_stats, stats_key, sender, last_active: Instant::now() };
+
+            let result = {
+                let mut lobby = lobby.lock();
+                match &player.stats_key {
+                    Some(key) if lobby.banned.contains(key) => Err(errors::Join::Kicked),
+                    _ => lobby.add_player(player.clone()),
+                }
+            };
+            match result {
+                Ok(()) => (),
+                Err(errors::Join::GameFull) => {
+                    yield make_event!(Message::Error {
+                        reason: "This lobby is full"
+                    });
+                    return;
+                }
+                Err(errors::Join::AlreadyConnected) => {
+                    yield make_event!(Message::Error {
+                        reason: "You are already connected to this game"
+                    });
+                    return;
+                }
+                Err(errors::Join::Started) => {
+                    yield make_event!(Message::Error {
+                        reason: "This game has already started"
+                    });
+                    return;
+                }
+                Err(errors::Join::Kicked) => {
+                    yield make_event!(Message::Error { reason: KICKED });
+                    return;
+                }
             }
-        }
 
-        let lobby_name = lobby.lock().name().to_owned();
-        yield make_event!(Message::Initialize {
-            lobby: lobby_name,
-            players: lobby.lock().players().values().cloned().collect(),
-        });
+            lobby.broadcast(&Message::Join { player });
+            watch_idle(&lobby, id, config);
+        }
 
-        lobby.broadcast(&Message::Join { player });
+        let initialize = {
+            let lobby = lobby.lock();
+            Message::Initialize {
+                lobby: lobby.name().to_owned(),
+                players: lobby.players().values().cloned().collect(),
+                host: lobby.host(),
+                rules: lobby.rules.clone(),
+                closes_in: closes_in(&lobby),
+            }
+        };
+        yield make_event!(initialize);
 
-        let guard = ConnectionGuard { lobbys: &state.lobbys, lobby, id };
+        let guard = ConnectionGuard {
+            lobbys: Arc::downgrade(&state.lobbys),
+            lobby,
+            id,
+            channel,
+            janitor: state.janitor.handle(),
+        };
 
         loop {
             let Some(msg) = (select! {
-                msg = receiver.recv() => msg,
+                msg = receiver.recv(|| Message::Resync) => msg,
                 () = &mut end => {
                     yield make_event!(Message::Error {
                         reason: "Server closed",
@@ -286,18 +793,41 @@ fn events<'a>(
 
             yield make_event!(msg.clone());
 
-            if matches!(msg, Message::Start { .. }) {
+            if matches!(msg, Message::Start | Message::Kicked { .. } | Message::Closed { .. }) {
                 break;
             }
         }
 
         drop(guard);
-    }.heartbeat(Duration::from_secs(5))
+    }.heartbeat(Duration::from_secs(5)))
 }
 
-#[get("/lobby/ready?<state>")]
+#[get("/lobby/ready?<state>&<auto>")]
 #[allow(clippy::needless_pass_by_value)]
-fn ready(state: bool, lobby: Protected<Lobby<Player>>, jar: &CookieJar<'_>) {
+fn ready(
+    state: Option<bool>,
+    auto: Option<bool>,
+    lobby: Protected<Lobby<Player>>,
+    global: &State<GlobalState>,
+    jar: &CookieJar<'_>,
+) {
+    if let Some(auto) = auto {
+        if auto {
+            jar.add_private(("auto_ready", "true"));
+        } else {
+            jar.remove_private("auto_ready");
+        }
+        // the other browsers of the account are ready too
+        if let Some(account) = jar.get_private("account") {
+            let key = format!("account:{}", account.value());
+            global.stats.lock().unwrap().set_auto_ready(&key, auto);
+        }
+    }
+    // the preference is for the next lobbies, the player may not be ready for this one
+    let Some(state) = state else {
+        return;
+    };
+
     let Some(Ok(id)) = jar
         .get_private("id")
         .map(|x| x.value().parse::<<Player as gameplay::Player>::ID>())
@@ -306,21 +836,278 @@ fn ready(state: bool, lobby: Protected<Lobby<Player>>, jar: &CookieJar<'_>) {
     };
 
     if lobby.lock().get_player(id).is_some() {
-        lobby.lock().get_player_mut(id).unwrap().ready = state;
+        {
+            let mut lobby = lobby.lock();
+            let player = lobby.get_player_mut(id).unwrap();
+            player.ready = state;
+            player.last_active = Instant::now();
+        }
         lobby.broadcast(&Message::Ready { player: id, state });
     };
 }
 
+#[derive(FromForm)]
+struct Settings {
+    /// applied before the other settings, so they can tweak it
+    preset: Option<Preset>,
+    max_players: Option<usize>,
+    /// Games of two or three players, with a neutral hand
+    small_table: Option<bool>,
+    reconnect_grace: Option<u64>,
+    vote_removal: Option<bool>,
+    bot_takeover: Option<bool>,
+    /// A bot plays for the players leaving the game
+    forfeit_takeover: Option<bool>,
+    auto_pause: Option<u64>,
+    no_cut_back: Option<bool>,
+    confirm_cuts: Option<bool>,
+    self_cuts: Option<u8>,
+    free_self_cuts: Option<bool>,
+    teams: Option<TeamDistribution>,
+    /// Given together with `moriarty`, both at 0 go back to `teams`
+    sherlock: Option<usize>,
+    moriarty: Option<usize>,
+    balance_teams: Option<bool>,
+    anonymous: Option<bool>,
+    theme: Option<String>,
+    /// 0 disables the turn timer
+    turn_timer: Option<u64>,
+    max_rounds: Option<usize>,
+    bombs: Option<usize>,
+    defusing_per_player: Option<usize>,
+    hand_size: Option<usize>,
+    /// Deal short fuses and decoy bombs
+    variant_cables: Option<bool>,
+    short_fuses: Option<usize>,
+    decoy_bombs: Option<usize>,
+    round_reveal: Option<RoundReveal>,
+    /// Wins needed to take the series, 0 plays single games
+    series: Option<u32>,
+    /// Comma separated names of the roles to deal, empty for none
+    roles: Option<String>,
+}
+
+#[get("/lobby/settings?<settings..>")]
+#[allow(clippy::needless_pass_by_value)]
+fn settings(
+    settings: Settings,
+    lobby: Protected<Lobby<Player>>,
+    config: &State<Config>,
+    id: PlayerId,
+) -> Result<(), BadRequest<&'static str>> {
+    let id = id.0;
+
+    let rules = {
+        let mut lobby = lobby.lock();
+        if lobby.get_player(id).is_none() {
+            return Err(BadRequest("You are not part of this lobby"));
+        }
+        if !lobby.is_host(id) {
+            return Err(BadRequest("Only the host can change the settings"));
+        }
+        if lobby.rules.daily.is_some() {
+            return Err(BadRequest(
+                "The rules of the daily challenge can't be changed",
+            ));
+        }
+
+        // nothing is changed unless every setting is valid
+        let mut rules = lobby.rules.clone();
+        if let Some(preset) = settings.preset {
+            // the presets are about how the game is played, not about the table
+            rules = Rules {
+                theme: rules.theme.take(),
+                max_players: rules.max_players,
+                small_table: rules.small_table,
+                team_sizes: rules.team_sizes.take(),
+                series: rules.series.take(),
+                seed: rules.seed,
+                ..preset.rules()
+            };
+        }
+
+        if let Some(max_players) = settings.max_players {
+            if !(MIN_PLAYERS..=MAX_PLAYERS).contains(&max_players) {
+                return Err(BadRequest("A table seats between 4 and 12 players"));
+            }
+            if max_players < lobby.players().len() {
+                return Err(BadRequest("More players already joined"));
+            }
+            rules.max_players = max_players;
+        }
+        if let Some(small_table) = settings.small_table {
+            rules.small_table = small_table;
+        }
+
+        if let Some(grace) = settings.reconnect_grace {
+            if grace > 60 * 60 {
+                return Err(BadRequest("The reconnect grace can't exceed one hour"));
+            }
+            rules.reconnect_grace = grace;
+        }
+        if let Some(vote_removal) = settings.vote_removal {
+            rules.vote_removal = vote_removal;
+        }
+        if let Some(bot_takeover) = settings.bot_takeover {
+            rules.bot_takeover = bot_takeover;
+        }
+        if let Some(forfeit_takeover) = settings.forfeit_takeover {
+            rules.forfeit_takeover = forfeit_takeover;
+        }
+        if let Some(auto_pause) = settings.auto_pause {
+            if auto_pause > 10 * 60 {
+                return Err(BadRequest("The automatic pause can't exceed ten minutes"));
+            }
+            rules.auto_pause = (auto_pause > 0).then_some(auto_pause);
+        }
+        if let Some(no_cut_back) = settings.no_cut_back {
+            rules.no_cut_back = no_cut_back;
+        }
+        if let Some(confirm_cuts) = settings.confirm_cuts {
+            rules.confirm_cuts = confirm_cuts;
+        }
+        if let Some(self_cuts) = settings.self_cuts {
+            rules.self_cuts = self_cuts;
+        }
+        if let Some(free_self_cuts) = settings.free_self_cuts {
+            rules.free_self_cuts = free_self_cuts;
+        }
+        if let Some(teams) = settings.teams {
+            rules.teams = teams;
+        }
+        match (settings.sherlock, settings.moriarty) {
+            (None, None) => {}
+            (Some(0), Some(0)) => rules.team_sizes = None,
+            (Some(sherlock), Some(moriarty)) => {
+                let sizes = TeamSizes { sherlock, moriarty };
+                if sherlock == 0 || moriarty == 0 {
+                    return Err(BadRequest("Both teams need at least one player"));
+                }
+                let min_players = if rules.small_table {
+                    SMALL_TABLE_MIN_PLAYERS
+                } else {
+                    MIN_PLAYERS
+                };
+                if !(min_players..=rules.max_players).contains(&sizes.total()) {
+                    return Err(BadRequest(
+                        "The teams don't add up to a possible number of players",
+                    ));
+                }
+                rules.team_sizes = Some(sizes);
+            }
+            _ => return Err(BadRequest("The size of both teams must be given")),
+        }
+        if let Some(balance_teams) = settings.balance_teams {
+            rules.balance_teams = balance_teams;
+        }
+        if let Some(anonymous) = settings.anonymous {
+            rules.anonymous = anonymous;
+        }
+        if let Some(theme) = settings.theme {
+            if !config.all_themes().iter().any(|x| x.name == theme) {
+                return Err(BadRequest("Unknown theme"));
+            }
+            rules.theme = Some(theme);
+        }
+        if let Some(turn_timer) = settings.turn_timer {
+            if turn_timer > 10 * 60 {
+                return Err(BadRequest("The turn timer can't exceed ten minutes"));
+            }
+            rules.turn_timer = (turn_timer > 0).then_some(turn_timer);
+        }
+        if let Some(max_rounds) = settings.max_rounds {
+            if max_rounds == 0 {
+                return Err(BadRequest("A game needs at least one round"));
+            }
+            rules.max_rounds = max_rounds;
+        }
+        let deck = DeckBuilder {
+            bombs: settings.bombs.unwrap_or(rules.deck.bombs),
+            defusing_per_player: settings
+                .defusing_per_player
+                .unwrap_or(rules.deck.defusing_per_player),
+            hand_size: settings.hand_size.unwrap_or(rules.deck.hand_size),
+            short_fuses: settings.short_fuses.unwrap_or(rules.deck.short_fuses),
+            decoy_bombs: settings.decoy_bombs.unwrap_or(rules.deck.decoy_bombs),
+        };
+        let variant_cables = settings.variant_cables.unwrap_or(rules.variant_cables);
+        deck.validate(variant_cables, rules.small_table)
+            .map_err(|e| match e {
+                errors::Deck::NoBomb => BadRequest("The deck needs at least one bomb"),
+                errors::Deck::NoDefusing => BadRequest("Every player needs a defusing cable"),
+                errors::Deck::HandTooBig => BadRequest("The hands can't hold more than 10 cables"),
+                errors::Deck::DoesntFit => {
+                    BadRequest("The bombs and defusing cables don't fit in the hands")
+                }
+            })?;
+        rules.deck = deck;
+        rules.variant_cables = variant_cables;
+        if let Some(round_reveal) = settings.round_reveal {
+            rules.round_reveal = round_reveal;
+        }
+        if let Some(series) = settings.series {
+            rules.series = (series > 0).then(|| Series::new(series));
+        }
+        if let Some(roles) = settings.roles {
+            let mut parsed = Vec::new();
+            for name in roles.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+                let Some(role) = Role::from_name(name) else {
+                    return Err(BadRequest("Unknown role"));
+                };
+                if !parsed.contains(&role) {
+                    parsed.push(role);
+                }
+            }
+            rules.roles = parsed;
+        }
+
+        lobby.rules = rules.clone();
+        rules
+    };
+    lobby.broadcast(&Message::Settings { rules });
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct PresetInfo {
+    name: Preset,
+    rules: Rules,
+}
+
+#[get("/themes")]
+fn themes(config: &State<Config>) -> Json<Vec<Theme>> {
+    Json(config.all_themes())
+}
+
+#[get("/presets")]
+fn presets() -> Json<Vec<PresetInfo>> {
+    Json(
+        Preset::ALL
+            .into_iter()
+            .map(|name| PresetInfo {
+                name,
+                rules: name.rules(),
+            })
+            .collect(),
+    )
+}
+
 #[get("/lobby/leave")]
 #[must_use]
-fn leave(lobby: Option<Protected<Lobby<Player>>>, jar: &CookieJar<'_>) -> Redirect {
+fn leave(
+    lobby: Option<Protected<Lobby<Player>>>,
+    jar: &CookieJar<'_>,
+    _throttled: Throttled,
+) -> Redirect {
     if let Some(Ok(id)) = jar
         .get_private("id")
         .map(|x| x.value().parse::<<Player as gameplay::Player>::ID>())
     {
         if let Some(lobby) = lobby {
             if let Some(player) = lobby.lock().get_player(id) {
-                player.sender.send(Message::SelfLeave).unwrap();
+                player.send(Message::SelfLeave);
             }
         }
     };
@@ -328,71 +1115,453 @@ fn leave(lobby: Option<Protected<Lobby<Player>>>, jar: &CookieJar<'_>) -> Redire
     jar.remove_private("lobby");
     jar.remove_private("id");
     jar.remove_private("name");
+    jar.remove_private("party");
 
     Redirect::to("/gameMenu.html")
 }
 
 #[get("/lobby/start")]
 #[allow(clippy::significant_drop_in_scrutinee, clippy::similar_names)]
-fn start(state: &State<GlobalState>, jar: &CookieJar<'_>) -> Status {
+fn start(state: &State<GlobalState>, config: &State<Config>, jar: &CookieJar<'_>) -> Status {
     let Some(lobby) = jar.get_private("lobby") else {
         return Status::NotFound;
     };
 
-    let lobby = {
-        let mut lobbys = state.lobbys.lock().unwrap();
-        let name = {
-            let Some(lobby) = lobbys.get(lobby.value()) else {
-                return Status::NotFound;
+    // everything is done while holding the locks so that nobody can join or leave meanwhile
+    let game = {
+        let mut lobbys = state.lobbys.shard(lobby.value());
+        let mut games = state.games.shard(lobby.value());
+        let Some(lobby) = lobbys.get(lobby.value()).cloned() else {
+            return Status::NotFound;
+        };
+        let mut lobby = lobby.lock();
+        if !jar
+            .get_private("id")
+            .and_then(|x| x.value().parse().ok())
+            .is_some_and(|id| lobby.is_host(id))
+        {
+            return Status::Forbidden;
+        }
+        if !lobby.may_start() {
+            return Status::PreconditionRequired;
+        }
+
+        let mut moriarty_streaks = HashMap::new();
+        if lobby.rules.balance_teams {
+            let stats = state.stats.lock().unwrap();
+            for player in lobby.players().values().filter(|p| !p.no_stats) {
+                let key = player.stats_key.as_deref().unwrap_or(&player.name);
+                if let Some(stats) = stats.get(key) {
+                    moriarty_streaks.insert(player.id, stats.moriarty_streak);
+                }
+            }
+        }
+        let roster = lobby.players().keys().copied().collect();
+        if let Some(series) = &mut lobby.rules.series {
+            series.seat(roster);
+        }
+        let mut game: Game<game::Player> = lobby.start(&moriarty_streaks);
+        for player in lobby.players().values() {
+            let Some(seat) = game.get_player_mut(player.id) else {
+                continue;
             };
-            let locked = lobby.lock();
-            if !locked.may_start() {
-                return Status::PreconditionRequired;
+            if player.no_stats {
+                seat.hide_from_stats();
+            }
+            if let Some(key) = &player.stats_key {
+                seat.record_as(key.clone());
             }
+        }
+        let name = game.name().to_owned();
+        let game = Protected::new(game);
+        lobbys.remove(&name);
+        games.insert(name, game.clone());
+
+        for player in lobby.players().values() {
+            player.send(Message::Start);
+        }
+
+        game
+    };
+    game::schedule_nudge(&game, &state.games, config);
+    game::schedule_log(&game, config);
+    game::schedule_time_limit(&game, state, config);
+    game::schedule_turn_timer(&game, state, config);
+    game::schedule_abandon_check(&game, &state.games);
+
+    Status::Ok
+}
+
+#[get("/lobby/kick?<player>")]
+#[allow(clippy::needless_pass_by_value)]
+fn kick(
+    player: <Player as gameplay::Player>::ID,
+    lobby: Protected<Lobby<Player>>,
+    id: PlayerId,
+) -> Result<(), BadRequest<&'static str>> {
+    let id = id.0;
 
-            locked.name().to_owned()
+    {
+        let mut lobby = lobby.lock();
+        if !lobby.is_host(id) {
+            return Err(BadRequest("Only the host can kick players"));
+        }
+        if id == player {
+            return Err(BadRequest("You cannot kick yourself"));
+        }
+        let Some(target) = lobby.get_player(player) else {
+            return Err(BadRequest(
+                "The player you specified is not part of this lobby",
+            ));
         };
+        target.send(Message::Kicked {
+            reason: "The host removed you from the lobby",
+        });
+        // their cookies would let them back in otherwise
+        if let Some(key) = target.stats_key.clone() {
+            lobby.banned.insert(key);
+        }
+        lobby.kick(player);
+    }
+    lobby.announce_leave(player, false);
+
+    Ok(())
+}
 
-        lobbys.remove(&name).unwrap()
+/// Relays a WebRTC signaling blob (SDP offer/answer or ICE candidate) to another member of the room
+#[post("/lobby/signal?<to>", data = "<data>")]
+#[allow(clippy::needless_pass_by_value)]
+fn signal(
+    to: <Player as gameplay::Player>::ID,
+    data: String,
+    lobby: Protected<Lobby<Player>>,
+    id: PlayerId,
+) -> Result<(), BadRequest<&'static str>> {
+    let id = id.0;
+
+    let lobby = lobby.lock();
+    if lobby.get_player(id).is_none() {
+        return Err(BadRequest("You are not part of this lobby"));
+    }
+    let Some(player) = lobby.get_player(to) else {
+        return Err(BadRequest(
+            "The player you specified is not part of this lobby",
+        ));
     };
+    if id == to {
+        return Err(BadRequest("You cannot signal yourself"));
+    }
+
+    player.send(Message::Signal { from: id, data });
+    Ok(())
+}
 
-    let game: Game<game::Player> = lobby.lock().start();
-    let name = game.name().to_owned();
-    state
-        .games
-        .lock()
-        .unwrap()
-        .insert(name, Protected::new(game));
+#[get("/lobby/speaking?<state>")]
+#[allow(clippy::needless_pass_by_value)]
+fn speaking(
+    state: bool,
+    lobby: Protected<Lobby<Player>>,
+    id: PlayerId,
+) -> Result<(), BadRequest<&'static str>> {
+    let id = id.0;
 
-    for player in lobby.lock().players().values() {
-        player.sender.send(Message::Start).unwrap();
+    if lobby.lock().get_player(id).is_none() {
+        return Err(BadRequest("You are not part of this lobby"));
     }
 
-    let games_ref = Arc::downgrade(&state.games);
-    let id = lobby.lock().name().to_owned();
-    tokio::spawn(async move {
-        tokio::time::sleep(Duration::from_secs(120)).await;
-        let games = games_ref.upgrade()?;
-        {
-            let mut games = games.lock().unwrap();
-
-            if !games
-                .get(&id)?
-                .lock()
-                .players()
-                .values()
-                .any(PlayingPlayer::connected)
-            {
-                games.remove(&id);
-            }
-        }
+    lobby.broadcast(&Message::Speaking { player: id, state });
+    Ok(())
+}
 
-        Some(())
-    });
+#[post("/lobby/chat", data = "<message>")]
+#[allow(clippy::needless_pass_by_value)]
+fn chat(
+    message: String,
+    lobby: Protected<Lobby<Player>>,
+    id: PlayerId,
+) -> Result<(), BadRequest<&'static str>> {
+    let id = id.0;
 
-    Status::Ok
+    let msg = {
+        let mut lobby = lobby.lock();
+        let Some(player) = lobby.get_player_mut(id) else {
+            return Err(BadRequest("You are not part of this lobby"));
+        };
+        player.last_active = Instant::now();
+        ChatMessage::new(id, &player.name, &message).map_err(BadRequest)?
+    };
+    lobby.broadcast(&Message::Chat(msg));
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct LobbyState {
+    lobby: String,
+    players: Vec<Player>,
+    host: Option<<Player as gameplay::Player>::ID>,
+    rules: Rules,
+    closes_in: Option<u64>,
+}
+
+/// Seconds left before `lobby` is closed for having been open too long
+fn closes_in(lobby: &Lobby<Player>) -> Option<u64> {
+    lobby
+        .closes_at
+        .map(|at| at.saturating_duration_since(Instant::now()).as_secs())
+}
+
+/// Polling alternative to `/lobby/events`
+#[get("/lobby/state")]
+#[allow(clippy::needless_pass_by_value)]
+fn poll(lobby: Protected<Lobby<Player>>, if_none_match: IfNoneMatch) -> Polled<Json<LobbyState>> {
+    let lobby = lobby.lock();
+    Polled::new(&lobby.version.to_string(), &if_none_match, || {
+        Json(LobbyState {
+            lobby: lobby.name().to_owned(),
+            players: lobby.players().values().cloned().collect(),
+            host: lobby.host(),
+            rules: lobby.rules.clone(),
+            closes_in: closes_in(&lobby),
+        })
+    })
 }
 
 pub fn routes() -> Vec<rocket::Route> {
-    routes![create, join, events, ready, leave, start]
+    routes![
+        create, join, events, ready, settings, themes, presets, leave, start, kick, signal,
+        speaking, chat, poll
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::{Account, Config, NameCollision},
+        testing::{self, Server},
+    };
+    use rocket::tokio::time::{sleep, Duration};
+
+    #[rocket::async_test]
+    async fn auto_ready_follows_the_account() {
+        let config = Config {
+            accounts: vec![Account {
+                name: "alice".to_owned(),
+                key: "key".to_owned(),
+            }],
+            ..Config::default()
+        };
+        let server = Server::new(config).await;
+        let mut laptop = server.session(1);
+        laptop.redirect("/account/login?name=alice&key=key").await;
+        laptop.redirect("/lobby/create?id=AUTORE&name=alice").await;
+        laptop.events("/lobby/events").await.until("init").await;
+
+        // only the next lobbies are concerned
+        laptop.get("/lobby/ready?auto=true").await;
+        let state = laptop.json("/lobby/state").await;
+        assert_eq!(state["players"][0]["ready"], false);
+
+        let mut phone = server.session(2);
+        phone.redirect("/account/login?name=alice&key=key").await;
+        phone.redirect("/lobby/create?id=AUTOR2&name=alice").await;
+        let init = phone.events("/lobby/events").await.until("init").await;
+        assert_eq!(init["players"][0]["ready"], true);
+    }
+
+    #[rocket::async_test]
+    async fn late_joiners_know_when_the_lobby_closes() {
+        let config = Config {
+            max_lobby_lifetime: Some(600),
+            ..Config::default()
+        };
+        let server = Server::new(config).await;
+        let mut sessions = server.lobby("CLOSES", 2).await;
+        let init = sessions[1]
+            .events("/lobby/events")
+            .await
+            .until("init")
+            .await;
+        let closes_in = init["closes_in"].as_u64().unwrap();
+        assert!((590..=600).contains(&closes_in), "{closes_in}");
+
+        let server = Server::new(Config::default()).await;
+        let mut sessions = server.lobby("STAYS", 1).await;
+        let init = sessions[0]
+            .events("/lobby/events")
+            .await
+            .until("init")
+            .await;
+        assert!(init["closes_in"].is_null());
+    }
+
+    #[rocket::async_test]
+    async fn bots_are_numbered() {
+        let config = Config {
+            name_collision: NameCollision::Reject,
+            ..Config::default()
+        };
+        let server = Server::new(config).await;
+        let mut host = server.lobby("BOTS", 1).await.remove(0);
+        let mut events = host.events("/lobby/events").await;
+        events.until("init").await;
+        host.json("/lobby/addbot").await;
+        host.json("/lobby/addbot").await;
+
+        let state = host.json("/lobby/state").await;
+        let mut names: Vec<_> = state["players"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|x| x["name"].as_str().unwrap())
+            .collect();
+        names.sort_unstable();
+        assert_eq!(names, ["Bot", "Bot 2", "p1"]);
+    }
+
+    #[rocket::async_test]
+    async fn streams_are_capped_per_address() {
+        let config = Config {
+            max_streams_per_ip: Some(1),
+            ..Config::default()
+        };
+        let server = Server::new(config).await;
+        let mut sessions = server.lobby("CAPPED", 2).await;
+        let mut first = sessions[0].events("/lobby/events").await;
+        first.until("init").await;
+
+        let refused = sessions[0]
+            .events("/lobby/events")
+            .await
+            .until("error")
+            .await;
+        assert_eq!(refused["reason"], "Too many connections from your address");
+        // the others have their own address
+        sessions[1]
+            .events("/lobby/events")
+            .await
+            .until("init")
+            .await;
+    }
+
+    #[rocket::async_test]
+    async fn busy_server_asks_to_come_back_later() {
+        let config = Config {
+            max_streams: Some(1),
+            busy_retry_after: 7,
+            ..Config::default()
+        };
+        let server = Server::new(config).await;
+        let mut sessions = server.lobby("BUSY", 2).await;
+        let mut first = sessions[0].events("/lobby/events").await;
+        first.until("init").await;
+
+        let busy = sessions[1]
+            .events("/lobby/events")
+            .await
+            .until("busy")
+            .await;
+        assert_eq!(busy["retry_after"], 7);
+    }
+
+    #[rocket::async_test]
+    async fn password_stays_out_of_the_urls() {
+        let server = Server::new(Config::default()).await;
+        let mut host = server.session(1);
+        let redirect = host
+            .redirect("/lobby/create?id=SECRET&name=p1&password=hunter2")
+            .await;
+        assert_eq!(redirect, "/lobby.html");
+
+        let mut p2 = server.session(2);
+        let redirect = p2.redirect("/lobby/join?lobby=SECRET&name=p2").await;
+        assert!(
+            redirect.starts_with("/gameMenu.html?error=Wrong"),
+            "{redirect}"
+        );
+        let redirect = p2
+            .redirect("/lobby/join?lobby=SECRET&name=p2&password=hunter1")
+            .await;
+        assert!(
+            redirect.starts_with("/gameMenu.html?error=Wrong"),
+            "{redirect}"
+        );
+        let redirect = p2
+            .redirect("/lobby/join?lobby=SECRET&name=p2&password=hunter2")
+            .await;
+        assert_eq!(redirect, "/lobby.html");
+    }
+
+    #[rocket::async_test]
+    async fn kicked_player_cant_come_back() {
+        let server = Server::new(Config::default()).await;
+        let mut sessions = server.lobby("KICKED", 2).await;
+        let mut events = Vec::new();
+        for session in &mut sessions {
+            let mut stream = session.events("/lobby/events").await;
+            stream.until("init").await;
+            events.push(stream);
+        }
+        let p2 = testing::id(&sessions[0].json("/lobby/state").await, 2);
+        sessions[0].get(&format!("/lobby/kick?player={p2}")).await;
+        events[1].until("kicked").await;
+        drop(events);
+
+        // neither with the cookies they had, nor under another name
+        let mut stream = sessions[1].events("/lobby/events").await;
+        assert_eq!(
+            stream.until("error").await["reason"],
+            "You were removed from this lobby"
+        );
+        let redirect = sessions[1]
+            .redirect("/lobby/join?lobby=KICKED&name=p3")
+            .await;
+        assert!(redirect.starts_with("/gameMenu.html?error="), "{redirect}");
+        assert_eq!(
+            sessions[0].json("/lobby/state").await["players"]
+                .as_array()
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[rocket::async_test]
+    async fn idle_player_is_removed() {
+        let config = Config {
+            idle_kick_after: Some(1),
+            ..Config::default()
+        };
+        let server = Server::new(config).await;
+        let mut sessions = server.lobby("IDLERS", 2).await;
+        let mut events = Vec::new();
+        for session in &mut sessions {
+            let mut stream = session.events("/lobby/events").await;
+            stream.until("init").await;
+            events.push(stream);
+        }
+        // ready players are never kicked
+        sessions[0].get("/lobby/ready?state=true").await;
+
+        // chatting is being active
+        for _ in 0..5 {
+            sleep(Duration::from_millis(300)).await;
+            sessions[1].post("/lobby/chat", "still here").await;
+        }
+        let state = sessions[0].json("/lobby/state").await;
+        assert_eq!(state["players"].as_array().unwrap().len(), 2);
+
+        let p2 = testing::id(&state, 2);
+        events[1].until("kicked").await;
+        assert_eq!(events[0].until("leave").await["player"], p2);
+        let state = sessions[0].json("/lobby/state").await;
+        assert_eq!(state["players"].as_array().unwrap().len(), 1);
+        drop(events);
+        let mut stream = sessions[1].events("/lobby/events").await;
+        assert_eq!(
+            stream.until("error").await["reason"],
+            "You were removed from this lobby"
+        );
+    }
 }
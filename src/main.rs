@@ -1,18 +1,37 @@
 #![allow(clippy::option_if_let_else, clippy::no_effect_underscore_binding)]
 
-use rocket::{
-    fs::{relative, FileServer},
-    get, launch,
-    response::Redirect,
-    routes,
-};
+use rand::distributions::{Alphanumeric, DistString};
+use rocket::{get, response::Redirect, routes, Build, Rocket};
+use std::time::Duration;
 
+mod account;
+mod admin;
+mod assets;
+mod bot;
+mod channel;
+mod chat;
+mod cluster;
 mod common;
+mod config;
 mod game;
-mod gameplay;
+mod history;
+mod janitor;
+mod limits;
+mod load_test;
 mod lobby;
+mod mirror;
+mod party;
+mod scenario;
+mod sse;
+mod stats;
+#[cfg(test)]
+mod testing;
+mod tls;
+mod webhook;
 
 use common::GlobalState;
+use config::Config;
+use untimed_explosion::{agent, gameplay, roles};
 
 // TODO: use async mutex
 
@@ -21,12 +40,59 @@ fn index() -> Redirect {
     Redirect::to("/gameMenu.html")
 }
 
-#[launch]
-fn rocket() -> _ {
-    rocket::build()
-        .manage(GlobalState::new())
-        .mount("/", FileServer::from(relative!("static")))
-        .mount("/", routes![index])
+#[rocket::main]
+#[allow(clippy::result_large_err)]
+async fn main() -> Result<(), rocket::Error> {
+    if let Some(options) = load_test::Options::from_args() {
+        load_test::run(options).await;
+        return Ok(());
+    }
+
+    rocket().launch().await?;
+    Ok(())
+}
+
+fn rocket() -> Rocket<Build> {
+    let rocket = rocket::build();
+    let config: Config = rocket.figment().extract().expect("invalid configuration");
+    server(rocket, config)
+}
+
+fn server(rocket: Rocket<Build>, mut config: Config) -> Rocket<Build> {
+    if config.daily_secret.is_empty() {
+        config.daily_secret = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    }
+    channel::set_limits(
+        config.channel_capacity,
+        config.channel_overflow,
+        config.max_client_lag.map(Duration::from_secs),
+    );
+
+    let mirror = config.mirror_of.is_some();
+    let rocket = rocket
+        .attach(assets::CacheControl(config.cache_control.clone()))
+        .attach(tls::redirector())
+        .attach(janitor::fairing())
+        .manage(GlobalState::new(&config))
+        .manage(config)
+        .register("/", common::catchers())
+        .mount("/", assets::routes())
+        .mount("/", routes![index]);
+    // everything but the spectators is left to the origin
+    if mirror {
+        return rocket.mount("/", mirror::mirror_routes());
+    }
+
+    rocket
+        .mount("/", account::routes())
+        .mount("/", admin::routes())
+        .mount("/", bot::routes())
+        .mount("/", cluster::routes())
         .mount("/", game::routes())
+        .mount("/", history::routes())
         .mount("/", lobby::routes())
+        .mount("/", mirror::routes())
+        .mount("/", party::routes())
+        .mount("/", scenario::routes())
+        .mount("/", stats::routes())
 }
@@ -0,0 +1,263 @@
+//! Spectators of big games can be sent to mirrors, instances started with `mirror_of` that only
+//! serve spectators and replays. A mirror follows each watched game once through
+//! `/mirror/<code>/events` on the origin, and shares the events with all of its spectators.
+
+use crate::{
+    channel::{channel, Receiver, Sender},
+    common::{make_event, GlobalState},
+    config::Config,
+    game::{self, PlayerView, NO_GAME},
+    limits::{Refused, Shed, StreamSlot},
+    sse,
+};
+use reqwest::Response;
+use rocket::{
+    get,
+    http::{ContentType, Status},
+    request::{FromRequest, Outcome, Request},
+    response::stream::{Event, EventStream},
+    routes,
+    serde::json::json,
+    tokio::{self, select},
+    Shutdown, State,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Replays kept by a mirror, the oldest ones are forgotten first
+const MAX_REPLAYS: usize = 1000;
+
+/// Requests of a mirror, authenticated with `Authorization: Bearer <mirror_key>`
+pub struct MirrorKey;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for MirrorKey {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let config = request.rocket().state::<Config>().unwrap();
+        let Some(key) = &config.mirror_key else {
+            return Outcome::Error((Status::NotFound, ()));
+        };
+
+        match request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|x| x.strip_prefix("Bearer "))
+        {
+            Some(x) if x == key => Outcome::Success(Self),
+            _ => Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
+
+/// Name and data of an event received from the origin
+type Raw = (String, String);
+
+/// A game followed from the origin
+#[derive(Default)]
+struct Feed {
+    /// Every event received, so that late spectators start with the greeting too
+    backlog: Vec<Raw>,
+    spectators: Vec<Sender<Raw>>,
+}
+
+/// What a mirror got from its origin
+#[derive(Default)]
+pub struct Mirrored {
+    feeds: Arc<Mutex<HashMap<String, Arc<Mutex<Feed>>>>>,
+    /// Finished games never change, they are only fetched once
+    replays: Mutex<BTreeMap<u64, String>>,
+}
+
+impl Mirrored {
+    fn subscribe(&self, config: &Config, code: &str) -> Receiver<Raw> {
+        let (sender, receiver) = channel();
+        let mut feeds = self.feeds.lock().unwrap();
+        let feed = feeds.entry(code.to_owned()).or_insert_with(|| {
+            let feed = Arc::default();
+            tokio::spawn(follow(
+                Arc::clone(&self.feeds),
+                Arc::clone(&feed),
+                config.clone(),
+                code.to_owned(),
+            ));
+            feed
+        });
+
+        let mut feed = feed.lock().unwrap();
+        for event in &feed.backlog {
+            let _ = sender.send(event.clone());
+        }
+        feed.spectators.push(sender);
+
+        receiver
+    }
+}
+
+fn origin(config: &Config, path: &str) -> reqwest::RequestBuilder {
+    let origin = config.mirror_of.as_deref().unwrap_or_default();
+    let request = reqwest::Client::new().get(format!("{}{path}", origin.trim_end_matches('/')));
+    match &config.mirror_key {
+        Some(key) => request.bearer_auth(key),
+        None => request,
+    }
+}
+
+/// Forward the events of the game to the spectators, until the game or all the spectators are gone
+async fn follow(
+    feeds: Arc<Mutex<HashMap<String, Arc<Mutex<Feed>>>>>,
+    feed: Arc<Mutex<Feed>>,
+    config: Config,
+    code: String,
+) {
+    let response = origin(&config, &format!("/mirror/{code}/events"))
+        .send()
+        .await
+        .and_then(Response::error_for_status);
+    match response {
+        Ok(response) => {
+            let mut events = sse::read(response);
+            while let Some(event) = events.recv().await {
+                let mut feed = feed.lock().unwrap();
+                feed.spectators.retain(|x| x.send(event.clone()).is_ok());
+                if feed.spectators.is_empty() {
+                    break;
+                }
+                feed.backlog.push(event);
+            }
+        }
+        Err(e) => {
+            rocket::warn!("Cannot follow {code}: {e}");
+            let error = json!({ "reason": "The game cannot be watched right now" });
+            for spectator in &feed.lock().unwrap().spectators {
+                let _ = spectator.send(("error".to_owned(), error.to_string()));
+            }
+        }
+    }
+
+    // the streams of the spectators end with the last reference to the feed
+    let mut feeds = feeds.lock().unwrap();
+    if feeds.get(&code).is_some_and(|x| Arc::ptr_eq(x, &feed)) {
+        feeds.remove(&code);
+    }
+}
+
+fn spectator(code: &str, state: &GlobalState, config: &Config) -> Option<PlayerView> {
+    let game = state.games.get(&code.to_uppercase())?;
+    Some(PlayerView::spectator(&game, config))
+}
+
+/// Same as `/game/spectate?<lobby>`
+#[get("/spectate/<code>/events")]
+#[allow(clippy::needless_pass_by_value)]
+fn events(
+    code: &str,
+    state: &State<GlobalState>,
+    config: &State<Config>,
+    slot: Result<StreamSlot, Refused>,
+    end: Shutdown,
+) -> Shed<EventStream![]> {
+    game::spectator_stream(spectator(code, state, config), slot, end)
+}
+
+/// Same as `/spectate/<code>/events`, for the mirrors
+#[get("/mirror/<code>/events")]
+#[allow(clippy::needless_pass_by_value)]
+fn mirror_events(
+    _mirror: MirrorKey,
+    code: &str,
+    state: &State<GlobalState>,
+    config: &State<Config>,
+    mut end: Shutdown,
+) -> EventStream![] {
+    let view = spectator(code, state, config);
+    EventStream! {
+        let Some(mut view) = view else {
+            yield Event::json(&json!({ "reason": NO_GAME })).event("error");
+            return;
+        };
+        while let Some(event) = select! {
+            event = view.next() => event,
+            () = &mut end => None,
+        } {
+            yield event;
+        }
+    }
+    .heartbeat(Duration::from_secs(5))
+}
+
+#[get("/spectate/<code>/events")]
+#[allow(clippy::needless_pass_by_value)]
+fn mirrored_events<'a>(
+    code: &str,
+    state: &'a State<GlobalState>,
+    config: &'a State<Config>,
+    slot: Result<StreamSlot, Refused>,
+    mut end: Shutdown,
+) -> Shed<EventStream![Event + 'a]> {
+    let code = code.to_uppercase();
+    Shed::new(
+        slot.as_ref().err().copied(),
+        EventStream! {
+            let _slot = match slot {
+                Ok(slot) => slot,
+                Err(refused) => {
+                    let event = make_event!(refused);
+                    yield match refused.retry_after() {
+                        Some(seconds) => event.with_retry(Duration::from_secs(seconds)),
+                        None => event,
+                    };
+                    return;
+                }
+            };
+            let mut receiver = state.mirrored.subscribe(config, &code);
+            while let Some((name, data)) = select! {
+                event = receiver.recv(|| ("resync".to_owned(), "null".to_owned())) => event,
+                () = &mut end => None,
+            } {
+                yield Event::data(data).event(name);
+            }
+        }
+        .heartbeat(Duration::from_secs(5)),
+    )
+}
+
+/// `/history/game/<id>` of the origin
+#[get("/history/game/<id>")]
+async fn mirrored_replay(
+    id: u64,
+    state: &State<GlobalState>,
+    config: &State<Config>,
+) -> Option<(ContentType, String)> {
+    if let Some(replay) = state.mirrored.replays.lock().unwrap().get(&id) {
+        return Some((ContentType::JSON, replay.clone()));
+    }
+
+    let response = origin(config, &format!("/history/game/{id}"))
+        .send()
+        .await
+        .and_then(Response::error_for_status)
+        .ok()?;
+    let replay = response.text().await.ok()?;
+    let mut replays = state.mirrored.replays.lock().unwrap();
+    replays.insert(id, replay.clone());
+    if replays.len() > MAX_REPLAYS {
+        replays.pop_first();
+    }
+
+    Some((ContentType::JSON, replay))
+}
+
+/// Routes of the instance owning the games
+pub fn routes() -> Vec<rocket::Route> {
+    routes![events, mirror_events]
+}
+
+/// Every route of a mirror
+pub fn mirror_routes() -> Vec<rocket::Route> {
+    routes![mirrored_events, mirrored_replay]
+}
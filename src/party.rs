@@ -0,0 +1,250 @@
+use crate::{
+    admin::Kind,
+    channel::{channel, Sender},
+    chat::ChatMessage,
+    common::{make_event, GlobalState, Protected, Registry},
+    janitor::JanitorHandle,
+    limits::{Refused, Shed, StreamSlot},
+};
+use rocket::{
+    get,
+    http::{CookieJar, Status},
+    response::{
+        status::BadRequest,
+        stream::{Event, EventStream},
+    },
+    routes,
+    serde::Serialize,
+    tokio::{self, select},
+    Shutdown, State,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+type PlayerId = u32;
+
+/// How many chat messages are replayed to people joining the party
+const HISTORY_SIZE: usize = 50;
+
+/// A group of people playing together, which outlives the lobbies and games they go through
+#[derive(Debug, Default)]
+pub struct Party {
+    members: HashMap<PlayerId, Sender<Message>>,
+    history: VecDeque<ChatMessage>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(untagged)]
+enum Message {
+    Error {
+        reason: &'static str,
+    },
+    /// Some messages were dropped because the client is too slow
+    Resync,
+    Initialize {
+        history: Vec<ChatMessage>,
+    },
+    Chat(ChatMessage),
+}
+
+impl Message {
+    const fn name(&self) -> &'static str {
+        match self {
+            Self::Error { .. } => "error",
+            Self::Resync => "resync",
+            Self::Initialize { .. } => "init",
+            Self::Chat { .. } => "chat",
+        }
+    }
+}
+
+impl Protected<Party> {
+    #[allow(clippy::significant_drop_in_scrutinee)]
+    fn broadcast(&self, msg: &Message) {
+        // forget the members whose stream stopped without cleaning up after itself
+        self.lock()
+            .members
+            .retain(|_, sender| sender.send(msg.clone()).is_ok());
+    }
+}
+
+struct ConnectionGuard {
+    parties: Weak<Registry<Party>>,
+    party: Protected<Party>,
+    name: String,
+    id: PlayerId,
+    channel: u64,
+    janitor: JanitorHandle,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let parties = Weak::clone(&self.parties);
+        let party = self.party.clone();
+        let name = self.name.clone();
+        let id = self.id;
+        let channel = self.channel;
+        self.janitor
+            .schedule(move || leave_party(parties, &party, name, id, channel));
+    }
+}
+
+fn leave_party(
+    parties: Weak<Registry<Party>>,
+    party: &Protected<Party>,
+    name: String,
+    id: PlayerId,
+    channel: u64,
+) {
+    let empty = {
+        let mut party = party.lock();
+        // the member may have reconnected in the meantime
+        if party.members.get(&id).is_some_and(|x| x.id() == channel) {
+            party.members.remove(&id);
+        }
+        party.members.is_empty()
+    };
+    if !empty {
+        return;
+    }
+
+    // give people some time to come back, they may just be changing room
+    let party = party.downgrade();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(60 * 5)).await;
+        let parties = parties.upgrade()?;
+        let party = party.upgrade()?;
+        let mut parties = parties.shard(&name);
+
+        if party.lock().members.is_empty() && parties.get(&name)?.ptr_eq(&party) {
+            parties.remove(&name);
+        }
+
+        Some(())
+    });
+}
+
+fn player_id(jar: &CookieJar<'_>) -> Option<PlayerId> {
+    jar.get_private("id")?.value().parse().ok()
+}
+
+// WARNING: EventStream is broken with rust 1.74.X, stay on 1.73.X until this is fixed
+#[get("/party/events")]
+#[must_use]
+fn events<'a>(
+    state: &'a State<GlobalState>,
+    jar: &'a CookieJar<'_>,
+    slot: Result<StreamSlot, Refused>,
+    mut end: Shutdown,
+) -> Shed<EventStream![Event + 'a]> {
+    Shed::new(
+        slot.as_ref().err().copied(),
+        EventStream! {
+            let _slot = match slot {
+                Ok(slot) => slot,
+                Err(refused) => {
+                    let event = make_event!(refused);
+                    yield match refused.retry_after() {
+                        Some(seconds) => event.with_retry(Duration::from_secs(seconds)),
+                        None => event,
+                    };
+                    return;
+                }
+            };
+            let Some(name) = jar.get_private("party").map(|x| x.value().to_owned()) else {
+                yield make_event!(Message::Error {
+                    reason: "You are not in a party"
+                });
+                return;
+            };
+
+            let Some(id) = player_id(jar) else {
+                yield make_event!(Message::Error {
+                    reason: "Invalid player id"
+                });
+                return;
+            };
+
+            let party = state
+                .parties
+                .shard(&name)
+                .entry(name.clone())
+                .or_insert_with(|| Protected::new(Party::default()))
+                .clone();
+
+            let (sender, mut receiver) = channel();
+            let _tracked = state.connections.track(Kind::Party, &name, id, sender.depth());
+            let channel = sender.id();
+            party.lock().members.insert(id, sender);
+            let history = party.lock().history.iter().cloned().collect();
+            yield make_event!(Message::Initialize { history });
+
+            let guard = ConnectionGuard {
+                parties: Arc::downgrade(&state.parties),
+                party,
+                name,
+                id,
+                channel,
+                janitor: state.janitor.handle(),
+            };
+
+            while let Some(msg) = select! {
+                msg = receiver.recv(|| Message::Resync) => msg,
+                () = &mut end => {
+                    yield make_event!(Message::Error {
+                        reason: "Server closed",
+                    });
+                    return;
+                },
+            } {
+                yield make_event!(msg);
+            }
+
+            drop(guard);
+        }
+        .heartbeat(Duration::from_secs(5)),
+    )
+}
+
+#[get("/party/chat?<message>")]
+fn chat(
+    message: String,
+    state: &State<GlobalState>,
+    jar: &CookieJar<'_>,
+) -> Result<Status, BadRequest<&'static str>> {
+    let (Some(party), Some(id), Some(name)) = (
+        jar.get_private("party"),
+        player_id(jar),
+        jar.get_private("name"),
+    ) else {
+        return Err(BadRequest("You are not in a party"));
+    };
+
+    let msg = ChatMessage::new(id, name.value(), &message).map_err(BadRequest)?;
+
+    let Some(party) = state.parties.get(party.value()) else {
+        return Err(BadRequest("You are not in a party"));
+    };
+    if !party.lock().members.contains_key(&id) {
+        return Err(BadRequest("You are not connected to your party"));
+    }
+
+    {
+        let mut party = party.lock();
+        if party.history.len() >= HISTORY_SIZE {
+            party.history.pop_front();
+        }
+        party.history.push_back(msg.clone());
+    }
+    party.broadcast(&Message::Chat(msg));
+
+    Ok(Status::Ok)
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![events, chat]
+}
@@ -0,0 +1,88 @@
+//! Special characters dealt on top of the teams. Everything a role does goes through
+//! [`Role::on_cut`], called by [`Game::cut`](crate::gameplay::Game::cut) for the players involved
+//! in the cut, so a new role only has to be added here.
+
+use crate::gameplay::{Cable, Team};
+use rocket::serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Sherlock, the first bomb cut in their hand counts as a safe cable
+    Bodyguard,
+    /// Sherlock, learns the team of every player they cut
+    Detective,
+    /// Moriarty, learns the team of every player cutting them
+    Spy,
+}
+
+impl Role {
+    pub const ALL: [Self; 3] = [Self::Bodyguard, Self::Detective, Self::Spy];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Bodyguard => "bodyguard",
+            Self::Detective => "detective",
+            Self::Spy => "spy",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|x| x.name() == name)
+    }
+
+    /// The team of the players who may get the role
+    pub const fn team(self) -> Team {
+        match self {
+            Self::Bodyguard | Self::Detective => Team::Sherlock,
+            Self::Spy => Team::Moriarty,
+        }
+    }
+
+    /// What the role of `holder` does to a cut they're part of. `spent` is set once the role
+    /// replaced a cable, it can only do it once.
+    pub fn on_cut<ID: Copy + PartialEq>(
+        self,
+        holder: ID,
+        cut: &Cut<ID>,
+        spent: bool,
+    ) -> Option<Effect<ID>> {
+        match self {
+            Self::Bodyguard => (holder == cut.cutted && cut.cable == Cable::Bomb && !spent)
+                .then_some(Effect::Replace(Cable::Safe)),
+            Self::Detective => {
+                (holder == cut.cutting && holder != cut.cutted).then_some(Effect::RevealTeam {
+                    to: holder,
+                    player: cut.cutted,
+                    team: cut.cutted_team,
+                })
+            }
+            Self::Spy => {
+                (holder == cut.cutted && holder != cut.cutting).then_some(Effect::RevealTeam {
+                    to: holder,
+                    player: cut.cutting,
+                    team: cut.cutting_team,
+                })
+            }
+        }
+    }
+}
+
+/// A cut, as the roles of the players involved see it
+#[derive(Debug, Clone, Copy)]
+pub struct Cut<ID> {
+    pub cutting: ID,
+    pub cutting_team: Team,
+    pub cutted: ID,
+    pub cutted_team: Team,
+    pub cable: Cable,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect<ID> {
+    /// The cut cable counts as this one instead
+    Replace(Cable),
+    /// `to` privately learns the team of `player`
+    RevealTeam { to: ID, player: ID, team: Team },
+}
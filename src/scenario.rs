@@ -0,0 +1,277 @@
+use crate::{
+    account,
+    admin::Admin,
+    bot,
+    common::{GlobalState, Protected},
+    config::Config,
+    game::{self, Player},
+    gameplay::{self, Cable, Game, Room, Scenario},
+    limits::Throttled,
+    stats,
+};
+use rand::{
+    distributions::{Alphanumeric, DistString},
+    random, thread_rng,
+};
+use rocket::{
+    get,
+    http::CookieJar,
+    response::{status::BadRequest, Redirect},
+    routes,
+    serde::{
+        json::{self, Json},
+        Serialize,
+    },
+    uri, State,
+};
+use std::{collections::BTreeMap, fs, path::Path, sync::Arc};
+
+fn load(path: &Path) -> Result<Scenario, &'static str> {
+    let content = fs::read_to_string(path).map_err(|_| "Scenario not found")?;
+    let scenario: Scenario = json::from_str(&content).map_err(|_| "Invalid scenario file")?;
+
+    if scenario.seats.len() < 2 {
+        return Err("A scenario needs at least two seats");
+    }
+    if scenario.wire_cutters >= scenario.seats.len() {
+        return Err("The wire cutters are given to a seat that doesn't exist");
+    }
+    let cables = || scenario.seats.iter().flat_map(|seat| &seat.cables);
+    if !cables().any(|x| *x == Cable::Bomb) {
+        return Err("A scenario needs a bomb");
+    }
+    if !cables().any(|x| *x == Cable::Defusing) {
+        return Err("A scenario needs a defusing cable");
+    }
+
+    Ok(scenario)
+}
+
+/// The scenarios of the scenario directory, read at startup
+#[derive(Debug, Default)]
+pub struct Scenarios(BTreeMap<String, Scenario>);
+
+impl Scenarios {
+    pub fn load(dir: &Path) -> Self {
+        let mut scenarios = BTreeMap::new();
+        for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+            let path = entry.path();
+            if path.extension().is_none_or(|x| x != "json") {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|x| x.to_str()) else {
+                continue;
+            };
+            match load(&path) {
+                Ok(scenario) => {
+                    scenarios.insert(name.to_owned(), scenario);
+                }
+                Err(e) => rocket::warn!("Ignoring scenario {}: {e}", path.display()),
+            }
+        }
+
+        Self(scenarios)
+    }
+
+    fn get(&self, name: &str) -> Result<Scenario, &'static str> {
+        self.0.get(name).cloned().ok_or("Scenario not found")
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct ScenarioInfo {
+    name: String,
+    description: String,
+    seats: usize,
+}
+
+#[get("/scenarios")]
+fn list(state: &State<GlobalState>) -> Json<Vec<ScenarioInfo>> {
+    Json(
+        state
+            .scenarios
+            .0
+            .iter()
+            .map(|(name, scenario)| ScenarioInfo {
+                name: name.clone(),
+                description: scenario.description.clone(),
+                seats: scenario.seats.len(),
+            })
+            .collect(),
+    )
+}
+
+/// A live game playing a scenario
+struct Instance {
+    code: String,
+    game: Protected<Game<Player>>,
+    /// Id of each seat
+    ids: Vec<<Player as gameplay::Player>::ID>,
+}
+
+fn instantiate(
+    scenario: &Scenario,
+    state: &GlobalState,
+    config: &Config,
+) -> Result<Instance, &'static str> {
+    let mut ids: Vec<<Player as gameplay::Player>::ID> = Vec::new();
+    while ids.len() < scenario.seats.len() {
+        let id = random();
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+
+    let (code, game) = loop {
+        let code = Alphanumeric
+            .sample_string(&mut thread_rng(), 6)
+            .to_uppercase();
+        let lobbys = state.lobbys.shard(&code);
+        let mut games = state.games.shard(&code);
+        if lobbys.contains_key(&code) || games.contains_key(&code) || !state.ring.owns(&code) {
+            continue;
+        }
+
+        let game = Game::from_scenario(code.clone(), scenario, &ids).map_err(|e| match e {
+            gameplay::errors::Scenario::UnknownSeat => {
+                "An action of the scenario refers to a seat that doesn't exist"
+            }
+            gameplay::errors::Scenario::Cut(_) => "A scripted cut of the scenario is not allowed",
+            gameplay::errors::Scenario::GameOver => {
+                "The scripted actions of the scenario end the game"
+            }
+        })?;
+        // the first seat is the only one sure to be taken
+        if game.wire_cutters != ids[0] {
+            return Err(
+                "The scripted actions of the scenario must give the wire cutters to the first seat",
+            );
+        }
+        let game = Protected::new(game);
+        games.insert(code.clone(), game.clone());
+        break (code, game);
+    };
+    game::schedule_nudge(&game, &state.games, config);
+    game::schedule_log(&game, config);
+    game::schedule_time_limit(&game, state, config);
+    game::schedule_turn_timer(&game, state, config);
+    game::schedule_abandon_check(&game, &state.games);
+
+    Ok(Instance { code, game, ids })
+}
+
+/// Start a game from a scenario, the caller takes the first seat and bots played by the server take
+/// the remaining ones
+#[get("/scenario/start?<scenario>&<name>")]
+fn start(
+    scenario: &str,
+    name: String,
+    state: &State<GlobalState>,
+    config: &State<Config>,
+    jar: &CookieJar<'_>,
+    _throttled: Throttled,
+) -> Result<Redirect, BadRequest<&'static str>> {
+    if let Err(reserved) = account::check_name(&name, config, jar) {
+        return Ok(reserved.redirect());
+    }
+    let mut scenario = state.scenarios.get(scenario).map_err(BadRequest)?;
+    let Some(name) = config.name_collision.resolve(name, |name| {
+        scenario.seats[1..].iter().any(|x| x.name == name)
+    }) else {
+        return Err(BadRequest(
+            "One of the seats of the scenario uses this name",
+        ));
+    };
+    scenario.seats[0].name.clone_from(&name);
+
+    let Instance { code, game, ids } = instantiate(&scenario, state, config).map_err(BadRequest)?;
+    {
+        let mut game = game.lock();
+        let seat = game.get_player_mut(ids[0]).unwrap();
+        seat.record_as(stats::identify(jar));
+        if jar.get_private("no_stats").is_some() {
+            seat.hide_from_stats();
+        }
+    }
+    for &id in &ids[1..] {
+        bot::play(
+            game.clone(),
+            id,
+            Arc::clone(&state.games),
+            Arc::clone(&state.stats),
+            config.inner().clone(),
+        );
+    }
+
+    jar.add_private(("party", code.clone()));
+    jar.add_private(("lobby", code));
+    jar.add_private(("id", ids[0].to_string()));
+    jar.remove_private("seat");
+    jar.add_private(("name", name));
+
+    Ok(Redirect::to("/game.html"))
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct SeatLink {
+    player: <Player as gameplay::Player>::ID,
+    name: String,
+    /// Opening it in a browser takes the seat
+    join: String,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Started {
+    lobby: String,
+    seats: Vec<SeatLink>,
+}
+
+/// Start a game from a scenario with every seat free, for testers to take them
+#[get("/admin/scenarios/<scenario>/start")]
+#[allow(clippy::needless_pass_by_value)]
+fn admin_start(
+    _admin: Admin,
+    scenario: &str,
+    state: &State<GlobalState>,
+    config: &State<Config>,
+) -> Result<Json<Started>, BadRequest<&'static str>> {
+    let scenario = state.scenarios.get(scenario).map_err(BadRequest)?;
+    let Instance { code, game, ids } = instantiate(&scenario, state, config).map_err(BadRequest)?;
+
+    let mut game = game.lock();
+    let seats = ids
+        .into_iter()
+        .zip(scenario.seats)
+        .map(|(player, seat)| {
+            let token = game::invite(&mut game, player).unwrap();
+            SeatLink {
+                player,
+                name: seat.name,
+                join: uri!(take(&code, player, token)).to_string(),
+            }
+        })
+        .collect();
+
+    Ok(Json(Started { lobby: code, seats }))
+}
+
+#[get("/scenario/take?<lobby>&<player>&<token>")]
+fn take(
+    lobby: String,
+    player: <Player as gameplay::Player>::ID,
+    token: String,
+    jar: &CookieJar<'_>,
+) -> Redirect {
+    jar.add_private(("substitute_lobby", lobby));
+    jar.add_private(("substitute_seat", player.to_string()));
+    jar.add_private(("substitute_token", token));
+
+    Redirect::to("/game/substitute/claim")
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![list, start, admin_start, take]
+}
@@ -0,0 +1,37 @@
+use reqwest::Response;
+use rocket::tokio::{
+    self,
+    sync::mpsc::{unbounded_channel, UnboundedReceiver},
+};
+
+/// Read the events of a stream as they arrive, yielding their name and data
+pub fn read(mut response: Response) -> UnboundedReceiver<(String, String)> {
+    let (sender, receiver) = unbounded_channel();
+    tokio::spawn(async move {
+        let mut buffer = String::new();
+        while let Ok(Some(chunk)) = response.chunk().await {
+            buffer.push_str(&String::from_utf8_lossy(&chunk));
+            while let Some(end) = buffer.find("\n\n") {
+                let block: String = buffer.drain(..end + 2).collect();
+                let mut name = None;
+                let mut data = String::new();
+                for line in block.lines() {
+                    if let Some(x) = line.strip_prefix("event:") {
+                        name = Some(x.trim().to_owned());
+                    } else if let Some(x) = line.strip_prefix("data:") {
+                        data.push_str(x);
+                    }
+                }
+                // heartbeats have no name
+                let Some(name) = name else {
+                    continue;
+                };
+                if sender.send((name, data)).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    receiver
+}
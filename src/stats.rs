@@ -0,0 +1,601 @@
+use crate::{
+    common::GlobalState,
+    config::Config,
+    gameplay::{HistoryEntry, Rules, Team},
+};
+use rand::{
+    distributions::{Alphanumeric, DistString},
+    thread_rng,
+};
+use rocket::{
+    get,
+    http::{Cookie, CookieJar},
+    routes,
+    serde::{
+        json::{self, Json},
+        Deserialize, Serialize,
+    },
+    State,
+};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fs,
+    path::PathBuf,
+    sync::mpsc::{self, Sender},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// How many days of daily challenge results are kept
+const DAILY_HISTORY: u64 = 30;
+/// How many finished games are kept in the match history
+const MAX_GAMES: usize = 1000;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(default)]
+pub struct PlayerStats {
+    /// Last name the player played under, empty if it is the key
+    pub name: String,
+    pub games: u32,
+    pub wins: u32,
+    /// Number of consecutive games played as Moriarty
+    pub moriarty_streak: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct DailyResult {
+    /// `None` for the players who opted out of the stats
+    pub name: Option<String>,
+    pub team: Team,
+    pub won: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(default)]
+struct Data {
+    /// Stats of the current season, by [`key`]
+    players: HashMap<String, PlayerStats>,
+    daily: HashMap<u64, Vec<DailyResult>>,
+    totals: Totals,
+    /// Season `players` is for
+    season: u64,
+    /// Stats of the previous seasons
+    archive: BTreeMap<u64, HashMap<String, PlayerStats>>,
+    /// Most recent last
+    games: VecDeque<GameRecord>,
+    next_game: u64,
+    /// Picked for the pseudonyms when none is configured, they would be easy to reverse without
+    salt: String,
+    /// Accounts marked ready as soon as they join a lobby, by [`key`]
+    auto_ready: HashSet<String>,
+}
+
+/// A finished game, as shown in the match history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct GameRecord {
+    pub id: u64,
+    /// Code of the lobby the game was played in
+    pub code: String,
+    /// Seconds since the UNIX epoch
+    pub ended_at: u64,
+    pub winner: Option<Team>,
+    pub players: Vec<RecordedPlayer>,
+    #[serde(default)]
+    pub history: Vec<HistoryEntry<u32>>,
+    /// Including the score of the series once this game is counted
+    #[serde(default)]
+    pub rules: Rules,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct RecordedPlayer {
+    /// Used by the entries of the history
+    pub id: u32,
+    /// `None` for the players who opted out of the stats
+    pub name: Option<String>,
+    pub team: Team,
+    /// Left before the end, which counts as a loss
+    #[serde(default)]
+    pub forfeited: bool,
+}
+
+/// Results of all the games, including the ones of players who opted out
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(default)]
+pub struct Totals {
+    pub games: u32,
+    pub sherlock_wins: u32,
+    pub moriarty_wins: u32,
+    pub draws: u32,
+}
+
+/// Results of finished games, keyed by account or client, optionally saved to disk
+#[derive(Debug, Default)]
+pub struct Stats {
+    /// Saves the serialized data, away from the lock of the stats
+    writer: Option<Sender<String>>,
+    salt: String,
+    season_days: Option<u64>,
+    data: Data,
+}
+
+/// Line of a leaderboard
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Standing {
+    pub name: String,
+    pub games: u32,
+    pub wins: u32,
+}
+
+/// Who the results of the client are recorded for, the account they are logged in to or else their
+/// browser
+pub fn key(jar: &CookieJar<'_>) -> Option<String> {
+    if let Some(account) = jar.get_private("account") {
+        return Some(format!("account:{}", account.value()));
+    }
+    jar.get_private("client")
+        .map(|x| format!("client:{}", x.value()))
+}
+
+/// Give the client an id that outlives its games if it has none, returns its [`key`]
+pub fn identify(jar: &CookieJar<'_>) -> String {
+    if let Some(key) = key(jar) {
+        return key;
+    }
+    let client = Alphanumeric.sample_string(&mut thread_rng(), 16);
+    let mut cookie = Cookie::new("client", client.clone());
+    cookie.make_permanent();
+    jar.add_private(cookie);
+    format!("client:{client}")
+}
+
+/// Write every content sent to `path`, skipping the ones already replaced by a newer one
+fn spawn_writer(path: PathBuf) -> Sender<String> {
+    let (sender, receiver) = mpsc::channel::<String>();
+    thread::spawn(move || {
+        while let Ok(mut content) = receiver.recv() {
+            while let Ok(newer) = receiver.try_recv() {
+                content = newer;
+            }
+            if let Err(e) = fs::write(&path, content) {
+                rocket::error!("could not save stats to {}: {e}", path.display());
+            }
+        }
+    });
+    sender
+}
+
+/// Number of days since the UNIX epoch
+pub fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs() / (60 * 60 * 24))
+}
+
+impl Stats {
+    pub fn load(config: &Config) -> Self {
+        let path = config.stats_file.clone();
+        let mut data: Data = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|content| json::from_str(&content).ok())
+            .unwrap_or_default();
+        if data.salt.is_empty() {
+            data.salt = Alphanumeric.sample_string(&mut thread_rng(), 32);
+        }
+        let salt = if config.pseudonym_salt.is_empty() {
+            data.salt.clone()
+        } else {
+            config.pseudonym_salt.clone()
+        };
+
+        let mut stats = Self {
+            writer: path.map(spawn_writer),
+            salt,
+            season_days: config.season_days,
+            data,
+        };
+        stats.roll_season();
+        stats
+    }
+
+    /// The seasons are numbered from the UNIX epoch, there is only season 0 if they are disabled
+    pub fn season(&self) -> u64 {
+        self.season_days.map_or(0, |days| today() / days.max(1))
+    }
+
+    /// Archive the stats of the previous season if a new one started
+    fn roll_season(&mut self) -> bool {
+        let season = self.season();
+        if self.data.season == season {
+            return false;
+        }
+
+        // the streaks are used to balance teams, they have nothing to do with the leaderboard
+        let streaks = self
+            .data
+            .players
+            .iter()
+            .filter(|(_, stats)| stats.moriarty_streak > 0)
+            .map(|(key, stats)| {
+                (
+                    key.clone(),
+                    PlayerStats {
+                        name: stats.name.clone(),
+                        moriarty_streak: stats.moriarty_streak,
+                        ..PlayerStats::default()
+                    },
+                )
+            })
+            .collect();
+        let previous = std::mem::replace(&mut self.data.players, streaks);
+        // e.g. the season 0 of new stats
+        if previous.values().any(|stats| stats.games > 0) {
+            self.data.archive.insert(self.data.season, previous);
+        }
+        self.data.season = season;
+        true
+    }
+
+    /// Best players of `season` first, `None` if there is nothing about this season
+    pub fn leaderboard(&mut self, season: u64) -> Option<Vec<Standing>> {
+        if self.roll_season() {
+            self.save();
+        }
+        let players = if season == self.data.season {
+            &self.data.players
+        } else {
+            self.data.archive.get(&season)?
+        };
+
+        let mut standings: Vec<_> = players
+            .iter()
+            .filter(|(_, stats)| stats.games > 0)
+            .map(|(key, stats)| Standing {
+                name: if stats.name.is_empty() {
+                    key.clone()
+                } else {
+                    stats.name.clone()
+                },
+                games: stats.games,
+                wins: stats.wins,
+            })
+            .collect();
+        standings.sort_by(|a, b| {
+            b.wins
+                .cmp(&a.wins)
+                .then_with(|| a.games.cmp(&b.games))
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        Some(standings)
+    }
+
+    /// Seasons that are over
+    pub fn archived_seasons(&self) -> Vec<u64> {
+        self.data.archive.keys().copied().collect()
+    }
+
+    fn save(&self) {
+        let Some(writer) = &self.writer else {
+            return;
+        };
+
+        match json::to_string(&self.data) {
+            // the writer only stops with the server
+            Ok(content) => _ = writer.send(content),
+            Err(e) => rocket::error!("could not serialize the stats: {e}"),
+        }
+    }
+
+    /// Whether `key` asked to be marked ready as soon as they join a lobby
+    pub fn auto_ready(&self, key: &str) -> bool {
+        self.data.auto_ready.contains(key)
+    }
+
+    pub fn set_auto_ready(&mut self, key: &str, state: bool) {
+        let changed = if state {
+            self.data.auto_ready.insert(key.to_owned())
+        } else {
+            self.data.auto_ready.remove(key)
+        };
+        if changed {
+            self.save();
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&PlayerStats> {
+        self.data.players.get(key)
+    }
+
+    /// Name recorded in place of the one of `key` in anonymous games, always the same for a given key
+    pub fn pseudonym(&self, key: &str) -> String {
+        // FNV-1a, unlike the standard hasher it is guaranteed to stay the same between versions
+        let hash = self
+            .salt
+            .bytes()
+            .chain([0])
+            .chain(key.bytes())
+            .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+            });
+        format!("anonymous-{:08x}", hash >> 32)
+    }
+
+    /// Finished games, most recent last
+    pub const fn games(&self) -> &VecDeque<GameRecord> {
+        &self.data.games
+    }
+
+    pub const fn totals(&self) -> &Totals {
+        &self.data.totals
+    }
+
+    pub fn daily(&self, day: u64) -> &[DailyResult] {
+        self.data.daily.get(&day).map_or(&[], Vec::as_slice)
+    }
+
+    /// Players are given with their key and name, `None` for the ones who opted out, their results
+    /// are only counted in the totals
+    pub fn record<'a>(
+        &mut self,
+        code: &str,
+        players: impl IntoIterator<Item = (u32, Option<(&'a str, &'a str)>, Team, bool)>,
+        winner: Option<Team>,
+        rules: &Rules,
+        history: &[HistoryEntry<u32>],
+    ) {
+        self.roll_season();
+        let totals = &mut self.data.totals;
+        totals.games += 1;
+        match winner {
+            Some(Team::Sherlock) => totals.sherlock_wins += 1,
+            Some(Team::Moriarty) => totals.moriarty_wins += 1,
+            None => totals.draws += 1,
+        }
+
+        let mut recorded = Vec::new();
+        for (id, player, team, forfeited) in players {
+            let won = winner == Some(team) && !forfeited;
+            let name = player.map(|(key, name)| {
+                // the streaks balance the teams of the next games, whatever the name of this one
+                let streak = self.data.players.entry(key.to_owned()).or_default();
+                match team {
+                    Team::Moriarty => streak.moriarty_streak += 1,
+                    Team::Sherlock => streak.moriarty_streak = 0,
+                }
+
+                let (key, name) = if rules.anonymous {
+                    let pseudonym = self.pseudonym(key);
+                    (pseudonym.clone(), pseudonym)
+                } else {
+                    (key.to_owned(), name.to_owned())
+                };
+                let stats = self.data.players.entry(key).or_default();
+                stats.name.clone_from(&name);
+                stats.games += 1;
+                if won {
+                    stats.wins += 1;
+                }
+                name
+            });
+
+            if let Some(day) = rules.daily {
+                self.data.daily.entry(day).or_default().push(DailyResult {
+                    name: name.clone(),
+                    team,
+                    won,
+                });
+            }
+            recorded.push(RecordedPlayer {
+                id,
+                name,
+                team,
+                forfeited,
+            });
+        }
+
+        self.data.games.push_back(GameRecord {
+            id: self.data.next_game,
+            code: code.to_owned(),
+            ended_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |x| x.as_secs()),
+            winner,
+            players: recorded,
+            history: history.to_vec(),
+            rules: rules.clone(),
+        });
+        self.data.next_game += 1;
+        if self.data.games.len() > MAX_GAMES {
+            self.data.games.pop_front();
+        }
+
+        let oldest = today().saturating_sub(DAILY_HISTORY);
+        self.data.daily.retain(|day, _| *day >= oldest);
+
+        self.save();
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Daily {
+    day: u64,
+    rules: Rules,
+    leaderboard: Vec<DailyResult>,
+}
+
+#[get("/daily")]
+fn daily(state: &State<GlobalState>, config: &State<Config>) -> Json<Daily> {
+    let day = today();
+    let mut leaderboard = state.stats.lock().unwrap().daily(day).to_vec();
+    leaderboard.sort_by(|a, b| b.won.cmp(&a.won).then_with(|| a.name.cmp(&b.name)));
+
+    Json(Daily {
+        day,
+        rules: Rules::daily(day, &config.daily_secret),
+        leaderboard,
+    })
+}
+
+#[get("/stats/totals")]
+fn totals(state: &State<GlobalState>) -> Json<Totals> {
+    Json(state.stats.lock().unwrap().totals().clone())
+}
+
+/// Stop or resume recording the games of the player under their name
+#[get("/stats/opt_out?<state>")]
+fn opt_out(state: bool, jar: &CookieJar<'_>) {
+    if state {
+        jar.add_private(("no_stats", "true"));
+    } else {
+        jar.remove_private("no_stats");
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Season {
+    season: u64,
+    leaderboard: Vec<Standing>,
+}
+
+#[get("/stats/leaderboard")]
+fn leaderboard(state: &State<GlobalState>) -> Json<Season> {
+    let mut stats = state.stats.lock().unwrap();
+    let season = stats.season();
+    Json(Season {
+        season,
+        leaderboard: stats.leaderboard(season).unwrap_or_default(),
+    })
+}
+
+#[get("/stats/seasons")]
+fn seasons(state: &State<GlobalState>) -> Json<Vec<u64>> {
+    Json(state.stats.lock().unwrap().archived_seasons())
+}
+
+#[get("/stats/seasons/<season>")]
+fn season(season: u64, state: &State<GlobalState>) -> Option<Json<Season>> {
+    let leaderboard = state.stats.lock().unwrap().leaderboard(season)?;
+    Some(Json(Season {
+        season,
+        leaderboard,
+    }))
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![daily, totals, opt_out, leaderboard, seasons, season]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forfeit_counts_as_a_loss() {
+        let mut stats = Stats::load(&Config::default());
+        let players = [
+            (1, Some(("client:a", "a")), Team::Sherlock, false),
+            (2, Some(("client:b", "b")), Team::Sherlock, true),
+            (3, Some(("client:c", "c")), Team::Moriarty, false),
+        ];
+        stats.record(
+            "GAME",
+            players,
+            Some(Team::Sherlock),
+            &Rules::default(),
+            &[],
+        );
+
+        assert_eq!(stats.get("client:a").unwrap().wins, 1);
+        let forfeiter = stats.get("client:b").unwrap();
+        assert_eq!((forfeiter.games, forfeiter.wins), (1, 0));
+        let recorded = &stats.games()[0].players;
+        assert!(!recorded[0].forfeited && recorded[1].forfeited);
+    }
+
+    #[test]
+    fn finished_seasons_are_archived() {
+        let config = Config {
+            season_days: Some(1),
+            ..Config::default()
+        };
+        let mut stats = Stats::load(&config);
+        let players = [
+            (1, Some(("client:a", "a")), Team::Moriarty, false),
+            (2, Some(("client:b", "b")), Team::Sherlock, false),
+        ];
+        stats.record(
+            "GAME",
+            players,
+            Some(Team::Moriarty),
+            &Rules::default(),
+            &[],
+        );
+        let names = |standings: Vec<Standing>| -> Vec<_> {
+            standings.into_iter().map(|x| x.name).collect()
+        };
+        assert_eq!(
+            names(stats.leaderboard(stats.season()).unwrap()),
+            ["a", "b"]
+        );
+
+        // as if the game was played yesterday
+        let season = stats.season();
+        stats.data.season = season - 1;
+        assert_eq!(stats.leaderboard(season).unwrap().len(), 0);
+        assert_eq!(stats.archived_seasons(), [season - 1]);
+        assert_eq!(names(stats.leaderboard(season - 1).unwrap()), ["a", "b"]);
+        assert_eq!(stats.get("client:a").unwrap().moriarty_streak, 1);
+        assert!(stats.leaderboard(season + 1).is_none());
+    }
+
+    #[test]
+    fn anonymous_games_keep_the_streaks() {
+        let mut stats = Stats::load(&Config::default());
+        let rules = Rules {
+            anonymous: true,
+            ..Rules::default()
+        };
+        let players = [(1, Some(("account:bob", "bob")), Team::Moriarty, false)];
+        stats.record("GAME", players, Some(Team::Moriarty), &rules, &[]);
+
+        let pseudonym = stats.pseudonym("account:bob");
+        let anonymous = stats.get(&pseudonym).unwrap();
+        assert_eq!((anonymous.games, anonymous.wins), (1, 1));
+        assert_eq!(anonymous.name, pseudonym);
+        let real = stats.get("account:bob").unwrap();
+        assert_eq!((real.games, real.moriarty_streak), (0, 1));
+        assert!(real.name.is_empty());
+        assert!(stats
+            .leaderboard(0)
+            .unwrap()
+            .iter()
+            .all(|x| x.name == pseudonym));
+    }
+
+    #[test]
+    fn pseudonyms_are_salted_without_a_configured_salt() {
+        let a = Stats::load(&Config::default());
+        let b = Stats::load(&Config::default());
+        assert_eq!(a.pseudonym("account:bob"), a.pseudonym("account:bob"));
+        assert_ne!(a.pseudonym("account:bob"), b.pseudonym("account:bob"));
+
+        let config = Config {
+            pseudonym_salt: "salt".to_owned(),
+            ..Config::default()
+        };
+        assert_eq!(
+            Stats::load(&config).pseudonym("account:bob"),
+            Stats::load(&config).pseudonym("account:bob")
+        );
+    }
+}
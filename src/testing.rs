@@ -0,0 +1,262 @@
+//! A server for the tests, with clients keeping their own cookies
+use crate::{common::GlobalState, config::Config};
+use rocket::{
+    http::{Cookie, Status},
+    local::asynchronous::{Client, LocalRequest, LocalResponse},
+    serde::json::{self, Value},
+    tokio::{self, io::AsyncReadExt},
+};
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+
+/// How long a test waits for something that should happen right away
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Server(Client);
+
+impl Server {
+    pub async fn new(config: Config) -> Self {
+        let rocket = crate::server(rocket::build(), config);
+        let client = Client::untracked(rocket).await.unwrap();
+        // the local client doesn't lift off
+        crate::janitor::start(client.rocket().state().unwrap());
+        Self(client)
+    }
+
+    pub fn state(&self) -> &GlobalState {
+        self.0.rocket().state().unwrap()
+    }
+
+    /// A new browser, each one with its own address
+    pub fn session(&self, number: u8) -> Session<'_> {
+        Session {
+            client: &self.0,
+            cookies: Vec::new(),
+            remote: SocketAddr::from((Ipv4Addr::new(10, 0, 0, number), 1000)),
+        }
+    }
+
+    /// Players named `p1`, `p2`... in the lobby `code`, the first one is its host
+    pub async fn lobby(&self, code: &str, players: u8) -> Vec<Session<'_>> {
+        let mut sessions = Vec::new();
+        for i in 1..=players {
+            let mut session = self.session(i);
+            let mut uri = if i == 1 {
+                format!("/lobby/create?id={code}&name=p{i}")
+            } else {
+                format!("/lobby/join?lobby={code}&name=p{i}")
+            };
+            // the pages are served by the client, only the routes are followed
+            while uri.starts_with("/lobby/") {
+                uri = session.redirect(&uri).await;
+            }
+            assert_eq!(uri, "/lobby.html");
+            sessions.push(session);
+        }
+        sessions
+    }
+
+    /// A game of `players` started by `p1` with the lobby `settings`, with the event stream of each
+    /// player once the game started
+    pub async fn game(&self, code: &str, players: u8, settings: &str) -> Game<'_> {
+        let mut sessions = self.lobby(code, players).await;
+        let mut lobby_events = Vec::new();
+        for session in &mut sessions {
+            let mut events = session.events("/lobby/events").await;
+            events.until("init").await;
+            lobby_events.push(events);
+        }
+        if !settings.is_empty() {
+            let (status, body) = sessions[0]
+                .get(&format!("/lobby/settings?{settings}"))
+                .await;
+            assert_eq!(status, Status::Ok, "{body}");
+        }
+        for session in &mut sessions {
+            session.get("/lobby/ready?state=true").await;
+        }
+        let (status, _) = sessions[0].get("/lobby/start").await;
+        assert_eq!(status, Status::Ok);
+        drop(lobby_events);
+
+        let mut events = Vec::new();
+        for session in &mut sessions {
+            let mut stream = session.events("/game/events").await;
+            let player = stream.until("init").await["player"].clone();
+            // a stream dropped before it announced its own connection leaves the seat taken
+            while stream.until("connect").await["player"] != player {}
+            events.push(stream);
+        }
+        // and then the ones who connected after them
+        for (i, stream) in events.iter_mut().enumerate() {
+            for _ in i + 1..sessions.len() {
+                stream.until("connect").await;
+            }
+        }
+        Game { sessions, events }
+    }
+}
+
+pub struct Game<'c> {
+    pub sessions: Vec<Session<'c>>,
+    pub events: Vec<Events<'c>>,
+}
+
+impl Game<'_> {
+    /// Id of the seat of `p<number>`
+    pub async fn id(&mut self, number: usize) -> u64 {
+        id(&self.sessions[0].json("/game/state").await, number)
+    }
+}
+
+/// Id of `p<number>` in the state of a room
+pub fn id(state: &Value, number: usize) -> u64 {
+    state["players"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|x| x["name"] == format!("p{number}"))
+        .unwrap()["id"]
+        .as_u64()
+        .unwrap()
+}
+
+pub struct Session<'c> {
+    client: &'c Client,
+    cookies: Vec<Cookie<'static>>,
+    remote: SocketAddr,
+}
+
+impl<'c> Session<'c> {
+    fn request(&self, request: LocalRequest<'c>) -> LocalRequest<'c> {
+        request
+            .remote(self.remote)
+            .cookies(self.cookies.iter().cloned())
+    }
+
+    fn keep_cookies(&mut self, response: &LocalResponse<'_>) {
+        for header in response.headers().get("Set-Cookie") {
+            let cookie = Cookie::parse_encoded(header.to_owned()).unwrap();
+            self.cookies.retain(|x| x.name() != cookie.name());
+            if !cookie.value().is_empty() {
+                self.cookies.push(cookie);
+            }
+        }
+    }
+
+    pub async fn get(&mut self, uri: &str) -> (Status, String) {
+        let response = self
+            .request(self.client.get(uri.to_owned()))
+            .dispatch()
+            .await;
+        self.keep_cookies(&response);
+        let status = response.status();
+        (status, response.into_string().await.unwrap_or_default())
+    }
+
+    pub async fn post(&mut self, uri: &str, body: &str) -> (Status, String) {
+        let request = self.client.post(uri.to_owned()).body(body);
+        let response = self.request(request).dispatch().await;
+        self.keep_cookies(&response);
+        let status = response.status();
+        (status, response.into_string().await.unwrap_or_default())
+    }
+
+    /// Where a request that must redirect leads
+    pub async fn redirect(&mut self, uri: &str) -> String {
+        let response = self
+            .request(self.client.get(uri.to_owned()))
+            .dispatch()
+            .await;
+        self.keep_cookies(&response);
+        assert_eq!(response.status(), Status::SeeOther, "{uri}");
+        response.headers().get_one("Location").unwrap().to_owned()
+    }
+
+    /// The JSON answer of a request that must succeed
+    pub async fn json(&mut self, uri: &str) -> Value {
+        let (status, body) = self.get(uri).await;
+        assert_eq!(status, Status::Ok, "{uri}: {body}");
+        json::from_str(&body).unwrap()
+    }
+
+    /// Whatever was answered to a request that must fail
+    pub async fn refused(&mut self, uri: &str) -> String {
+        let (status, body) = self.get(uri).await;
+        assert!(status.class().is_client_error(), "{uri}: {status} {body}");
+        body
+    }
+
+    /// Open an event stream, it stays connected until it is dropped
+    pub async fn events(&mut self, uri: &str) -> Events<'c> {
+        let response = self
+            .request(self.client.get(uri.to_owned()))
+            .dispatch()
+            .await;
+        self.keep_cookies(&response);
+        Events {
+            response,
+            buffer: String::new(),
+        }
+    }
+
+    pub fn has_cookie(&self, name: &str) -> bool {
+        self.cookies.iter().any(|x| x.name() == name)
+    }
+}
+
+pub struct Events<'c> {
+    response: LocalResponse<'c>,
+    buffer: String,
+}
+
+impl Events<'_> {
+    /// The next event, with its name and data
+    pub async fn next(&mut self) -> Option<(String, Value)> {
+        // the heartbeats must not keep a test waiting forever
+        let deadline = tokio::time::Instant::now() + TIMEOUT;
+        loop {
+            if let Some(end) = self.buffer.find("\n\n") {
+                let event: String = self.buffer.drain(..end + 2).collect();
+                let mut name = String::new();
+                let mut data = String::new();
+                for line in event.lines() {
+                    if let Some(x) = line.strip_prefix("event:") {
+                        x.trim().clone_into(&mut name);
+                    } else if let Some(x) = line.strip_prefix("data:") {
+                        data.push_str(x.trim());
+                    }
+                }
+                // comments keep the connection alive
+                if name.is_empty() && data.is_empty() {
+                    continue;
+                }
+                return Some((name, json::from_str(&data).unwrap_or(Value::Null)));
+            }
+
+            let mut chunk = [0; 4096];
+            let read = tokio::time::timeout_at(deadline, self.response.read(&mut chunk))
+                .await
+                .ok()?
+                .ok()?;
+            if read == 0 {
+                return None;
+            }
+            self.buffer
+                .push_str(&String::from_utf8_lossy(&chunk[..read]));
+        }
+    }
+
+    /// Skip events until one named `name`, panics if the stream ends first
+    pub async fn until(&mut self, name: &str) -> Value {
+        loop {
+            match self.next().await {
+                Some((event, data)) if event == name => return data,
+                Some(_) => (),
+                None => panic!("no {name} event"),
+            }
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use crate::config::Config;
+use rocket::{
+    fairing::AdHoc,
+    get,
+    http::uri::{Host, Origin},
+    response::Redirect,
+    routes, tokio, State,
+};
+
+/// Port of the HTTPS listener, for the redirections
+struct HttpsPort(u16);
+
+#[get("/<_..>")]
+fn redirect(host: &Host<'_>, origin: &Origin<'_>, port: &State<HttpsPort>) -> Redirect {
+    let authority = match port.0 {
+        443 => host.domain().to_string(),
+        port => format!("{}:{port}", host.domain()),
+    };
+    Redirect::permanent(format!("https://{authority}{origin}"))
+}
+
+/// Starts a plain HTTP listener redirecting everything to the HTTPS one, when TLS is enabled
+pub fn redirector() -> AdHoc {
+    AdHoc::on_liftoff("HTTP redirection", |rocket| {
+        Box::pin(async move {
+            let Some(config) = rocket.state::<Config>() else {
+                return;
+            };
+            let Some(http_port) = config.http_redirect_port else {
+                return;
+            };
+            if !rocket.config().tls_enabled() {
+                rocket::warn!("`http_redirect_port` is set but TLS isn't configured");
+                return;
+            }
+
+            let redirector = rocket::custom(rocket::Config {
+                address: rocket.config().address,
+                port: http_port,
+                secret_key: rocket.config().secret_key.clone(),
+                ..rocket::Config::default()
+            })
+            .manage(HttpsPort(rocket.config().port))
+            .mount("/", routes![redirect]);
+
+            tokio::spawn(async move {
+                if let Err(e) = redirector.launch().await {
+                    rocket::error!("HTTP redirection listener failed: {e}");
+                }
+            });
+        })
+    })
+}
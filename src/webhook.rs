@@ -0,0 +1,40 @@
+use crate::game::GameState;
+use rocket::serde::Deserialize;
+use std::{future::Future, pin::Pin, time::Duration};
+
+type Decision = Pin<Box<dyn Future<Output = Option<(u32, Option<usize>)>> + Send>>;
+
+/// Answer of a bot webhook, a random cable of the player is cut when `index` is missing
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Choice {
+    player: u32,
+    index: Option<usize>,
+}
+
+/// Ask `url` which cable to cut by posting it the state of the game
+pub fn decider(url: String, timeout: Duration) -> impl Fn(GameState) -> Decision + Send + 'static {
+    let client = reqwest::Client::builder()
+        .timeout(timeout)
+        .build()
+        .expect("failed to build HTTP client");
+
+    move |state| {
+        let request = client.post(&url).json(&state);
+        let url = url.clone();
+        Box::pin(async move {
+            let choice = async {
+                request
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json::<Choice>()
+                    .await
+            }
+            .await
+            .inspect_err(|e| rocket::warn!("bot webhook {url} failed: {e}"))
+            .ok()?;
+            Some((choice.player, choice.index))
+        }) as Decision
+    }
+}